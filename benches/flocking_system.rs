@@ -2,7 +2,7 @@
 extern crate criterion;
 extern crate aproxiflock;
 
-use aproxiflock::system::{FlockingConfig, FlockingSystem};
+use aproxiflock::system::{Boundary, FlockingConfig, FlockingSystem};
 use criterion::Criterion;
 
 fn criterion_benchmark(c: &mut Criterion) {
@@ -10,12 +10,22 @@ fn criterion_benchmark(c: &mut Criterion) {
         width: 1000.,
         height: 800.,
         boid_count: 80000,
+        seed: 0,
         max_speed: 2.5,
         max_force: 0.4,
         mouse_weight: 600.,
         sep_radius: 6.,
         ali_radius: 11.5,
         coh_radius: 11.5,
+        target_weight: 3.0,
+        obstacle_weight: 2.0,
+        drag: 0.0,
+        cruise_speed: 0.0,
+        perch_chance: 0.0,
+        perch_time: 120.0,
+        max_turn_rate: 0.0,
+        verlet_skin: 0.0,
+        boundary: Boundary::Wrap,
         sep_weight: 1.5,
         ali_weight: 1.0,
         coh_weight: 1.0,
@@ -0,0 +1,180 @@
+// Analysis of the flock for active matter research, written out at the end of a run.
+// Every `interval` ticks the flock's polarization and the spatial correlation function of the
+// boids' velocity fluctuations are sampled:
+//
+//   C(r) = sum(dv_i . dv_j over pairs at distance r) / pairs at distance r / mean(dv_i . dv_i)
+//
+// where dv_i is a boid's velocity minus the flock's mean velocity. The correlation length is
+// where C(r) first crosses zero. Distances wrap around the edges of the world.
+
+use std::{
+    fs::File,
+    io::{self, prelude::*, BufWriter},
+};
+
+use cgmath::{InnerSpace, Vector2};
+
+use crate::system::FlockingSystem;
+
+// Correlations are computed over at most this many boids, as they take O(n^2) time
+const MAX_SAMPLED_BOIDS: usize = 2000;
+const CORRELATION_BINS: usize = 50;
+
+struct Sample {
+    tick: u64,
+    polarization: f32,
+    correlation_length: Option<f32>,
+}
+
+pub struct FlockAnalysis {
+    path: String,
+    interval: u64,
+    bin_width: f32,
+    samples: Vec<Sample>,
+    // Running sums of C(r) over all samples, for the averaged correlation function
+    correlation_sums: Vec<f64>,
+    correlation_counts: Vec<u64>,
+}
+
+impl FlockAnalysis {
+    pub fn new(path: &str, interval: u64, simulation: &FlockingSystem) -> FlockAnalysis {
+        let (width, height) = simulation.dimensions();
+        FlockAnalysis {
+            path: path.to_string(),
+            interval: interval.max(1),
+            bin_width: width.min(height) / 2. / CORRELATION_BINS as f32,
+            samples: vec![],
+            correlation_sums: vec![0.; CORRELATION_BINS],
+            correlation_counts: vec![0; CORRELATION_BINS],
+        }
+    }
+
+    pub fn poll(&mut self, simulation: &FlockingSystem) {
+        let tick = simulation.tick();
+        if !tick.is_multiple_of(self.interval) || self.samples.last().map(|s| s.tick) == Some(tick)
+        {
+            return;
+        }
+        let correlation = self.correlation(simulation);
+        for (bin, value) in correlation.iter().enumerate() {
+            if let Some(value) = value {
+                self.correlation_sums[bin] += f64::from(*value);
+                self.correlation_counts[bin] += 1;
+            }
+        }
+        self.samples.push(Sample {
+            tick,
+            polarization: simulation.stats().polarization,
+            correlation_length: self.correlation_length(&correlation),
+        });
+    }
+
+    pub fn finish(self) -> io::Result<()> {
+        let mut writer = BufWriter::new(File::create(&self.path)?);
+        writeln!(writer, "# Flock over time")?;
+        writeln!(writer, "tick,polarization,correlation_length")?;
+        for sample in &self.samples {
+            let length = sample
+                .correlation_length
+                .map(|l| l.to_string())
+                .unwrap_or_default();
+            writeln!(writer, "{},{},{}", sample.tick, sample.polarization, length)?;
+        }
+        writeln!(writer)?;
+        writeln!(
+            writer,
+            "# Velocity correlation function averaged over the run"
+        )?;
+        writeln!(writer, "r,correlation")?;
+        for (bin, (&sum, &count)) in self
+            .correlation_sums
+            .iter()
+            .zip(self.correlation_counts.iter())
+            .enumerate()
+        {
+            if count > 0 {
+                writeln!(writer, "{},{}", self.bin_centre(bin), sum / count as f64)?;
+            }
+        }
+        writer.flush()?;
+        println!("Wrote flock analysis to {}", self.path);
+        Ok(())
+    }
+
+    // C(r) for each distance bin, None for bins without any pairs
+    fn correlation(&self, simulation: &FlockingSystem) -> Vec<Option<f32>> {
+        let (width, height) = simulation.dimensions();
+        let boids = simulation.boids();
+        let stride = (boids.len() / MAX_SAMPLED_BOIDS).max(1);
+        let sampled: Vec<_> = boids.iter().step_by(stride).collect();
+        if sampled.is_empty() {
+            return vec![None; CORRELATION_BINS];
+        }
+
+        let mean_velocity = sampled
+            .iter()
+            .fold(Vector2::new(0., 0.), |acc, b| acc + b.velocity())
+            / sampled.len() as f32;
+        let fluctuations: Vec<Vector2<f32>> = sampled
+            .iter()
+            .map(|b| b.velocity() - mean_velocity)
+            .collect();
+        let variance =
+            fluctuations.iter().map(|v| v.magnitude2()).sum::<f32>() / sampled.len() as f32;
+        if variance == 0. {
+            return vec![None; CORRELATION_BINS];
+        }
+
+        let mut sums = vec![0f64; CORRELATION_BINS];
+        let mut counts = vec![0u64; CORRELATION_BINS];
+        for i in 0..sampled.len() {
+            for j in (i + 1)..sampled.len() {
+                let d = sampled[i].position() - sampled[j].position();
+                let dx = wrapped(d.x, width);
+                let dy = wrapped(d.y, height);
+                let bin = ((dx * dx + dy * dy).sqrt() / self.bin_width) as usize;
+                if bin < CORRELATION_BINS {
+                    sums[bin] += f64::from(fluctuations[i].dot(fluctuations[j]));
+                    counts[bin] += 1;
+                }
+            }
+        }
+        sums.iter()
+            .zip(counts.iter())
+            .map(|(&sum, &count)| match count {
+                0 => None,
+                _ => Some((sum / count as f64) as f32 / variance),
+            })
+            .collect()
+    }
+
+    // Distance at which C(r) first crosses zero, interpolated between bins
+    fn correlation_length(&self, correlation: &[Option<f32>]) -> Option<f32> {
+        let mut previous: Option<(f32, f32)> = None;
+        for (bin, value) in correlation.iter().enumerate() {
+            let value = match *value {
+                Some(value) => value,
+                None => continue,
+            };
+            let r = self.bin_centre(bin);
+            if value <= 0. {
+                return Some(match previous {
+                    Some((r0, c0)) => r0 + (r - r0) * c0 / (c0 - value),
+                    None => r,
+                });
+            }
+            previous = Some((r, value));
+        }
+        None
+    }
+
+    fn bin_centre(&self, bin: usize) -> f32 {
+        (bin as f32 + 0.5) * self.bin_width
+    }
+}
+
+// Shortest distance along one axis of the wrapping world
+fn wrapped(d: f32, size: f32) -> f32 {
+    let d = d.abs();
+    d.min(size - d)
+}
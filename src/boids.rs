@@ -1,25 +1,74 @@
-use std::{error, fmt, process};
+use std::{
+    error, fmt, io, process,
+    str::FromStr,
+    sync::mpsc::{self, Sender},
+    thread,
+    time::{Duration, Instant},
+};
 
 use gl;
 use glutin::{
     self, dpi, Api, ContextBuilder, ContextError, CreationError, EventsLoop, GlContext, GlProfile,
-    GlRequest, GlWindow, VirtualKeyCode, WindowBuilder,
+    GlRequest, GlWindow, MonitorId, WindowBuilder,
 };
+use rand::{self, Rng};
+use rayon::ThreadPoolBuilder;
 
+use crate::analysis::FlockAnalysis;
+use crate::checkpoint::{self, Checkpointer};
+use crate::command::Command;
+use crate::console;
+use crate::demo::Demo;
+use crate::desktop;
 use crate::event::{BoidControlEvent, EventFilter};
-use crate::fps::{FpsCache, FpsCounter};
+use crate::experiment;
+use crate::export::TrajectoryWriter;
+use crate::formation;
+use crate::fps::{FpsCache, FpsCounter, FrameLimiter};
 use crate::glx;
+use crate::governor::Governor;
+use crate::heatmap::HeatmapWriter;
+use crate::history::History;
+use crate::mask::Mask;
+#[cfg(feature = "metrics")]
+use crate::metrics::MetricsServer;
+#[cfg(feature = "midi")]
+use crate::midi::{self, MidiListener};
+use crate::osc;
+use crate::overlay::Overlay;
+use crate::profiler::Profiler;
+#[cfg(feature = "remote")]
+use crate::remote;
 use crate::render::{Renderer, RendererConfig};
-use crate::system::{FlockingConfig, FlockingSystem};
+use crate::replay::{Input, Player, Recorder};
+#[cfg(feature = "audio")]
+use crate::sonify::{self, Sonifier};
+use crate::state;
+use crate::stress::StressRamp;
+use crate::svg::TrailWriter;
+use crate::sync::{SyncFollower, SyncMaster};
+use crate::system::{
+    Boundary, Emitter, FlockingConfig, FlockingSystem, MouseForce, Parameter, Path, SocialForce,
+    Startle, Well,
+};
+use crate::teaching::Teaching;
+use crate::timeline::{Easing, Timeline};
+use crate::verify;
+use crate::video::VideoRecorder;
 
 const TITLE: &str = "rusty-boids";
 const CACHE_FPS_MS: u64 = 500;
+const DEFAULT_FPS_LIMIT: u32 = 60;
+const SUSPENDED_POLL_MS: u64 = 100;
 
 #[derive(Debug)]
 pub enum SimulatorError {
     GlCreation(CreationError),
     GlContext(ContextError),
     Window(String),
+    Io(io::Error),
+    // A replay being verified stopped matching its recording on this tick
+    Diverged(u64),
 }
 
 impl fmt::Display for SimulatorError {
@@ -28,6 +77,10 @@ impl fmt::Display for SimulatorError {
             SimulatorError::GlCreation(ref err) => write!(f, "GL creation error, {}", err),
             SimulatorError::GlContext(ref err) => write!(f, "GL context error, {}", err),
             SimulatorError::Window(ref err) => write!(f, "Window error, {}", err),
+            SimulatorError::Io(ref err) => write!(f, "IO error, {}", err),
+            SimulatorError::Diverged(tick) => {
+                write!(f, "Replay diverged from the recording at tick {}", tick)
+            }
         }
     }
 }
@@ -37,7 +90,8 @@ impl error::Error for SimulatorError {
         match *self {
             SimulatorError::GlCreation(ref err) => Some(err),
             SimulatorError::GlContext(ref err) => Some(err),
-            SimulatorError::Window(..) => None,
+            SimulatorError::Window(..) | SimulatorError::Diverged(..) => None,
+            SimulatorError::Io(ref err) => Some(err),
         }
     }
 }
@@ -54,6 +108,12 @@ impl From<ContextError> for SimulatorError {
     }
 }
 
+impl From<io::Error> for SimulatorError {
+    fn from(err: io::Error) -> SimulatorError {
+        SimulatorError::Io(err)
+    }
+}
+
 impl SimulatorError {
     pub fn exit(&self) -> ! {
         println!("{}", self);
@@ -63,6 +123,7 @@ impl SimulatorError {
 
 pub struct SimulationConfig {
     pub boid_count: u32,
+    pub seed: Option<u64>,
     pub window_size: WindowSize,
     pub debug: bool,
     pub max_speed: f32,
@@ -74,13 +135,176 @@ pub struct SimulationConfig {
     pub sep_radius: f32,
     pub ali_radius: f32,
     pub coh_radius: f32,
+    pub target_weight: f32,
+    pub obstacle_weight: f32,
+    pub drag: f32,
+    pub cruise_speed: f32,
+    pub perch_chance: f32,
+    pub perch_time: f32,
+    pub max_turn_rate: f32,
+    pub verlet_skin: f32,
+    pub obstacles: Option<String>,
+    pub formation: Option<FormationShape>,
     pub boid_size: f32,
+    pub speed_sizes: (f32, f32),
+    pub size_curve: f32,
+    pub species_sizes: Vec<f32>,
+    pub age_ramp: Option<u32>,
+    pub max_age: Option<u32>,
+    pub console: bool,
+    pub teaching: bool,
+    pub sonify: bool,
+    pub timelapse: u32,
+    pub compact_vertices: bool,
+    // Worker threads updating the flock, rayon picks one per core by default
+    pub threads: Option<usize>,
+    pub vsync: bool,
+    // Frames per second to hold to in software, when vsync is off or doesn't work
+    pub fps_limit: Option<u32>,
+    pub metrics_address: Option<String>,
+    pub remote_address: Option<String>,
+    pub osc: Option<OscConfig>,
+    pub midi: Option<MidiConfig>,
+    pub record_input: Option<String>,
+    pub play_input: Option<String>,
+    pub verify: Option<String>,
+    pub trajectory_path: Option<String>,
+    pub trajectory_interval: u64,
+    pub analysis_path: Option<String>,
+    pub analysis_interval: u64,
+    pub svg_path: Option<String>,
+    pub svg_interval: u64,
+    pub svg_boids: usize,
+    pub heatmap_path: Option<String>,
+    pub heatmap_interval: u64,
+    pub video_path: Option<String>,
+    pub video_fps: u32,
+    pub initial_state: Option<String>,
+    pub save_state: Option<String>,
+    // Seconds of history kept to rewind through
+    pub rewind: Option<f32>,
+    pub checkpoint: Option<CheckpointConfig>,
+    pub viewport: Option<(f32, f32, f32, f32)>,
+    pub sync: Option<SyncRole>,
+    pub timeline: Vec<Keyframe>,
+    pub emitters: Vec<Emitter>,
+    pub wells: Vec<Well>,
+    pub paths: Vec<Path>,
+    pub startle: Option<Startle>,
+    pub model: Model,
+    pub boundary: Boundary,
+    pub mouse_force: MouseForce,
+    pub mouse_swirl: f32,
+    pub social_force: SocialForce,
+    // Seconds between presets and seconds spent moving between them
+    pub demo: Option<(f32, f32)>,
+    pub experiment: Option<Experiment>,
+    pub sweep: Option<Sweep>,
+    // Frame budget in ms for the stress test
+    pub stress_budget: Option<f32>,
+    // Tick budget in ms for the quality governor
+    pub governor_budget: Option<f32>,
+}
+
+// Configs to compare headlessly, each named after the file it was loaded from
+pub struct Experiment {
+    pub ticks: u64,
+    pub variants: Vec<(String, SimulationConfig)>,
+}
+
+// A parameter to run headlessly at each of `steps + 1` values across `range`, or from zero to
+// twice its configured value, with the flock statistics for each written to `output`
+pub struct Sweep {
+    pub parameter: Parameter,
+    pub range: Option<(f32, f32)>,
+    pub steps: u32,
+    pub ticks: u64,
+    pub output: String,
+}
+
+// How boids decide where to go
+#[derive(Clone, Copy, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Model {
+    Boids,
+    SocialForce,
+}
+
+impl FromStr for Model {
+    type Err = String;
+
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        match name {
+            "boids" => Ok(Model::Boids),
+            "social_force" => Ok(Model::SocialForce),
+            _ => Err(format!("Unknown model {}", name)),
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct CheckpointConfig {
+    pub directory: String,
+    // Minutes between checkpoints
+    pub interval: f32,
+    // Checkpoints kept before the oldest is overwritten
+    pub keep: u32,
+    // Start from the latest checkpoint, if there is one
+    pub resume: bool,
+}
+
+// Shape the flock can form, press T to assemble and disperse
+pub enum FormationShape {
+    Image(String),
+    Text(String),
+}
+
+pub enum SyncRole {
+    Master { address: String, followers: usize },
+    Follower { address: String },
+}
+
+#[derive(Clone)]
+pub struct OscConfig {
+    pub address: String,
+    pub mouse_path: Option<String>,
+    pub mappings: Vec<OscMapping>,
+}
+
+#[derive(Clone)]
+pub struct OscMapping {
+    pub path: String,
+    pub parameter: Parameter,
+    pub range: Option<(f32, f32)>,
+}
+
+#[derive(Clone)]
+pub struct Keyframe {
+    pub tick: u64,
+    pub parameter: Parameter,
+    pub value: f32,
+    pub easing: Easing,
+}
+
+#[derive(Clone)]
+pub struct MidiConfig {
+    pub port: Option<String>,
+    pub mappings: Vec<MidiMapping>,
+}
+
+#[derive(Clone)]
+pub struct MidiMapping {
+    pub controller: u8,
+    pub channel: Option<u8>,
+    pub parameter: Parameter,
+    pub range: (f32, f32),
 }
 
 impl Default for SimulationConfig {
     fn default() -> SimulationConfig {
         SimulationConfig {
             boid_count: 1000,
+            seed: None,
             window_size: WindowSize::Dimensions((800, 800)),
             debug: false,
             max_speed: 2.5,
@@ -89,23 +313,94 @@ impl Default for SimulationConfig {
             sep_radius: 6.,
             ali_radius: 11.5,
             coh_radius: 11.5,
+            target_weight: 3.0,
+            obstacle_weight: 2.0,
+            drag: 0.0,
+            cruise_speed: 0.0,
+            perch_chance: 0.0,
+            perch_time: 120.0,
+            max_turn_rate: 0.0,
+            verlet_skin: 0.0,
+            obstacles: None,
+            formation: None,
             sep_weight: 1.5,
             ali_weight: 1.0,
             coh_weight: 1.0,
             boid_size: 3.0,
+            speed_sizes: (1.0, 1.0),
+            size_curve: 1.0,
+            species_sizes: vec![1.0],
+            age_ramp: None,
+            max_age: None,
+            console: false,
+            teaching: false,
+            sonify: false,
+            timelapse: 1,
+            compact_vertices: false,
+            threads: None,
+            vsync: true,
+            fps_limit: None,
+            metrics_address: None,
+            remote_address: None,
+            osc: None,
+            midi: None,
+            record_input: None,
+            play_input: None,
+            verify: None,
+            trajectory_path: None,
+            trajectory_interval: 10,
+            analysis_path: None,
+            analysis_interval: 10,
+            svg_path: None,
+            svg_interval: 2,
+            svg_boids: 500,
+            heatmap_path: None,
+            heatmap_interval: 1,
+            video_path: None,
+            video_fps: 60,
+            initial_state: None,
+            save_state: None,
+            rewind: None,
+            checkpoint: None,
+            viewport: None,
+            sync: None,
+            timeline: vec![],
+            emitters: vec![],
+            wells: vec![],
+            paths: vec![],
+            startle: None,
+            model: Model::Boids,
+            boundary: Boundary::Wrap,
+            mouse_force: MouseForce::Push,
+            mouse_swirl: 1.,
+            social_force: SocialForce {
+                desired_speed: 1.3,
+                relaxation: 10.,
+                repulsion: 0.3,
+                range: 3.,
+                radius: 3.,
+                destinations: vec![],
+            },
+            demo: None,
+            experiment: None,
+            sweep: None,
+            stress_budget: None,
+            governor_budget: None,
         }
     }
 }
 
-fn build_flocking_config(
+pub(crate) fn build_flocking_config(
     sim_config: &SimulationConfig,
-    window_size: &WindowSizeInfo,
+    (width, height): (f32, f32),
+    seed: u64,
 ) -> FlockingConfig {
     FlockingConfig {
         //TODO: Does the update syntax work here?
         boid_count: sim_config.boid_count,
-        width: window_size.width,
-        height: window_size.height,
+        seed,
+        width,
+        height,
         max_speed: sim_config.max_speed,
         max_force: sim_config.max_force,
         mouse_weight: sim_config.mouse_weight,
@@ -115,63 +410,606 @@ fn build_flocking_config(
         sep_radius: sim_config.sep_radius,
         ali_radius: sim_config.ali_radius,
         coh_radius: sim_config.coh_radius,
+        target_weight: sim_config.target_weight,
+        obstacle_weight: sim_config.obstacle_weight,
+        drag: sim_config.drag,
+        cruise_speed: sim_config.cruise_speed,
+        perch_chance: sim_config.perch_chance,
+        perch_time: sim_config.perch_time,
+        max_turn_rate: sim_config.max_turn_rate,
+        verlet_skin: sim_config.verlet_skin,
+        boundary: sim_config.boundary,
     }
 }
 
 fn build_render_config(
     sim_config: &SimulationConfig,
     window_size: &WindowSizeInfo,
+    world: (f32, f32),
 ) -> RendererConfig {
+    let (x, y, width, height) = sim_config.viewport.unwrap_or((0., 0., world.0, world.1));
     RendererConfig {
-        width: window_size.width,
-        height: window_size.height,
+        x,
+        y,
+        width,
+        height,
         boid_size: sim_config.boid_size * (window_size.hidpi_factor as f32),
         max_speed: sim_config.max_speed,
+        speed_sizes: sim_config.speed_sizes,
+        size_curve: sim_config.size_curve,
+        species_sizes: sim_config.species_sizes.clone(),
+        transparent: matches!(sim_config.window_size, WindowSize::Desktop),
+        cull: sim_config.viewport.is_some(),
+        compact_vertices: sim_config.compact_vertices,
+        world,
+        age_ramp: sim_config.age_ramp,
     }
 }
 
 pub enum WindowSize {
     Fullscreen,
+    // An undecorated window covering the primary monitor
+    Borderless,
+    // A transparent, click-through window over the primary monitor and every other window
+    Desktop,
+    // One borderless window covering the given monitors, or all of them
+    Span(Option<Vec<usize>>),
     Dimensions((u32, u32)),
 }
 
 pub fn run_simulation(config: SimulationConfig) -> Result<(), SimulatorError> {
+    if let Some(threads) = config.threads {
+        ThreadPoolBuilder::new()
+            .num_threads(threads)
+            .build_global()
+            .map_err(|err| io::Error::other(err.to_string()))?;
+    }
+    if let Some(ref experiment) = config.experiment {
+        let seed = config.seed.unwrap_or_else(|| rand::thread_rng().gen());
+        return experiment::run(seed, experiment);
+    }
+    if let Some(ref sweep) = config.sweep {
+        let seed = config.seed.unwrap_or_else(|| rand::thread_rng().gen());
+        return experiment::sweep(seed, &config, sweep);
+    }
+    if let Some(ref path) = config.verify {
+        return verify::run(path, &config);
+    }
     let mut events_loop = EventsLoop::new();
-    let window = build_window(&events_loop, &config.window_size)?;
-    gl_init(&window, config.debug)?;
-    let window_size = get_window_size_info(&window)?;
-    let flock_conf = build_flocking_config(&config, &window_size);
-    let render_conf = build_render_config(&config, &window_size);
+    let vsync = config.vsync && config.stress_budget.is_none();
+    let mut window = build_window(&events_loop, &config.window_size, vsync, config.debug)?;
+    let mut check_gl_errors = gl_init(&window, config.debug)?;
+    let mut window_size = get_window_size_info(&window)?;
+    let mut follower = match config.sync {
+        Some(SyncRole::Follower { ref address }) => Some(SyncFollower::connect(address)?),
+        _ => None,
+    };
+    let mut player = match config.play_input {
+        Some(ref path) => Some(Player::open(path)?),
+        None => None,
+    };
+    let seed = follower
+        .as_ref()
+        .map(SyncFollower::seed)
+        .or_else(|| player.as_ref().map(Player::seed))
+        .or(config.seed)
+        .unwrap_or_else(|| rand::thread_rng().gen());
+    // Followers simulate the master's world, whatever the size of their own window
+    let world = follower
+        .as_ref()
+        .map(SyncFollower::world)
+        .unwrap_or((window_size.width, window_size.height));
+    if let Some(ref p) = player {
+        if p.world() != world {
+            println!("Warning: window size differs from the recording, playback may diverge");
+        }
+    }
+    let mut recorder = match config.record_input {
+        Some(ref path) => Some(Recorder::create(path, seed, world.0, world.1)?),
+        None => None,
+    };
+    if let (Some(ref mut r), Some(seconds)) = (&mut recorder, config.rewind) {
+        r.rewind(seconds)?;
+    }
+    let flock_conf = build_flocking_config(&config, world, seed);
+    let render_conf = build_render_config(&config, &window_size, world);
+    let mut event_filter = EventFilter::new(
+        window_size.hidpi_factor,
+        (window_size.width, window_size.height),
+        (
+            render_conf.x,
+            render_conf.y,
+            render_conf.width,
+            render_conf.height,
+        ),
+    );
     let mut simulation = FlockingSystem::new(flock_conf);
-    simulation.randomise();
-    let renderer = Renderer::new(render_conf);
-    renderer.init_pipeline();
+    init_flock(&mut simulation, &config)?;
+    if let Some(ref shape) = config.formation {
+        formation::load(shape, &mut simulation)?;
+    }
+    let mut overlay = build_overlay(&config, &window_size, world);
+    let mut teaching = if config.teaching {
+        Some(Teaching::new(&mut simulation))
+    } else {
+        None
+    };
+    let obstacles = match config.obstacles {
+        Some(ref path) => Some(Mask::load(path)?),
+        None => None,
+    };
+    if let Some(ref mask) = obstacles {
+        simulation.set_obstacles(mask.width, mask.height, mask.filled.clone());
+    }
+    let mut renderer = build_renderer(&config, &window_size, world, obstacles.as_ref());
+    let mut suspended = false;
+    let mut lost_context = false;
+    if check_gl_errors {
+        glx::report_errors("setting up the renderer");
+    }
     let mut fps_counter = FpsCounter::new();
     let mut fps_cacher = FpsCache::new(CACHE_FPS_MS);
+    // Without vsync frames are limited anyway, unless stress testing wants every frame it can get
+    let fps_limit = match config.fps_limit {
+        None if !config.vsync && config.stress_budget.is_none() => Some(DEFAULT_FPS_LIMIT),
+        limit => limit,
+    };
+    let mut limiter = fps_limit.map(FrameLimiter::new);
     let mut running = true;
     let mut paused = false;
-    let event_filter = EventFilter::new(window_size.hidpi_factor);
+    let mut metrics = start_metrics(&config)?;
+    let (command_sender, commands) = mpsc::channel();
+    start_remote(&config, &command_sender)?;
+    if config.console {
+        console::start(command_sender.clone());
+    }
+    if let Some(ref osc_config) = config.osc {
+        osc::start(osc_config.clone(), command_sender.clone())?;
+    }
+    let _midi = start_midi(&config, &command_sender)?;
+    let mut sonifier = start_audio(&config)?;
+    let mut trajectory = match config.trajectory_path {
+        Some(ref path) => Some(TrajectoryWriter::create(path, config.trajectory_interval)?),
+        None => None,
+    };
+    let mut analysis = config
+        .analysis_path
+        .as_ref()
+        .map(|path| FlockAnalysis::new(path, config.analysis_interval, &simulation));
+    let mut trails = config
+        .svg_path
+        .as_ref()
+        .map(|path| TrailWriter::new(path, config.svg_interval, config.svg_boids));
+    let mut heatmap = config
+        .heatmap_path
+        .as_ref()
+        .map(|path| HeatmapWriter::new(path, config.heatmap_interval, &simulation));
+    let mut video = match config.video_path {
+        Some(ref path) => Some(VideoRecorder::start(
+            path,
+            window_size.width as u32,
+            window_size.height as u32,
+            config.video_fps,
+        )?),
+        None => None,
+    };
+    let mut master = match config.sync {
+        Some(SyncRole::Master {
+            ref address,
+            followers,
+        }) => Some(SyncMaster::serve(
+            address, followers, seed, world.0, world.1,
+        )?),
+        _ => None,
+    };
+    let timeline = Timeline::new(&config.timeline);
+    let mut stress = config.stress_budget.map(StressRamp::new);
+    let mut governor = config
+        .governor_budget
+        .map(|budget| Governor::new(budget, &simulation));
+    let mut history = rewind_seconds(&config, player.as_ref()).map(History::new);
+    let mut checkpoints = match config.checkpoint {
+        Some(ref c) => Some(Checkpointer::new(c)?),
+        None => None,
+    };
+    let mut demo = config
+        .demo
+        .map(|(interval, transition)| Demo::new(interval, transition));
+    let mut profiler = Profiler::new();
+    let mut pending_events = Vec::new();
+    let mut pending_commands = Vec::new();
     while running {
-        if !paused {
-            simulation.update();
-        }
-        events_loop.poll_events(|e| match event_filter.process(e) {
-            Some(BoidControlEvent::Stop) => running = false,
-            Some(BoidControlEvent::Pause) => paused = !paused,
-            Some(event) => handle_event(&mut simulation, event),
-            _ => (),
+        let tick_start = Instant::now();
+        if !paused && !suspended {
+            // Time-lapses only draw every few ticks, but everything else sees every tick
+            for _ in 0..config.timelapse {
+                timeline.apply(&mut simulation);
+                if let Some(ref mut d) = demo {
+                    d.poll(&mut simulation);
+                }
+                simulation.update();
+                if let Some(ref mut h) = history {
+                    h.poll(&simulation);
+                }
+                if let Some(ref mut r) = recorder {
+                    r.checksum(simulation.tick(), simulation.checksum())?;
+                }
+                if let Some(ref mut writer) = trajectory {
+                    writer.poll(&simulation)?;
+                }
+                if let Some(ref mut a) = analysis {
+                    a.poll(&simulation);
+                }
+                if let Some(ref mut t) = trails {
+                    t.poll(&simulation);
+                }
+                if let Some(ref mut h) = heatmap {
+                    h.poll(&simulation);
+                }
+            }
+        }
+        let tick_duration = tick_start.elapsed();
+        if let Some(ref mut g) = governor {
+            if !paused {
+                g.poll(&mut simulation, tick_duration / config.timelapse);
+            }
+        }
+        let mut window_changed = false;
+        let mut resumed = false;
+        events_loop.poll_events(|e| {
+            if let glutin::Event::Suspended(s) = e {
+                resumed |= suspended && !s;
+                suspended = s;
+            }
+            window_changed |= matches!(
+                e,
+                glutin::Event::WindowEvent {
+                    event: glutin::WindowEvent::Resized(_)
+                        | glutin::WindowEvent::HiDpiFactorChanged(_),
+                    ..
+                }
+            );
+            if let Some(event) = event_filter.process(e) {
+                pending_events.push(event);
+            }
         });
-        renderer.render(&simulation.boids());
-        window.swap_buffers()?;
+        if window_changed {
+            // The world keeps its size, it's just drawn to a different number of pixels
+            window_size = get_window_size_info(&window)?;
+            let (width, height) = (window_size.width, window_size.height);
+            window.resize(dpi::PhysicalSize::new(f64::from(width), f64::from(height)));
+            glx::set_viewport(width as u32, height as u32);
+            event_filter.resize(window_size.hidpi_factor, (width, height));
+            renderer.set_boid_size(config.boid_size * window_size.hidpi_factor as f32);
+            overlay.resize((width, height), (2. * window_size.hidpi_factor) as f32);
+            if video.is_some() {
+                println!("Warning: window size changed, video frames keep their original size");
+            }
+        }
+        pending_commands.extend(commands.try_iter());
+        if player.is_some() || follower.is_some() {
+            // Only let the user quit while input is coming from elsewhere
+            pending_events.retain(|e| matches!(e, BoidControlEvent::Stop));
+            pending_commands.clear();
+        }
+        if let Some(ref mut p) = player {
+            p.replay(simulation.tick(), |input| match input {
+                Input::Event(e) => pending_events.push(e),
+                Input::Command(c) => pending_commands.push(c),
+            });
+        }
+        // Input can rewind the flock, so frames are matched by the tick they start on
+        let frame_tick = simulation.tick();
+        if let Some(ref mut f) = follower {
+            let frame = f.next_frame(frame_tick, |input| match input {
+                Input::Event(e) => pending_events.push(e),
+                Input::Command(c) => pending_commands.push(c),
+            })?;
+            if !frame {
+                println!("Sync master disconnected");
+                running = false;
+            }
+        }
+        for event in pending_events.drain(..) {
+            if let Some(ref mut r) = recorder {
+                r.record(simulation.tick(), &event)?;
+            }
+            if let Some(ref mut m) = master {
+                m.send(simulation.tick(), &event)?;
+            }
+            match event {
+                BoidControlEvent::Stop => running = false,
+                BoidControlEvent::Pause => match teaching {
+                    Some(ref mut t) => t.next_stage(&mut simulation),
+                    None => paused = !paused,
+                },
+                BoidControlEvent::ToggleProfiler => profiler.toggle(),
+                BoidControlEvent::StepBack => match history {
+                    Some(ref mut h) => h.back(&mut simulation),
+                    None => println!("Rewinding is off, run with --rewind SECONDS to keep history"),
+                },
+                BoidControlEvent::StepForward => {
+                    if let Some(ref mut h) = history {
+                        h.forward(&mut simulation);
+                    }
+                }
+                event => handle_event(&mut simulation, event),
+            }
+        }
+        for command in pending_commands.drain(..) {
+            if let Some(ref mut r) = recorder {
+                r.command(simulation.tick(), &command)?;
+            }
+            if let Some(ref mut m) = master {
+                m.send_command(simulation.tick(), &command)?;
+            }
+            apply_command(&mut simulation, &mut paused, command);
+        }
+        if let Some(ref mut m) = master {
+            m.end_frame(frame_tick)?;
+        }
+        if resumed {
+            // Suspending can take the context with it, so start afresh
+            lost_context = true;
+        }
+        if lost_context {
+            println!("Recreating the GL context");
+            // The old GL objects have to go before another context is made current
+            drop(renderer);
+            drop(overlay);
+            window = build_window(&events_loop, &config.window_size, vsync, config.debug)?;
+            check_gl_errors = gl_init(&window, config.debug)?;
+            window_size = get_window_size_info(&window)?;
+            event_filter.resize(
+                window_size.hidpi_factor,
+                (window_size.width, window_size.height),
+            );
+            renderer = build_renderer(&config, &window_size, world, obstacles.as_ref());
+            overlay = build_overlay(&config, &window_size, world);
+            lost_context = false;
+        }
+        if suspended {
+            // Nothing can be drawn until the app is resumed
+            thread::sleep(Duration::from_millis(SUSPENDED_POLL_MS));
+            continue;
+        }
+        renderer.render(simulation.boids());
+        if let Some(ref t) = teaching {
+            t.draw(&simulation, &mut overlay);
+        }
+        profiler.draw(&mut overlay);
+        overlay.draw();
+        if check_gl_errors {
+            glx::report_errors("drawing a frame");
+        }
+        if let Some(ref mut recorder) = video {
+            recorder.capture()?;
+        }
+        if let Some(ref mut l) = limiter {
+            l.wait();
+        }
+        let swap_start = Instant::now();
+        match window.swap_buffers() {
+            // Laptops switching GPUs and driver resets lose the context, but not the flock
+            Err(ContextError::ContextLost) => {
+                println!("GL context lost");
+                lost_context = true;
+            }
+            result => result?,
+        }
+        let update = simulation.take_timings();
+        let (upload, draw) = renderer.timings();
+        profiler.record([
+            update.neighbours,
+            update.forces,
+            update.integration,
+            upload,
+            draw,
+            swap_start.elapsed(),
+        ]);
         fps_counter.tick();
         fps_cacher.poll(&fps_counter, |new_fps| {
             let title = format!("{} - {:02} fps", TITLE, new_fps);
             window.set_title(&title);
         });
+        if let Some(ref mut server) = metrics {
+            server.poll(fps_counter.average_fps(), tick_duration, &simulation);
+        }
+        if let Some(ref mut s) = sonifier {
+            s.poll(&simulation);
+        }
+        if let Some(ref mut ramp) = stress {
+            running &= ramp.poll(&mut simulation);
+        }
+        if let Some(ref mut c) = checkpoints {
+            c.poll(&simulation);
+        }
+    }
+    if let Some(c) = checkpoints {
+        c.finish();
+    }
+    if let Some(ramp) = stress {
+        ramp.report();
+    }
+    if let Some(writer) = trajectory {
+        writer.finish()?;
+    }
+    if let Some(recorder) = video {
+        recorder.finish()?;
+    }
+    if let Some(a) = analysis {
+        a.finish()?;
+    }
+    if let Some(t) = trails {
+        t.finish(&simulation)?;
+    }
+    if let Some(h) = heatmap {
+        h.finish()?;
+    }
+    if let Some(ref path) = config.save_state {
+        state::save(path, &simulation)?;
     }
     Ok(())
 }
 
+pub(crate) fn init_flock(
+    simulation: &mut FlockingSystem,
+    config: &SimulationConfig,
+) -> Result<(), SimulatorError> {
+    let resume = config
+        .checkpoint
+        .as_ref()
+        .filter(|c| c.resume)
+        .and_then(|c| checkpoint::latest_in(&c.directory));
+    if let Some(ref path) = resume {
+        println!("Resuming from checkpoint {}", path.display());
+        let initial = state::load(path)?;
+        simulation.restore(initial.tick, &initial.boids);
+    } else if let Some(ref path) = config.initial_state {
+        let initial = state::load(path)?;
+        simulation.restore(initial.tick, &initial.boids);
+    } else {
+        simulation.randomise();
+    }
+    simulation.set_mouse_force(config.mouse_force, config.mouse_swirl);
+    simulation.set_max_age(config.max_age);
+    simulation.set_startle(config.startle.clone());
+    if let Model::SocialForce = config.model {
+        simulation.set_social_force(Some(config.social_force.clone()));
+    }
+    for path in &config.paths {
+        simulation.add_path(path.clone());
+    }
+    for well in &config.wells {
+        simulation.add_well(well.clone());
+    }
+    for emitter in &config.emitters {
+        simulation.add_emitter(emitter.clone());
+    }
+    Ok(())
+}
+
+#[cfg(feature = "metrics")]
+fn start_metrics(config: &SimulationConfig) -> Result<Option<MetricsServer>, SimulatorError> {
+    match config.metrics_address {
+        Some(ref address) => Ok(Some(MetricsServer::start(address)?)),
+        None => Ok(None),
+    }
+}
+
+#[cfg(not(feature = "metrics"))]
+fn start_metrics(config: &SimulationConfig) -> Result<Option<NoMetrics>, SimulatorError> {
+    if config.metrics_address.is_some() {
+        println!("Ignoring metrics address, rebuild with `--features metrics` to enable it");
+    }
+    Ok(None)
+}
+
+#[cfg(not(feature = "metrics"))]
+struct NoMetrics;
+
+#[cfg(not(feature = "metrics"))]
+impl NoMetrics {
+    fn poll(&mut self, _fps: u32, _tick: std::time::Duration, _simulation: &FlockingSystem) {}
+}
+
+#[cfg(feature = "remote")]
+fn start_remote(config: &SimulationConfig, sender: &Sender<Command>) -> Result<(), SimulatorError> {
+    if let Some(ref address) = config.remote_address {
+        remote::start(address, sender.clone())?;
+    }
+    Ok(())
+}
+
+#[cfg(not(feature = "remote"))]
+fn start_remote(
+    config: &SimulationConfig,
+    _sender: &Sender<Command>,
+) -> Result<(), SimulatorError> {
+    if config.remote_address.is_some() {
+        println!("Ignoring remote address, rebuild with `--features remote` to enable it");
+    }
+    Ok(())
+}
+
+#[cfg(feature = "midi")]
+fn start_midi(
+    config: &SimulationConfig,
+    sender: &Sender<Command>,
+) -> Result<Option<MidiListener>, SimulatorError> {
+    match config.midi {
+        Some(ref midi_config) => Ok(Some(midi::start(midi_config.clone(), sender.clone())?)),
+        None => Ok(None),
+    }
+}
+
+#[cfg(not(feature = "midi"))]
+fn start_midi(
+    config: &SimulationConfig,
+    _sender: &Sender<Command>,
+) -> Result<Option<()>, SimulatorError> {
+    if config.midi.is_some() {
+        println!("Ignoring midi config, rebuild with `--features midi` to enable it");
+    }
+    Ok(None)
+}
+
+#[cfg(feature = "audio")]
+fn start_audio(config: &SimulationConfig) -> Result<Option<Sonifier>, SimulatorError> {
+    if config.sonify {
+        Ok(Some(sonify::start()?))
+    } else {
+        Ok(None)
+    }
+}
+
+#[cfg(not(feature = "audio"))]
+fn start_audio(config: &SimulationConfig) -> Result<Option<NoAudio>, SimulatorError> {
+    if config.sonify {
+        println!("Ignoring sonify, rebuild with `--features audio` to enable it");
+    }
+    Ok(None)
+}
+
+#[cfg(not(feature = "audio"))]
+struct NoAudio;
+
+#[cfg(not(feature = "audio"))]
+impl NoAudio {
+    fn poll(&mut self, _simulation: &FlockingSystem) {}
+}
+
+// Builds everything the flock is drawn with, again whenever the GL context is lost
+fn build_renderer(
+    config: &SimulationConfig,
+    window_size: &WindowSizeInfo,
+    world: (f32, f32),
+    obstacles: Option<&Mask>,
+) -> Renderer {
+    let mut renderer = Renderer::new(build_render_config(config, window_size, world));
+    if let Some(mask) = obstacles {
+        let coverage: Vec<u8> = mask
+            .filled
+            .iter()
+            .map(|&f| if f { 255 } else { 0 })
+            .collect();
+        renderer.set_backdrop(world, mask.width, mask.height, &coverage);
+    }
+    renderer.init_pipeline();
+    renderer
+}
+
+fn build_overlay(
+    config: &SimulationConfig,
+    window_size: &WindowSizeInfo,
+    world: (f32, f32),
+) -> Overlay {
+    let view = build_render_config(config, window_size, world);
+    let pixel_size = (2. * window_size.hidpi_factor) as f32;
+    let window = (window_size.width, window_size.height);
+    Overlay::new(&view, window, pixel_size)
+}
+
 struct WindowSizeInfo {
     width: f32,
     height: f32,
@@ -186,35 +1024,101 @@ fn get_window_size_info(window: &GlWindow) -> Result<WindowSizeInfo, SimulatorEr
 
     let physical_size = logical_size.to_physical(hidpi_factor);
 
-    Ok(WindowSizeInfo{
+    Ok(WindowSizeInfo {
         width: physical_size.width as f32,
         height: physical_size.height as f32,
         hidpi_factor,
     })
 }
 
-fn handle_event(simulation: &mut FlockingSystem, event: BoidControlEvent) {
+pub(crate) fn handle_event(simulation: &mut FlockingSystem, event: BoidControlEvent) {
     match event {
         BoidControlEvent::MouseMove(x, y) => simulation.set_mouse(x, y),
         BoidControlEvent::MousePress => simulation.enable_mouse_attraction(),
         BoidControlEvent::MouseRelease => simulation.enable_mouse_repulsion(),
-        BoidControlEvent::Key(VirtualKeyCode::R) => simulation.randomise(),
-        BoidControlEvent::Key(VirtualKeyCode::F) => simulation.zeroise(),
-        BoidControlEvent::Key(VirtualKeyCode::C) => simulation.centralise(),
+        BoidControlEvent::Randomise => simulation.randomise(),
+        BoidControlEvent::Zeroise => simulation.zeroise(),
+        BoidControlEvent::Centralise => simulation.centralise(),
+        BoidControlEvent::ToggleFormation => {
+            if simulation.is_forming() {
+                simulation.disperse()
+            } else {
+                simulation.form()
+            }
+        }
         _ => (),
     }
 }
 
+// Playback keeps as much history as the recording did, so stepping back goes to the same place
+pub(crate) fn rewind_seconds(config: &SimulationConfig, player: Option<&Player>) -> Option<f32> {
+    match player {
+        Some(p) => {
+            if p.rewind() != config.rewind {
+                println!(
+                    "Warning: rewind history differs from the recording, using the recording's"
+                );
+            }
+            p.rewind()
+        }
+        None => config.rewind,
+    }
+}
+
+pub(crate) fn apply_command(simulation: &mut FlockingSystem, paused: &mut bool, command: Command) {
+    match command {
+        Command::Set { parameter, value } => simulation.set_parameter(parameter, value),
+        Command::Pause => *paused = true,
+        Command::Resume => *paused = false,
+        Command::Randomise => simulation.randomise(),
+        Command::Centralise => simulation.centralise(),
+        Command::Zeroise => simulation.zeroise(),
+        Command::Form => simulation.form(),
+        Command::Disperse => simulation.disperse(),
+        Command::Spawn { count, position } => simulation.spawn(count, position),
+        Command::Mouse { x, y } => {
+            let (width, height) = simulation.dimensions();
+            simulation.set_mouse(x * width, y * height);
+        }
+        Command::Save { path } => match state::save(&path, simulation) {
+            Ok(()) => println!("Saved state to {}", path),
+            Err(err) => println!("Could not save state to {}: {}", path, err),
+        },
+    }
+}
+
 fn build_window(
     events_loop: &EventsLoop,
     window_size: &WindowSize,
+    vsync: bool,
+    debug: bool,
 ) -> Result<GlWindow, SimulatorError> {
     let window_builder = WindowBuilder::new().with_title(TITLE);
+    let mut position = None;
     let window_builder = match window_size {
         WindowSize::Fullscreen => {
             let screen = Some(events_loop.get_primary_monitor());
             window_builder.with_fullscreen(screen)
         }
+        WindowSize::Borderless => {
+            let (origin, size) = monitor_bounds(&[events_loop.get_primary_monitor()])?;
+            position = Some(origin);
+            borderless(window_builder, size)
+        }
+        WindowSize::Desktop => {
+            let (origin, size) = monitor_bounds(&[events_loop.get_primary_monitor()])?;
+            position = Some(origin);
+            borderless(window_builder, size)
+                .with_transparency(true)
+                .with_always_on_top(true)
+        }
+        WindowSize::Span(monitors) => {
+            let monitors = chosen_monitors(events_loop, monitors.as_deref())?;
+            let (origin, size) = monitor_bounds(&monitors)?;
+            println!("Spanning {} monitors", monitors.len());
+            position = Some(origin);
+            borderless(window_builder, size)
+        }
         WindowSize::Dimensions((width, height)) => window_builder
             .with_dimensions(dpi::LogicalSize::new(f64::from(*width), f64::from(*height))),
     };
@@ -222,22 +1126,107 @@ fn build_window(
     let context_builder = ContextBuilder::new()
         .with_gl(GlRequest::Specific(Api::OpenGl, (3, 3)))
         .with_gl_profile(GlProfile::Core)
-        .with_vsync(true);
+        .with_vsync(vsync)
+        .with_gl_debug_flag(debug);
+    let context_builder = match window_size {
+        // The background can only be see through with an alpha channel
+        WindowSize::Desktop => context_builder.with_pixel_format(24, 8),
+        _ => context_builder,
+    };
+
+    let window = GlWindow::new(window_builder, context_builder, events_loop)?;
+    if let Some(origin) = position {
+        window.set_position(origin);
+    }
+    if let WindowSize::Desktop = window_size {
+        desktop::click_through(&window);
+        println!("Flying over the desktop, quit with Ctrl-C in the terminal");
+    }
+    Ok(window)
+}
+
+// An undecorated window filling some monitors, which unlike exclusive fullscreen lets other
+// windows be alt-tabbed to and screen capture software see it
+fn borderless(window_builder: WindowBuilder, size: dpi::LogicalSize) -> WindowBuilder {
+    window_builder
+        .with_decorations(false)
+        .with_resizable(false)
+        .with_dimensions(size)
+}
 
-    Ok(GlWindow::new(window_builder, context_builder, events_loop)?)
+fn chosen_monitors(
+    events_loop: &EventsLoop,
+    chosen: Option<&[usize]>,
+) -> Result<Vec<MonitorId>, SimulatorError> {
+    let available: Vec<_> = events_loop.get_available_monitors().collect();
+    for (i, monitor) in available.iter().enumerate() {
+        let (position, size) = (monitor.get_position(), monitor.get_dimensions());
+        println!(
+            "Monitor {}: {} {}x{} at ({}, {})",
+            i,
+            monitor.get_name().unwrap_or_default(),
+            size.width,
+            size.height,
+            position.x,
+            position.y
+        );
+    }
+
+    match chosen {
+        Some(indices) => indices
+            .iter()
+            .map(|&i| {
+                available
+                    .get(i)
+                    .cloned()
+                    .ok_or_else(|| SimulatorError::Window(format!("no monitor {}", i)))
+            })
+            .collect(),
+        None => Ok(available),
+    }
+}
+
+// Bounding box of the monitors, so the world keeps the same scale on every screen
+fn monitor_bounds(
+    monitors: &[MonitorId],
+) -> Result<(dpi::LogicalPosition, dpi::LogicalSize), SimulatorError> {
+    let first = monitors
+        .first()
+        .ok_or_else(|| SimulatorError::Window("no monitors to cover".to_string()))?;
+
+    let (mut left, mut top) = (f64::MAX, f64::MAX);
+    let (mut right, mut bottom) = (f64::MIN, f64::MIN);
+    for monitor in monitors {
+        let (position, size) = (monitor.get_position(), monitor.get_dimensions());
+        left = left.min(position.x);
+        top = top.min(position.y);
+        right = right.max(position.x + size.width);
+        bottom = bottom.max(position.y + size.height);
+    }
+    let hidpi_factor = first.get_hidpi_factor();
+    let origin = dpi::PhysicalPosition::new(left, top).to_logical(hidpi_factor);
+    let size = dpi::PhysicalSize::new(right - left, bottom - top).to_logical(hidpi_factor);
+    Ok((origin, size))
 }
 
-fn gl_init(window: &GlWindow, debug: bool) -> Result<(), SimulatorError> {
+// Returns whether GL errors have to be checked for every frame, in debug mode without debug output
+fn gl_init(window: &GlWindow, debug: bool) -> Result<bool, SimulatorError> {
     unsafe {
         window.make_current()?;
     }
     gl::load_with(|symbol| window.get_proc_address(symbol) as *const _);
 
     if debug {
-        print_debug_info(&window);
+        print_debug_info(window);
+        if glx::enable_debug_output() {
+            println!("Printing GL debug messages");
+        } else {
+            println!("GL debug output is unavailable, checking for errors every frame instead");
+            return Ok(true);
+        }
     }
 
-    Ok(())
+    Ok(false)
 }
 
 fn print_debug_info(window: &GlWindow) {
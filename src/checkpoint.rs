@@ -0,0 +1,110 @@
+// Periodic checkpoints of the flock, so long running installations can pick up where they left
+// off after a crash or power cut. Checkpoints are compressed saved states written by a background
+// thread, to a temporary file that's renamed into place so a half written one is never loaded.
+// A fixed number are kept, going round the same file names.
+
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+    thread::{self, JoinHandle},
+    time::{Duration, Instant},
+};
+
+use crate::boids::CheckpointConfig;
+use crate::state::{self, SavedState};
+use crate::system::FlockingSystem;
+
+const PREFIX: &str = "checkpoint-";
+const EXTENSION: &str = ".bin.gz";
+
+pub struct Checkpointer {
+    directory: PathBuf,
+    interval: Duration,
+    keep: u32,
+    slot: u32,
+    last: Instant,
+    writer: Option<JoinHandle<io::Result<PathBuf>>>,
+}
+
+impl Checkpointer {
+    pub fn new(config: &CheckpointConfig) -> io::Result<Checkpointer> {
+        let directory = PathBuf::from(&config.directory);
+        fs::create_dir_all(&directory)?;
+        // Carry on after the newest checkpoint rather than overwriting it
+        let slot = latest(&directory)
+            .and_then(|(_, slot)| slot.checked_add(1))
+            .unwrap_or(0)
+            % config.keep;
+        Ok(Checkpointer {
+            directory,
+            // Intervals too long to represent never come round anyway
+            interval: Duration::try_from_secs_f32(config.interval.max(0.) * 60.)
+                .unwrap_or(Duration::MAX),
+            keep: config.keep,
+            slot,
+            last: Instant::now(),
+            writer: None,
+        })
+    }
+
+    pub fn poll(&mut self, simulation: &FlockingSystem) {
+        if self.last.elapsed() < self.interval {
+            return;
+        }
+        // Let a slow disk finish the last checkpoint before starting another
+        if self.writer.as_ref().is_some_and(|w| !w.is_finished()) {
+            return;
+        }
+        self.report();
+        self.last = Instant::now();
+        let state = SavedState::capture(simulation);
+        let path = self
+            .directory
+            .join(format!("{}{}{}", PREFIX, self.slot, EXTENSION));
+        self.slot = (self.slot + 1) % self.keep;
+        self.writer = Some(thread::spawn(move || {
+            let partial = path.with_extension("tmp");
+            state::write_compressed(&partial, &state)?;
+            fs::rename(&partial, &path)?;
+            Ok(path)
+        }));
+    }
+
+    // Waits for the checkpoint being written, if any
+    pub fn finish(mut self) {
+        self.report();
+    }
+
+    fn report(&mut self) {
+        if let Some(writer) = self.writer.take() {
+            match writer.join() {
+                Ok(Ok(_)) => {}
+                Ok(Err(err)) => println!("Warning: could not write checkpoint: {}", err),
+                Err(_) => println!("Warning: checkpoint writer panicked"),
+            }
+        }
+    }
+}
+
+// The most recently written checkpoint in the directory, if there are any
+pub fn latest_in(directory: &str) -> Option<PathBuf> {
+    latest(Path::new(directory)).map(|(path, _)| path)
+}
+
+fn latest(directory: &Path) -> Option<(PathBuf, u32)> {
+    fs::read_dir(directory)
+        .ok()?
+        .filter_map(|entry| {
+            let entry = entry.ok()?;
+            let name = entry.file_name().into_string().ok()?;
+            let slot = name
+                .strip_prefix(PREFIX)?
+                .strip_suffix(EXTENSION)?
+                .parse()
+                .ok()?;
+            let modified = entry.metadata().ok()?.modified().ok()?;
+            Some((modified, entry.path(), slot))
+        })
+        .max_by_key(|&(modified, ..)| modified)
+        .map(|(_, path, slot)| (path, slot))
+}
@@ -0,0 +1,31 @@
+use crate::system::Parameter;
+
+// Commands that can be sent to a running simulation from outside the window
+#[derive(Clone, Debug, PartialEq, Deserialize)]
+#[serde(tag = "command", rename_all = "snake_case")]
+pub enum Command {
+    Set {
+        parameter: Parameter,
+        value: f32,
+    },
+    Pause,
+    Resume,
+    Randomise,
+    Centralise,
+    Zeroise,
+    Form,
+    Disperse,
+    Spawn {
+        count: u32,
+        position: Option<(f32, f32)>,
+    },
+    // Position as a fraction of the simulation's width and height
+    Mouse {
+        x: f32,
+        y: f32,
+    },
+    // Saves the state of the flock to a file
+    Save {
+        path: String,
+    },
+}
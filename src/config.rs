@@ -1,6 +1,11 @@
 use std::{fmt, fs::File, io, io::prelude::*, process};
 
-use crate::boids::{SimulationConfig, WindowSize};
+use crate::boids::{
+    CheckpointConfig, Experiment, FormationShape, Keyframe, MidiConfig, MidiMapping, Model,
+    OscConfig, OscMapping, SimulationConfig, Sweep, SyncRole, WindowSize,
+};
+use crate::system::{Boundary, Emitter, MouseForce, Parameter, Path, Startle, Well};
+use crate::timeline::Easing;
 
 use clap::{
     self, App, Arg, ArgMatches,
@@ -8,21 +13,145 @@ use clap::{
 };
 use toml;
 
+const DEFAULT_OSC_ADDRESS: &str = "0.0.0.0:9000";
+const DEFAULT_EXPERIMENT_TICKS: u64 = 1000;
+const DEFAULT_SWEEP_STEPS: u32 = 10;
+const DEFAULT_SWEEP_OUTPUT: &str = "sweep.csv";
+const DEFAULT_FRAME_BUDGET_MS: f32 = 1000. / 60.;
+const DEFAULT_TICK_BUDGET_MS: f32 = 8.;
+const DEFAULT_DEMO_INTERVAL: f32 = 30.;
+const DEFAULT_DEMO_TRANSITION: f32 = 5.;
+const DEFAULT_SOFTENING: f32 = 5.;
+const DEFAULT_PATH_RADIUS: f32 = 20.;
+const DEFAULT_STARTLE_INTERVAL: f32 = 600.;
+const DEFAULT_STARTLE_STRENGTH: f32 = 300.;
+const DEFAULT_STARTLE_RADIUS: f32 = 40.;
+const DEFAULT_STARTLE_DURATION: u32 = 10;
+const DEFAULT_CHECKPOINT_DIRECTORY: &str = "checkpoints";
+const DEFAULT_CHECKPOINT_INTERVAL: f32 = 10.;
+const DEFAULT_CHECKPOINT_KEEP: u32 = 3;
+const MIN_WINDOW_SIZE: u32 = 64;
+const MIN_BOID_SIZE: f32 = 1.;
+const MIN_MAX_SPEED: f32 = 0.001;
+const FISH_ALI_WEIGHT: f32 = 2.;
+const FISH_DRAG: f32 = 0.02;
+const FISH_CRUISE_SPEED: f32 = 1.8;
+
 const CONFIG_ARG: &str = "config";
 const WINDOW_SIZE_ARG: &str = "size";
 const FULLSCREEN_ARG: &str = "fullscreen";
+const BORDERLESS_ARG: &str = "borderless";
+const DESKTOP_ARG: &str = "desktop";
+const SPAN_ARG: &str = "span";
+const MONITORS_ARG: &str = "monitors";
 const BOID_COUNT_ARG: &str = "boids";
 const DEBUG_ARG: &str = "debug";
+const CONSOLE_ARG: &str = "console";
+const TEACHING_ARG: &str = "teaching";
+const FORM_IMAGE_ARG: &str = "form-image";
+const FORM_TEXT_ARG: &str = "form-text";
+const OBSTACLES_ARG: &str = "obstacles";
+const FISH_ARG: &str = "fish";
+const THREADS_ARG: &str = "threads";
+const TIMELAPSE_ARG: &str = "timelapse";
+const SONIFY_ARG: &str = "sonify";
+const NO_VSYNC_ARG: &str = "no-vsync";
+const MODEL_ARG: &str = "model";
+const BOUNDARY_ARG: &str = "boundary";
+const FPS_LIMIT_ARG: &str = "fps-limit";
+const METRICS_ARG: &str = "metrics";
+const REMOTE_ARG: &str = "remote";
+const OSC_ARG: &str = "osc";
+const SEED_ARG: &str = "seed";
+const RECORD_INPUT_ARG: &str = "record-input";
+const PLAY_INPUT_ARG: &str = "play-input";
+const VERIFY_ARG: &str = "verify";
+const EXPORT_TRAJECTORY_ARG: &str = "export-trajectory";
+const EXPORT_INTERVAL_ARG: &str = "export-interval";
+const ANALYSIS_ARG: &str = "analysis";
+const EXPORT_SVG_ARG: &str = "export-svg";
+const EXPORT_HEATMAP_ARG: &str = "export-heatmap";
+const RECORD_VIDEO_ARG: &str = "record-video";
+const VIDEO_FPS_ARG: &str = "video-fps";
+const INITIAL_STATE_ARG: &str = "initial-state";
+const SAVE_STATE_ARG: &str = "save-state";
+const CHECKPOINT_ARG: &str = "checkpoint";
+const RESUME_ARG: &str = "resume";
+const REWIND_ARG: &str = "rewind";
+const VIEWPORT_ARG: &str = "viewport";
+const SYNC_SERVE_ARG: &str = "sync-serve";
+const SYNC_FOLLOWERS_ARG: &str = "sync-followers";
+const SYNC_FOLLOW_ARG: &str = "sync-follow";
+const COMPARE_ARG: &str = "compare";
+const TICKS_ARG: &str = "ticks";
+const SWEEP_ARG: &str = "sweep";
+const SWEEP_RANGE_ARG: &str = "sweep-range";
+const SWEEP_STEPS_ARG: &str = "sweep-steps";
+const SWEEP_OUTPUT_ARG: &str = "sweep-output";
+const STRESS_ARG: &str = "stress";
+const GOVERNOR_ARG: &str = "governor";
+const TICK_BUDGET_ARG: &str = "tick-budget";
+const DEMO_ARG: &str = "demo";
+const DEMO_INTERVAL_ARG: &str = "demo-interval";
+const FRAME_BUDGET_ARG: &str = "frame-budget";
 
 pub fn build_config() -> Result<SimulationConfig, ConfigError> {
-    let mut builder = ConfigBuilder::new();
-
     let cli_args = parse_cli_args()?;
+    let mut config = build_layered_config(&cli_args, None)?;
+
+    if let Some(paths) = cli_args.values_of(COMPARE_ARG) {
+        let mut experiment = Experiment {
+            ticks: DEFAULT_EXPERIMENT_TICKS,
+            variants: vec![],
+        };
+        if cli_args.is_present(TICKS_ARG) {
+            experiment.ticks = value_t!(cli_args, TICKS_ARG, u64)?;
+        }
+        for path in paths {
+            let variant = build_layered_config(&cli_args, Some(path))?;
+            experiment.variants.push((path.to_string(), variant));
+        }
+        config.experiment = Some(experiment);
+    }
+    config.verify = cli_args.value_of(VERIFY_ARG).map(String::from);
+    check_sync(&config)?;
+
+    Ok(config)
+}
+
+// Synchronised machines only share their input, so nothing else may change the flock
+fn check_sync(config: &SimulationConfig) -> Result<(), ConfigError> {
+    if config.sync.is_none() {
+        return Ok(());
+    }
+    let unsynchronised = [
+        ("stress testing", config.stress_budget.is_some()),
+        ("the demo mode", config.demo.is_some()),
+        ("the governor", config.governor_budget.is_some()),
+    ];
+    match unsynchronised.iter().find(|&&(_, on)| on) {
+        Some(&(name, _)) => Err(ConfigError::Conflict(format!(
+            "{} can't be used with sync, as it changes the flock on one machine only",
+            name
+        ))),
+        None => Ok(()),
+    }
+}
+
+// Compared configs are layered between the main config file and the command line
+fn build_layered_config(
+    cli_args: &ArgMatches<'static>,
+    variant: Option<&str>,
+) -> Result<SimulationConfig, ConfigError> {
+    let mut builder = ConfigBuilder::new();
 
     if let Some(path) = cli_args.value_of(CONFIG_ARG) {
         builder.apply(UserSimulationConfig::from_toml_file(path)?);
     }
-    builder.apply(UserSimulationConfig::from_cli_args(&cli_args)?);
+    if let Some(path) = variant {
+        builder.apply(UserSimulationConfig::from_toml_file(path)?);
+    }
+    builder.apply(UserSimulationConfig::from_cli_args(cli_args)?);
 
     Ok(builder.build())
 }
@@ -41,9 +170,163 @@ impl ConfigBuilder {
     fn apply(&mut self, uc: UserSimulationConfig) {
         let c = &mut self.config;
         merge(&mut c.boid_count, uc.boid_count);
+        merge(&mut c.seed, uc.seed.map(Some));
+        merge(&mut c.record_input, uc.record_input.map(Some));
+        merge(&mut c.play_input, uc.play_input.map(Some));
+        merge(&mut c.initial_state, uc.initial_state.map(Some));
+        merge(&mut c.save_state, uc.save_state.map(Some));
+        merge(&mut c.rewind, uc.rewind.map(Some));
+        merge(&mut c.viewport, uc.viewport.map(Some));
+        merge(&mut c.obstacles, uc.obstacles.map(Some));
+        if uc.fish == Some(true) {
+            // Schools align more strongly than flocks, and swim through water at a steady pace
+            c.ali_weight = FISH_ALI_WEIGHT;
+            c.drag = FISH_DRAG;
+            c.cruise_speed = FISH_CRUISE_SPEED;
+        }
+        if let Some(uc_sync) = uc.sync {
+            merge(&mut c.sync, sync_role(uc_sync).map(Some));
+        }
+        if let Some(uc_stress) = uc.stress {
+            let budget = uc_stress.frame_budget.unwrap_or(DEFAULT_FRAME_BUDGET_MS);
+            c.stress_budget = Some(budget);
+        }
+        if let Some(uc_governor) = uc.governor {
+            let budget = uc_governor.tick_budget.unwrap_or(DEFAULT_TICK_BUDGET_MS);
+            c.governor_budget = Some(budget);
+        }
+        if let Some(uc_startle) = uc.startle {
+            c.startle = Some(Startle {
+                interval: uc_startle.interval.unwrap_or(DEFAULT_STARTLE_INTERVAL),
+                strength: uc_startle.strength.unwrap_or(DEFAULT_STARTLE_STRENGTH),
+                radius: uc_startle.radius.unwrap_or(DEFAULT_STARTLE_RADIUS),
+                duration: uc_startle.duration.unwrap_or(DEFAULT_STARTLE_DURATION),
+                from_boid: uc_startle.from_boid.unwrap_or(true),
+            });
+        }
+        if let Some(uc_checkpoint) = uc.checkpoint {
+            apply_checkpoint(&mut c.checkpoint, uc_checkpoint);
+        }
+        if let Some(uc_sweep) = uc.sweep {
+            apply_sweep(&mut c.sweep, uc_sweep);
+        }
+        if let Some(uc_demo) = uc.demo {
+            c.demo = Some((
+                uc_demo.interval.unwrap_or(DEFAULT_DEMO_INTERVAL),
+                uc_demo.transition.unwrap_or(DEFAULT_DEMO_TRANSITION),
+            ));
+        }
+        if let Some(uc_formation) = uc.formation {
+            merge(&mut c.formation, formation_shape(uc_formation).map(Some));
+        }
         merge(&mut c.debug, uc.debug);
+        merge(&mut c.console, uc.console);
+        merge(&mut c.teaching, uc.teaching);
+        merge(&mut c.sonify, uc.sonify);
+        merge(&mut c.timelapse, uc.timelapse.map(|t| t.max(1)));
+        merge(&mut c.compact_vertices, uc.compact_vertices);
+        merge(&mut c.threads, uc.threads.map(|t| Some(t.max(1))));
+        merge(&mut c.vsync, uc.vsync);
+        merge(&mut c.fps_limit, uc.fps_limit.map(|f| Some(f.max(1))));
         merge(&mut c.window_size, window_size(uc.window));
         merge(&mut c.boid_size, uc.boid_size);
+        if let Some(uc_sizing) = uc.sizing {
+            merge(&mut c.speed_sizes, uc_sizing.speed);
+            merge(&mut c.size_curve, uc_sizing.curve);
+            merge(&mut c.species_sizes, uc_sizing.species);
+        }
+        if let Some(uc_age) = uc.age {
+            merge(&mut c.age_ramp, uc_age.colour_ramp.map(|r| Some(r.max(1))));
+            merge(&mut c.max_age, uc_age.max.map(Some));
+        }
+        if let Some(uc_metrics) = uc.metrics {
+            merge(&mut c.metrics_address, uc_metrics.address.map(Some));
+        }
+        if let Some(uc_remote) = uc.remote {
+            merge(&mut c.remote_address, uc_remote.address.map(Some));
+        }
+        if let Some(uc_export) = uc.export {
+            merge(&mut c.trajectory_path, uc_export.trajectory.map(Some));
+            merge(&mut c.trajectory_interval, uc_export.trajectory_interval);
+            merge(&mut c.analysis_path, uc_export.analysis.map(Some));
+            merge(&mut c.analysis_interval, uc_export.analysis_interval);
+            merge(&mut c.svg_path, uc_export.svg.map(Some));
+            merge(&mut c.svg_interval, uc_export.svg_interval);
+            merge(&mut c.svg_boids, uc_export.svg_boids);
+            merge(&mut c.heatmap_path, uc_export.heatmap.map(Some));
+            merge(&mut c.heatmap_interval, uc_export.heatmap_interval);
+            merge(&mut c.video_path, uc_export.video.map(Some));
+            merge(&mut c.video_fps, uc_export.video_fps);
+        }
+        if let Some(uc_osc) = uc.osc {
+            apply_osc(&mut c.osc, uc_osc);
+        }
+        if let Some(uc_midi) = uc.midi {
+            apply_midi(&mut c.midi, uc_midi);
+        }
+        merge(&mut c.model, uc.model);
+        merge(&mut c.boundary, uc.boundary);
+        if let Some(uc_mouse) = uc.mouse {
+            merge(&mut c.mouse_force, uc_mouse.force);
+            merge(&mut c.mouse_swirl, uc_mouse.swirl);
+        }
+        if let Some(uc_social) = uc.social_force {
+            let social = &mut c.social_force;
+            merge(&mut social.desired_speed, uc_social.desired_speed);
+            merge(&mut social.relaxation, uc_social.relaxation);
+            merge(&mut social.repulsion, uc_social.repulsion);
+            merge(&mut social.range, uc_social.range);
+            merge(&mut social.radius, uc_social.radius);
+            merge(&mut social.destinations, uc_social.destinations);
+        }
+        if let Some(emitters) = uc.emitter {
+            c.emitters = emitters
+                .into_iter()
+                .map(|e| Emitter {
+                    position: e.position,
+                    rate: e.rate,
+                    direction: e.direction.unwrap_or(0.),
+                    spread: e.spread.unwrap_or(0.),
+                    speed: e.speed,
+                    lifetime: e.lifetime,
+                    max: e.max,
+                })
+                .collect();
+        }
+        if let Some(paths) = uc.path {
+            c.paths = paths
+                .into_iter()
+                .map(|p| Path {
+                    points: p.points,
+                    radius: p.radius.unwrap_or(DEFAULT_PATH_RADIUS),
+                    weight: p.weight.unwrap_or(1.),
+                    closed: p.closed.unwrap_or(false),
+                    smooth: p.smooth.unwrap_or(true),
+                })
+                .collect();
+        }
+        if let Some(wells) = uc.well {
+            c.wells = wells
+                .into_iter()
+                .map(|w| Well {
+                    position: w.position,
+                    mass: w.mass,
+                    softening: w.softening.unwrap_or(DEFAULT_SOFTENING),
+                    damping: w.damping.unwrap_or(0.),
+                })
+                .collect();
+        }
+        if let Some(keyframes) = uc.timeline.and_then(|t| t.keyframe) {
+            c.timeline = keyframes
+                .into_iter()
+                .map(|k| Keyframe {
+                    tick: k.tick,
+                    parameter: k.parameter,
+                    value: k.value,
+                    easing: k.easing.unwrap_or(Easing::Linear),
+                })
+                .collect();
+        }
         if let Some(uc_flock) = uc.flocking {
             merge(&mut c.max_speed, uc_flock.max_speed);
             merge(&mut c.max_force, uc_flock.max_force);
@@ -54,22 +337,216 @@ impl ConfigBuilder {
             merge(&mut c.sep_radius, uc_flock.sep_radius);
             merge(&mut c.ali_radius, uc_flock.ali_radius);
             merge(&mut c.coh_radius, uc_flock.coh_radius);
+            merge(&mut c.target_weight, uc_flock.target_weight);
+            merge(&mut c.obstacle_weight, uc_flock.obstacle_weight);
+            merge(&mut c.drag, uc_flock.drag);
+            merge(&mut c.cruise_speed, uc_flock.cruise_speed);
+            merge(&mut c.perch_chance, uc_flock.perch_chance);
+            merge(&mut c.perch_time, uc_flock.perch_time);
+            merge(&mut c.max_turn_rate, uc_flock.max_turn_rate);
+            merge(&mut c.verlet_skin, uc_flock.verlet_skin);
         }
     }
 
-    fn build(self) -> SimulationConfig {
+    fn build(mut self) -> SimulationConfig {
+        clamp_degenerate(&mut self.config);
         self.config
     }
 }
 
+// Settings that would crash or leave nothing to see are clamped, with a warning, rather than refused
+fn clamp_degenerate(c: &mut SimulationConfig) {
+    if c.boid_count == 0 {
+        println!("Warning: boid count is 0, the flock will be empty");
+    }
+    if let WindowSize::Dimensions((width, height)) = c.window_size {
+        if width < MIN_WINDOW_SIZE || height < MIN_WINDOW_SIZE {
+            println!(
+                "Warning: window size {}x{} is too small, using at least {}x{}",
+                width, height, MIN_WINDOW_SIZE, MIN_WINDOW_SIZE
+            );
+            c.window_size =
+                WindowSize::Dimensions((width.max(MIN_WINDOW_SIZE), height.max(MIN_WINDOW_SIZE)));
+        }
+    }
+    if let Some((_, _, width, height)) = c.viewport {
+        if !(width > 0. && height > 0.) {
+            println!("Warning: viewport has no area, showing the whole world instead");
+            c.viewport = None;
+        }
+    }
+    if c.boid_size.is_nan() || c.boid_size < MIN_BOID_SIZE {
+        println!(
+            "Warning: boid size {} is too small to see, using {}",
+            c.boid_size, MIN_BOID_SIZE
+        );
+        c.boid_size = MIN_BOID_SIZE;
+    }
+    if c.max_speed.is_nan() || c.max_speed < MIN_MAX_SPEED {
+        println!(
+            "Warning: max speed {} is too low, using {} so boids barely move",
+            c.max_speed, MIN_MAX_SPEED
+        );
+        c.max_speed = MIN_MAX_SPEED;
+    }
+    if let Some(budget) = c.stress_budget {
+        if !(budget > 0. && budget.is_finite()) {
+            println!(
+                "Warning: frame budget {} ms isn't a positive time, using {} ms",
+                budget, DEFAULT_FRAME_BUDGET_MS
+            );
+            c.stress_budget = Some(DEFAULT_FRAME_BUDGET_MS);
+        }
+    }
+    if let Some(budget) = c.governor_budget {
+        if !(budget > 0. && budget.is_finite()) {
+            println!(
+                "Warning: tick budget {} ms isn't a positive time, using {} ms",
+                budget, DEFAULT_TICK_BUDGET_MS
+            );
+            c.governor_budget = Some(DEFAULT_TICK_BUDGET_MS);
+        }
+    }
+    if c.sep_weight == 0. && c.ali_weight == 0. && c.coh_weight == 0. {
+        println!("Warning: all flocking weights are 0, boids won't react to each other");
+    }
+}
+
 fn merge<T>(existing: &mut T, candidate: Option<T>) {
     if let Some(v) = candidate {
         *existing = v;
     }
 }
 
+fn apply_osc(existing: &mut Option<OscConfig>, uc: UserOscConfig) {
+    let osc = existing.get_or_insert_with(|| OscConfig {
+        address: DEFAULT_OSC_ADDRESS.to_string(),
+        mouse_path: None,
+        mappings: vec![],
+    });
+    merge(&mut osc.address, uc.address);
+    merge(&mut osc.mouse_path, uc.mouse_path.map(Some));
+    if let Some(mappings) = uc.mapping {
+        osc.mappings = mappings
+            .into_iter()
+            .map(|m| OscMapping {
+                path: m.path,
+                parameter: m.parameter,
+                range: m.range,
+            })
+            .collect();
+    }
+}
+
+fn apply_checkpoint(existing: &mut Option<CheckpointConfig>, uc: UserCheckpointConfig) {
+    let checkpoint = existing.get_or_insert_with(|| CheckpointConfig {
+        directory: DEFAULT_CHECKPOINT_DIRECTORY.to_string(),
+        interval: DEFAULT_CHECKPOINT_INTERVAL,
+        keep: DEFAULT_CHECKPOINT_KEEP,
+        resume: false,
+    });
+    merge(&mut checkpoint.directory, uc.directory);
+    if let Some(interval) = uc.interval {
+        if interval >= 0. && interval.is_finite() {
+            checkpoint.interval = interval;
+        } else {
+            println!(
+                "Warning: checkpoint interval {} isn't a time, using {} minutes",
+                interval, DEFAULT_CHECKPOINT_INTERVAL
+            );
+            checkpoint.interval = DEFAULT_CHECKPOINT_INTERVAL;
+        }
+    }
+    merge(&mut checkpoint.keep, uc.keep.map(|k| k.max(1)));
+    merge(&mut checkpoint.resume, uc.resume);
+}
+
+fn apply_sweep(existing: &mut Option<Sweep>, uc: UserSweepConfig) {
+    let parameter = match existing.as_ref().map(|s| s.parameter).or(uc.parameter) {
+        Some(parameter) => parameter,
+        None => {
+            println!("Warning: sweep has no parameter to vary, ignoring it");
+            return;
+        }
+    };
+    let sweep = existing.get_or_insert_with(|| Sweep {
+        parameter,
+        range: None,
+        steps: DEFAULT_SWEEP_STEPS,
+        ticks: DEFAULT_EXPERIMENT_TICKS,
+        output: DEFAULT_SWEEP_OUTPUT.to_string(),
+    });
+    merge(&mut sweep.parameter, uc.parameter);
+    merge(&mut sweep.range, uc.range.map(Some));
+    merge(&mut sweep.steps, uc.steps);
+    merge(&mut sweep.ticks, uc.ticks);
+    merge(&mut sweep.output, uc.output);
+}
+
+fn apply_midi(existing: &mut Option<MidiConfig>, uc: UserMidiConfig) {
+    let midi = existing.get_or_insert_with(|| MidiConfig {
+        port: None,
+        mappings: vec![],
+    });
+    merge(&mut midi.port, uc.port.map(Some));
+    if let Some(mappings) = uc.mapping {
+        midi.mappings = mappings
+            .into_iter()
+            .map(|m| MidiMapping {
+                controller: m.cc,
+                channel: m.channel,
+                parameter: m.parameter,
+                range: m.range,
+            })
+            .collect();
+    }
+}
+
+fn sync_role(sync_conf: UserSyncConfig) -> Option<SyncRole> {
+    match sync_conf {
+        UserSyncConfig {
+            follow: Some(address),
+            ..
+        } => Some(SyncRole::Follower { address }),
+        UserSyncConfig {
+            serve: Some(address),
+            followers,
+            ..
+        } => Some(SyncRole::Master {
+            address,
+            followers: followers.unwrap_or(1),
+        }),
+        _ => None,
+    }
+}
+
+fn formation_shape(formation_conf: UserFormationConfig) -> Option<FormationShape> {
+    match formation_conf {
+        UserFormationConfig {
+            image: Some(path), ..
+        } => Some(FormationShape::Image(path)),
+        UserFormationConfig {
+            text: Some(text), ..
+        } => Some(FormationShape::Text(text)),
+        _ => None,
+    }
+}
+
 fn window_size(window_conf: Option<UserWindowConfig>) -> Option<WindowSize> {
     match window_conf {
+        Some(UserWindowConfig {
+            desktop: Some(true),
+            ..
+        }) => Some(WindowSize::Desktop),
+        Some(UserWindowConfig {
+            borderless: Some(true),
+            ..
+        }) => Some(WindowSize::Borderless),
+        Some(UserWindowConfig {
+            span: Some(true),
+            monitors,
+            ..
+        }) => Some(WindowSize::Span(monitors)),
         Some(UserWindowConfig {
             fullscreen: Some(true),
             ..
@@ -108,6 +585,32 @@ fn parse_cli_args() -> Result<ArgMatches<'static>, clap::Error> {
                 .help("Display fullscreen (overrides size argument)")
                 .conflicts_with("size"),
         )
+        .arg(
+            Arg::with_name(BORDERLESS_ARG)
+                .long("borderless")
+                .help("Covers the primary monitor with a borderless window instead of fullscreen")
+                .conflicts_with_all(&["size", "fullscreen"]),
+        )
+        .arg(
+            Arg::with_name(DESKTOP_ARG)
+                .long("desktop")
+                .help("Flies the boids over the desktop in a transparent, click-through window")
+                .conflicts_with_all(&["size", "fullscreen", "borderless"]),
+        )
+        .arg(
+            Arg::with_name(SPAN_ARG)
+                .long("span")
+                .help("Covers every monitor with one borderless window (overrides size argument)")
+                .conflicts_with_all(&["size", "fullscreen", "borderless", "desktop"]),
+        )
+        .arg(
+            Arg::with_name(MONITORS_ARG)
+                .long("monitors")
+                .value_name("INDEX")
+                .use_delimiter(true)
+                .requires(SPAN_ARG)
+                .help("Only spans these monitors, numbered as listed at startup, e.g. 0,2"),
+        )
         .arg(
             Arg::with_name(BOID_COUNT_ARG)
                 .short("b")
@@ -119,7 +622,301 @@ fn parse_cli_args() -> Result<ArgMatches<'static>, clap::Error> {
             Arg::with_name(DEBUG_ARG)
                 .short("d")
                 .long("debug")
-                .help("print opengl debug information"),
+                .help("print opengl debug information and driver messages"),
+        )
+        .arg(
+            Arg::with_name(CONSOLE_ARG)
+                .long("console")
+                .help("Reads live commands from the terminal, type `help` to list them"),
+        )
+        .arg(
+            Arg::with_name(TEACHING_ARG)
+                .long("teaching")
+                .help("Introduces the flocking rules one at a time, press space to step"),
+        )
+        .arg(
+            Arg::with_name(FORM_IMAGE_ARG)
+                .long("form-image")
+                .value_name("FILE")
+                .help("Lets the flock form the filled pixels of a PNG image, press T to toggle"),
+        )
+        .arg(
+            Arg::with_name(FORM_TEXT_ARG)
+                .long("form-text")
+                .value_name("TEXT")
+                .conflicts_with(FORM_IMAGE_ARG)
+                .help("Lets the flock spell out TEXT, press T to toggle"),
+        )
+        .arg(
+            Arg::with_name(OBSTACLES_ARG)
+                .long("obstacles")
+                .value_name("FILE")
+                .help("Stretches a PNG over the world as obstacles, where dark pixels are walls"),
+        )
+        .arg(
+            Arg::with_name(FISH_ARG)
+                .long("fish")
+                .help("Swims like a school of fish, with drag and burst and coast swimming"),
+        )
+        .arg(
+            Arg::with_name(THREADS_ARG)
+                .long("threads")
+                .value_name("COUNT")
+                .help("Sets how many threads update the flock (default one per core)"),
+        )
+        .arg(
+            Arg::with_name(MODEL_ARG)
+                .long("model")
+                .value_name("MODEL")
+                .possible_values(&["boids", "social_force"])
+                .help("Sets how boids move, flocking or as a crowd of pedestrians"),
+        )
+        .arg(
+            Arg::with_name(BOUNDARY_ARG)
+                .long("boundary")
+                .value_name("BOUNDARY")
+                .possible_values(&["wrap", "reflect", "slide", "respawn"])
+                .help("Sets what happens to boids that fly off the edge of the world"),
+        )
+        .arg(
+            Arg::with_name(NO_VSYNC_ARG)
+                .long("no-vsync")
+                .help("Disables vsync, limiting the frame rate in software instead"),
+        )
+        .arg(
+            Arg::with_name(FPS_LIMIT_ARG)
+                .long("fps-limit")
+                .value_name("FPS")
+                .help("Limits the frame rate in software (default 60 without vsync)"),
+        )
+        .arg(
+            Arg::with_name(TIMELAPSE_ARG)
+                .long("timelapse")
+                .value_name("TICKS")
+                .help("Only draws (and records) every this many ticks, simulating at full speed"),
+        )
+        .arg(
+            Arg::with_name(SONIFY_ARG)
+                .long("sonify")
+                .help("Plays the state of the flock as sound (needs the `audio` feature)"),
+        )
+        .arg(
+            Arg::with_name(METRICS_ARG)
+                .long("metrics")
+                .value_name("ADDRESS")
+                .help("Serves prometheus metrics on ADDRESS (needs the `metrics` feature)"),
+        )
+        .arg(
+            Arg::with_name(REMOTE_ARG)
+                .long("remote")
+                .value_name("ADDRESS")
+                .help("Accepts JSON control commands on ADDRESS (needs the `remote` feature)"),
+        )
+        .arg(
+            Arg::with_name(OSC_ARG)
+                .long("osc")
+                .value_name("ADDRESS")
+                .help("Listens for OSC control messages on ADDRESS"),
+        )
+        .arg(
+            Arg::with_name(SEED_ARG)
+                .long("seed")
+                .takes_value(true)
+                .help("Seeds the simulation so runs can be reproduced"),
+        )
+        .arg(
+            Arg::with_name(RECORD_INPUT_ARG)
+                .long("record-input")
+                .value_name("FILE")
+                .help("Records user input to FILE so it can be played back"),
+        )
+        .arg(
+            Arg::with_name(PLAY_INPUT_ARG)
+                .long("play-input")
+                .value_name("FILE")
+                .help("Plays back input recorded with --record-input")
+                .conflicts_with(RECORD_INPUT_ARG),
+        )
+        .arg(
+            Arg::with_name(VERIFY_ARG)
+                .long("verify")
+                .value_name("FILE")
+                .help("Replays a recording headlessly and reports the first tick it diverges on"),
+        )
+        .arg(
+            Arg::with_name(EXPORT_TRAJECTORY_ARG)
+                .long("export-trajectory")
+                .value_name("FILE")
+                .help("Writes boid positions and velocities to a CSV file"),
+        )
+        .arg(
+            Arg::with_name(EXPORT_INTERVAL_ARG)
+                .long("export-interval")
+                .value_name("TICKS")
+                .help("Sets how many ticks pass between trajectory samples"),
+        )
+        .arg(
+            Arg::with_name(ANALYSIS_ARG)
+                .long("analysis")
+                .value_name("FILE")
+                .help("Writes polarization and velocity correlations to FILE at the end of a run"),
+        )
+        .arg(
+            Arg::with_name(EXPORT_SVG_ARG)
+                .long("export-svg")
+                .value_name("FILE")
+                .help("Draws boid trails to an SVG file at the end of a run"),
+        )
+        .arg(
+            Arg::with_name(EXPORT_HEATMAP_ARG)
+                .long("export-heatmap")
+                .value_name("FILE")
+                .help("Writes a PNG of where boids went over the run at the end of it"),
+        )
+        .arg(
+            Arg::with_name(RECORD_VIDEO_ARG)
+                .long("record-video")
+                .value_name("FILE")
+                .help("Records a video of the simulation with ffmpeg"),
+        )
+        .arg(
+            Arg::with_name(VIDEO_FPS_ARG)
+                .long("video-fps")
+                .value_name("FPS")
+                .help("Sets the frame rate of recorded videos"),
+        )
+        .arg(
+            Arg::with_name(INITIAL_STATE_ARG)
+                .long("initial-state")
+                .value_name("FILE")
+                .help("Starts from boids in a saved state or CSV file instead of random ones"),
+        )
+        .arg(
+            Arg::with_name(SAVE_STATE_ARG)
+                .long("save-state")
+                .value_name("FILE")
+                .help("Saves the state of the flock to FILE on exit"),
+        )
+        .arg(
+            Arg::with_name(CHECKPOINT_ARG)
+                .long("checkpoint")
+                .value_name("DIRECTORY")
+                .help("Writes a compressed checkpoint of the flock to DIRECTORY every few minutes"),
+        )
+        .arg(
+            Arg::with_name(RESUME_ARG)
+                .long("resume")
+                .help("Starts from the latest checkpoint, if there is one"),
+        )
+        .arg(
+            Arg::with_name(REWIND_ARG)
+                .long("rewind")
+                .value_name("SECONDS")
+                .help("Keeps this much history to step back through with the , and . keys"),
+        )
+        .arg(
+            Arg::with_name(VIEWPORT_ARG)
+                .long("viewport")
+                .value_names(&["x", "y", "width", "height"])
+                .use_delimiter(true)
+                .help("Only draws this region of the simulation"),
+        )
+        .arg(
+            Arg::with_name(SYNC_SERVE_ARG)
+                .long("sync-serve")
+                .value_name("ADDRESS")
+                .help("Runs as a sync master, broadcasting input to followers"),
+        )
+        .arg(
+            Arg::with_name(SYNC_FOLLOWERS_ARG)
+                .long("sync-followers")
+                .value_name("COUNT")
+                .requires(SYNC_SERVE_ARG)
+                .help("Sets how many followers the sync master waits for"),
+        )
+        .arg(
+            Arg::with_name(SYNC_FOLLOW_ARG)
+                .long("sync-follow")
+                .value_name("ADDRESS")
+                .conflicts_with(SYNC_SERVE_ARG)
+                .help("Follows the sync master at ADDRESS"),
+        )
+        .arg(
+            Arg::with_name(COMPARE_ARG)
+                .long("compare")
+                .value_name("FILE")
+                .min_values(2)
+                .help("Runs each config headlessly from the same seed and compares the flocks"),
+        )
+        .arg(
+            Arg::with_name(TICKS_ARG)
+                .long("ticks")
+                .takes_value(true)
+                .help("Sets how many ticks each compared config or swept value runs for"),
+        )
+        .arg(
+            Arg::with_name(SWEEP_ARG)
+                .long("sweep")
+                .value_name("PARAMETER")
+                .help("Runs headlessly over a range of values of a parameter, writing a CSV"),
+        )
+        .arg(
+            Arg::with_name(SWEEP_RANGE_ARG)
+                .long("sweep-range")
+                .value_names(&["from", "to"])
+                .use_delimiter(true)
+                .requires(SWEEP_ARG)
+                .help("Sets the values swept over (default zero to twice the configured value)"),
+        )
+        .arg(
+            Arg::with_name(SWEEP_STEPS_ARG)
+                .long("sweep-steps")
+                .value_name("STEPS")
+                .requires(SWEEP_ARG)
+                .help("Sets how many steps the range is split into"),
+        )
+        .arg(
+            Arg::with_name(SWEEP_OUTPUT_ARG)
+                .long("sweep-output")
+                .value_name("FILE")
+                .requires(SWEEP_ARG)
+                .help("Sets the CSV file sweep results are written to (default sweep.csv)"),
+        )
+        .arg(
+            Arg::with_name(STRESS_ARG)
+                .long("stress")
+                .help("Adds boids until frames go over budget and reports the most sustained"),
+        )
+        .arg(
+            Arg::with_name(FRAME_BUDGET_ARG)
+                .long("frame-budget")
+                .value_name("MS")
+                .requires(STRESS_ARG)
+                .help("Sets the frame time the stress test must stay under (default 60 fps)"),
+        )
+        .arg(
+            Arg::with_name(GOVERNOR_ARG)
+                .long("governor")
+                .help("Lowers simulation quality while ticks take too long, to keep frames smooth"),
+        )
+        .arg(
+            Arg::with_name(TICK_BUDGET_ARG)
+                .long("tick-budget")
+                .value_name("MS")
+                .requires(GOVERNOR_ARG)
+                .help("Sets the tick time the governor aims to stay under (default 8 ms)"),
+        )
+        .arg(
+            Arg::with_name(DEMO_ARG)
+                .long("demo")
+                .help("Cycles through built-in presets for unattended displays"),
+        )
+        .arg(
+            Arg::with_name(DEMO_INTERVAL_ARG)
+                .long("demo-interval")
+                .value_name("SECONDS")
+                .requires(DEMO_ARG)
+                .help("Sets how long the demo shows each preset for"),
         )
         .get_matches_safe();
 
@@ -136,6 +933,7 @@ pub enum ConfigError {
     Io(io::Error),
     Clap(clap::Error),
     Toml(toml::de::Error),
+    Conflict(String),
 }
 
 impl From<io::Error> for ConfigError {
@@ -162,6 +960,7 @@ impl fmt::Display for ConfigError {
             ConfigError::Io(ref err) => write!(f, "Could not read config: {}", err),
             ConfigError::Clap(ref err) => write!(f, "{}", err),
             ConfigError::Toml(ref err) => write!(f, "Could not parse toml: {}", err),
+            ConfigError::Conflict(ref msg) => write!(f, "Invalid config: {}", msg),
         }
     }
 }
@@ -176,16 +975,238 @@ impl ConfigError {
 #[derive(Deserialize, Default)]
 struct UserSimulationConfig {
     boid_count: Option<u32>,
+    seed: Option<u64>,
+    record_input: Option<String>,
+    play_input: Option<String>,
+    initial_state: Option<String>,
+    save_state: Option<String>,
+    rewind: Option<f32>,
+    viewport: Option<(f32, f32, f32, f32)>,
+    obstacles: Option<String>,
+    sync: Option<UserSyncConfig>,
     debug: Option<bool>,
+    console: Option<bool>,
+    teaching: Option<bool>,
+    sonify: Option<bool>,
+    timelapse: Option<u32>,
+    compact_vertices: Option<bool>,
+    threads: Option<usize>,
+    vsync: Option<bool>,
+    fps_limit: Option<u32>,
+    fish: Option<bool>,
     window: Option<UserWindowConfig>,
     flocking: Option<UserFlockingConfig>,
     boid_size: Option<f32>,
+    metrics: Option<UserMetricsConfig>,
+    remote: Option<UserRemoteConfig>,
+    osc: Option<UserOscConfig>,
+    midi: Option<UserMidiConfig>,
+    export: Option<UserExportConfig>,
+    timeline: Option<UserTimelineConfig>,
+    emitter: Option<Vec<UserEmitterConfig>>,
+    well: Option<Vec<UserWellConfig>>,
+    path: Option<Vec<UserPathConfig>>,
+    model: Option<Model>,
+    boundary: Option<Boundary>,
+    social_force: Option<UserSocialForceConfig>,
+    mouse: Option<UserMouseConfig>,
+    age: Option<UserAgeConfig>,
+    startle: Option<UserStartleConfig>,
+    checkpoint: Option<UserCheckpointConfig>,
+    sweep: Option<UserSweepConfig>,
+    stress: Option<UserStressConfig>,
+    governor: Option<UserGovernorConfig>,
+    demo: Option<UserDemoConfig>,
+    formation: Option<UserFormationConfig>,
+    sizing: Option<UserSizingConfig>,
 }
 
-#[derive(Copy, Clone, Deserialize, Default)]
+#[derive(Clone, Deserialize, Default)]
 struct UserWindowConfig {
     size: Option<(u32, u32)>,
     fullscreen: Option<bool>,
+    borderless: Option<bool>,
+    desktop: Option<bool>,
+    span: Option<bool>,
+    monitors: Option<Vec<usize>>,
+}
+
+#[derive(Clone, Deserialize, Default)]
+struct UserMetricsConfig {
+    address: Option<String>,
+}
+
+#[derive(Clone, Deserialize, Default)]
+struct UserRemoteConfig {
+    address: Option<String>,
+}
+
+#[derive(Clone, Deserialize, Default)]
+struct UserSyncConfig {
+    serve: Option<String>,
+    followers: Option<usize>,
+    follow: Option<String>,
+}
+
+#[derive(Clone, Deserialize, Default)]
+struct UserExportConfig {
+    trajectory: Option<String>,
+    trajectory_interval: Option<u64>,
+    analysis: Option<String>,
+    analysis_interval: Option<u64>,
+    svg: Option<String>,
+    svg_interval: Option<u64>,
+    svg_boids: Option<usize>,
+    heatmap: Option<String>,
+    heatmap_interval: Option<u64>,
+    video: Option<String>,
+    video_fps: Option<u32>,
+}
+
+#[derive(Clone, Deserialize, Default)]
+struct UserOscConfig {
+    address: Option<String>,
+    mouse_path: Option<String>,
+    mapping: Option<Vec<UserOscMapping>>,
+}
+
+#[derive(Clone, Deserialize)]
+struct UserOscMapping {
+    path: String,
+    parameter: Parameter,
+    range: Option<(f32, f32)>,
+}
+
+#[derive(Clone, Deserialize, Default)]
+struct UserMidiConfig {
+    port: Option<String>,
+    mapping: Option<Vec<UserMidiMapping>>,
+}
+
+#[derive(Clone, Deserialize)]
+struct UserMidiMapping {
+    cc: u8,
+    channel: Option<u8>,
+    parameter: Parameter,
+    range: (f32, f32),
+}
+
+#[derive(Clone, Deserialize, Default)]
+struct UserSizingConfig {
+    speed: Option<(f32, f32)>,
+    curve: Option<f32>,
+    species: Option<Vec<f32>>,
+}
+
+#[derive(Clone, Deserialize, Default)]
+struct UserFormationConfig {
+    image: Option<String>,
+    text: Option<String>,
+}
+
+#[derive(Clone, Deserialize, Default)]
+struct UserDemoConfig {
+    interval: Option<f32>,
+    transition: Option<f32>,
+}
+
+#[derive(Clone, Deserialize, Default)]
+struct UserCheckpointConfig {
+    directory: Option<String>,
+    interval: Option<f32>,
+    keep: Option<u32>,
+    resume: Option<bool>,
+}
+
+#[derive(Clone, Deserialize, Default)]
+struct UserSweepConfig {
+    parameter: Option<Parameter>,
+    range: Option<(f32, f32)>,
+    steps: Option<u32>,
+    ticks: Option<u64>,
+    output: Option<String>,
+}
+
+#[derive(Clone, Deserialize, Default)]
+struct UserStressConfig {
+    frame_budget: Option<f32>,
+}
+
+#[derive(Clone, Deserialize, Default)]
+struct UserGovernorConfig {
+    tick_budget: Option<f32>,
+}
+
+#[derive(Clone, Deserialize, Default)]
+struct UserTimelineConfig {
+    keyframe: Option<Vec<UserKeyframe>>,
+}
+
+#[derive(Clone, Deserialize)]
+struct UserKeyframe {
+    tick: u64,
+    parameter: Parameter,
+    value: f32,
+    easing: Option<Easing>,
+}
+
+#[derive(Clone, Deserialize, Default)]
+struct UserStartleConfig {
+    interval: Option<f32>,
+    strength: Option<f32>,
+    radius: Option<f32>,
+    duration: Option<u32>,
+    from_boid: Option<bool>,
+}
+
+#[derive(Clone, Deserialize, Default)]
+struct UserAgeConfig {
+    colour_ramp: Option<u32>,
+    max: Option<u32>,
+}
+
+#[derive(Clone, Deserialize, Default)]
+struct UserMouseConfig {
+    force: Option<MouseForce>,
+    swirl: Option<f32>,
+}
+
+#[derive(Clone, Deserialize, Default)]
+struct UserSocialForceConfig {
+    desired_speed: Option<f32>,
+    relaxation: Option<f32>,
+    repulsion: Option<f32>,
+    range: Option<f32>,
+    radius: Option<f32>,
+    destinations: Option<Vec<(f32, f32)>>,
+}
+
+#[derive(Clone, Deserialize)]
+struct UserEmitterConfig {
+    position: (f32, f32),
+    rate: f32,
+    direction: Option<f32>,
+    spread: Option<f32>,
+    speed: Option<f32>,
+    lifetime: Option<u32>,
+    max: Option<u32>,
+}
+
+#[derive(Clone, Deserialize)]
+struct UserPathConfig {
+    points: Vec<(f32, f32)>,
+    radius: Option<f32>,
+    weight: Option<f32>,
+    closed: Option<bool>,
+    smooth: Option<bool>,
+}
+
+#[derive(Clone, Deserialize)]
+struct UserWellConfig {
+    position: (f32, f32),
+    mass: f32,
+    softening: Option<f32>,
+    damping: Option<f32>,
 }
 
 //TODO: Use rename annoations to make these nicer for the user
@@ -200,6 +1221,14 @@ struct UserFlockingConfig {
     sep_radius: Option<f32>,
     ali_radius: Option<f32>,
     coh_radius: Option<f32>,
+    target_weight: Option<f32>,
+    obstacle_weight: Option<f32>,
+    drag: Option<f32>,
+    cruise_speed: Option<f32>,
+    perch_chance: Option<f32>,
+    perch_time: Option<f32>,
+    max_turn_rate: Option<f32>,
+    verlet_skin: Option<f32>,
 }
 
 impl UserSimulationConfig {
@@ -218,20 +1247,289 @@ impl UserSimulationConfig {
             user_conf.boid_count = Some(value_t!(args, BOID_COUNT_ARG, u32)?);
         };
 
+        if args.is_present(SEED_ARG) {
+            user_conf.seed = Some(value_t!(args, SEED_ARG, u64)?);
+        };
+
+        user_conf.record_input = args.value_of(RECORD_INPUT_ARG).map(String::from);
+        user_conf.play_input = args.value_of(PLAY_INPUT_ARG).map(String::from);
+        user_conf.initial_state = args.value_of(INITIAL_STATE_ARG).map(String::from);
+        user_conf.save_state = args.value_of(SAVE_STATE_ARG).map(String::from);
+        user_conf.obstacles = args.value_of(OBSTACLES_ARG).map(String::from);
+
+        let mut export_conf = UserExportConfig {
+            trajectory: args.value_of(EXPORT_TRAJECTORY_ARG).map(String::from),
+            analysis: args.value_of(ANALYSIS_ARG).map(String::from),
+            svg: args.value_of(EXPORT_SVG_ARG).map(String::from),
+            heatmap: args.value_of(EXPORT_HEATMAP_ARG).map(String::from),
+            video: args.value_of(RECORD_VIDEO_ARG).map(String::from),
+            ..UserExportConfig::default()
+        };
+        if args.is_present(EXPORT_INTERVAL_ARG) {
+            export_conf.trajectory_interval = Some(value_t!(args, EXPORT_INTERVAL_ARG, u64)?);
+        };
+        if args.is_present(VIDEO_FPS_ARG) {
+            export_conf.video_fps = Some(value_t!(args, VIDEO_FPS_ARG, u32)?);
+        };
+        user_conf.export = Some(export_conf);
+
+        if args.is_present(VIEWPORT_ARG) {
+            let view = values_t!(args, VIEWPORT_ARG, f32)?;
+            user_conf.viewport = Some((view[0], view[1], view[2], view[3]));
+        };
+
+        if args.is_present(SYNC_SERVE_ARG) || args.is_present(SYNC_FOLLOW_ARG) {
+            let mut sync_conf = UserSyncConfig {
+                serve: args.value_of(SYNC_SERVE_ARG).map(String::from),
+                follow: args.value_of(SYNC_FOLLOW_ARG).map(String::from),
+                ..UserSyncConfig::default()
+            };
+            if args.is_present(SYNC_FOLLOWERS_ARG) {
+                sync_conf.followers = Some(value_t!(args, SYNC_FOLLOWERS_ARG, usize)?);
+            }
+            user_conf.sync = Some(sync_conf);
+        };
+
         if args.is_present(DEBUG_ARG) {
             user_conf.debug = Some(true);
         };
 
+        if args.is_present(DEMO_ARG) {
+            let mut demo_conf = UserDemoConfig::default();
+            if args.is_present(DEMO_INTERVAL_ARG) {
+                demo_conf.interval = Some(value_t!(args, DEMO_INTERVAL_ARG, f32)?);
+            }
+            user_conf.demo = Some(demo_conf);
+        };
+
+        if args.is_present(REWIND_ARG) {
+            user_conf.rewind = Some(value_t!(args, REWIND_ARG, f32)?);
+        };
+
+        if args.is_present(CHECKPOINT_ARG) || args.is_present(RESUME_ARG) {
+            user_conf.checkpoint = Some(UserCheckpointConfig {
+                directory: args.value_of(CHECKPOINT_ARG).map(String::from),
+                resume: args.is_present(RESUME_ARG).then_some(true),
+                ..UserCheckpointConfig::default()
+            });
+        };
+
+        if args.is_present(SWEEP_ARG) {
+            let mut sweep_conf = UserSweepConfig {
+                parameter: Some(value_t!(args, SWEEP_ARG, Parameter)?),
+                output: args.value_of(SWEEP_OUTPUT_ARG).map(String::from),
+                ..UserSweepConfig::default()
+            };
+            if args.is_present(SWEEP_RANGE_ARG) {
+                let range = values_t!(args, SWEEP_RANGE_ARG, f32)?;
+                sweep_conf.range = Some((range[0], range[1]));
+            }
+            if args.is_present(SWEEP_STEPS_ARG) {
+                sweep_conf.steps = Some(value_t!(args, SWEEP_STEPS_ARG, u32)?);
+            }
+            if args.is_present(TICKS_ARG) {
+                sweep_conf.ticks = Some(value_t!(args, TICKS_ARG, u64)?);
+            }
+            user_conf.sweep = Some(sweep_conf);
+        };
+
+        if args.is_present(STRESS_ARG) {
+            let mut stress_conf = UserStressConfig::default();
+            if args.is_present(FRAME_BUDGET_ARG) {
+                stress_conf.frame_budget = Some(value_t!(args, FRAME_BUDGET_ARG, f32)?);
+            }
+            user_conf.stress = Some(stress_conf);
+        };
+
+        if args.is_present(GOVERNOR_ARG) {
+            let mut governor_conf = UserGovernorConfig::default();
+            if args.is_present(TICK_BUDGET_ARG) {
+                governor_conf.tick_budget = Some(value_t!(args, TICK_BUDGET_ARG, f32)?);
+            }
+            user_conf.governor = Some(governor_conf);
+        };
+
+        if args.is_present(FORM_IMAGE_ARG) || args.is_present(FORM_TEXT_ARG) {
+            user_conf.formation = Some(UserFormationConfig {
+                image: args.value_of(FORM_IMAGE_ARG).map(String::from),
+                text: args.value_of(FORM_TEXT_ARG).map(String::from),
+            });
+        };
+
+        if args.is_present(FISH_ARG) {
+            user_conf.fish = Some(true);
+        };
+
+        if args.is_present(THREADS_ARG) {
+            user_conf.threads = Some(value_t!(args, THREADS_ARG, usize)?);
+        };
+
+        if args.is_present(MODEL_ARG) {
+            user_conf.model = Some(value_t!(args, MODEL_ARG, Model)?);
+        };
+
+        if args.is_present(BOUNDARY_ARG) {
+            user_conf.boundary = Some(value_t!(args, BOUNDARY_ARG, Boundary)?);
+        };
+
+        if args.is_present(NO_VSYNC_ARG) {
+            user_conf.vsync = Some(false);
+        };
+
+        if args.is_present(FPS_LIMIT_ARG) {
+            user_conf.fps_limit = Some(value_t!(args, FPS_LIMIT_ARG, u32)?);
+        };
+
+        if args.is_present(TIMELAPSE_ARG) {
+            user_conf.timelapse = Some(value_t!(args, TIMELAPSE_ARG, u32)?);
+        };
+
+        if args.is_present(SONIFY_ARG) {
+            user_conf.sonify = Some(true);
+        };
+
+        if args.is_present(TEACHING_ARG) {
+            user_conf.teaching = Some(true);
+        };
+
+        if args.is_present(CONSOLE_ARG) {
+            user_conf.console = Some(true);
+        };
+
         if args.is_present(FULLSCREEN_ARG) {
             window_conf.fullscreen = Some(true);
         };
 
+        if args.is_present(BORDERLESS_ARG) {
+            window_conf.borderless = Some(true);
+        };
+
+        if args.is_present(DESKTOP_ARG) {
+            window_conf.desktop = Some(true);
+        };
+
+        if args.is_present(SPAN_ARG) {
+            window_conf.span = Some(true);
+        };
+
+        if args.is_present(MONITORS_ARG) {
+            window_conf.monitors = Some(values_t!(args, MONITORS_ARG, usize)?);
+        };
+
         if args.is_present(WINDOW_SIZE_ARG) {
             let size = values_t!(args, WINDOW_SIZE_ARG, u32)?;
             window_conf.size = Some((size[0], size[1]));
         };
 
+        if let Some(address) = args.value_of(METRICS_ARG) {
+            user_conf.metrics = Some(UserMetricsConfig {
+                address: Some(address.to_string()),
+            });
+        };
+
+        if let Some(address) = args.value_of(REMOTE_ARG) {
+            user_conf.remote = Some(UserRemoteConfig {
+                address: Some(address.to_string()),
+            });
+        };
+
+        if let Some(address) = args.value_of(OSC_ARG) {
+            user_conf.osc = Some(UserOscConfig {
+                address: Some(address.to_string()),
+                ..UserOscConfig::default()
+            });
+        };
+
         user_conf.window = Some(window_conf);
         Ok(user_conf)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn clamped(c: SimulationConfig) -> SimulationConfig {
+        let mut c = c;
+        clamp_degenerate(&mut c);
+        c
+    }
+
+    #[test]
+    fn bad_tick_budgets_use_the_default() {
+        for &budget in &[-1., 0., f32::NAN, f32::INFINITY] {
+            let c = clamped(SimulationConfig {
+                governor_budget: Some(budget),
+                ..SimulationConfig::default()
+            });
+            assert_eq!(c.governor_budget, Some(DEFAULT_TICK_BUDGET_MS));
+        }
+        let c = clamped(SimulationConfig {
+            governor_budget: Some(2.5),
+            ..SimulationConfig::default()
+        });
+        assert_eq!(c.governor_budget, Some(2.5));
+    }
+
+    #[test]
+    fn sync_refuses_unsynchronised_changes() {
+        let follower = || {
+            Some(SyncRole::Follower {
+                address: "localhost:7878".to_string(),
+            })
+        };
+        assert!(check_sync(&SimulationConfig {
+            sync: follower(),
+            ..SimulationConfig::default()
+        })
+        .is_ok());
+        let configs = [
+            SimulationConfig {
+                stress_budget: Some(10.),
+                ..SimulationConfig::default()
+            },
+            SimulationConfig {
+                demo: Some((30., 5.)),
+                ..SimulationConfig::default()
+            },
+            SimulationConfig {
+                governor_budget: Some(8.),
+                ..SimulationConfig::default()
+            },
+        ];
+        for config in configs {
+            assert!(check_sync(&config).is_ok());
+            let synced = SimulationConfig {
+                sync: follower(),
+                ..config
+            };
+            assert!(matches!(check_sync(&synced), Err(ConfigError::Conflict(_))));
+        }
+    }
+
+    #[test]
+    fn bad_checkpoint_intervals_use_the_default() {
+        for &interval in &[-1., f32::NAN, f32::INFINITY] {
+            let mut checkpoint = None;
+            apply_checkpoint(
+                &mut checkpoint,
+                UserCheckpointConfig {
+                    interval: Some(interval),
+                    ..UserCheckpointConfig::default()
+                },
+            );
+            assert_eq!(checkpoint.unwrap().interval, DEFAULT_CHECKPOINT_INTERVAL);
+        }
+    }
+
+    #[test]
+    fn bad_frame_budgets_use_the_default() {
+        for &budget in &[-1., 0., f32::NAN, f32::NEG_INFINITY] {
+            let c = clamped(SimulationConfig {
+                stress_budget: Some(budget),
+                ..SimulationConfig::default()
+            });
+            assert_eq!(c.stress_budget, Some(DEFAULT_FRAME_BUDGET_MS));
+        }
+    }
+}
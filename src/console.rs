@@ -0,0 +1,87 @@
+// Interactive console, reading one command per line from stdin, e.g.
+//
+//   set sep_weight 2.0
+//   spawn 500
+//   save state.bin
+
+use std::{
+    io::{self, prelude::*},
+    sync::mpsc::Sender,
+    thread,
+};
+
+use crate::command::Command;
+
+const HELP: &str = "Commands:
+  set PARAMETER VALUE   e.g. set sep_weight 2.0
+  spawn COUNT [X Y]     spawn boids, at X,Y if given
+  mouse X Y             move the mouse point (fractions of the window)
+  save FILE             save the state of the flock
+  pause | resume | randomise | centralise | zeroise | form | disperse";
+
+pub fn start(commands: Sender<Command>) {
+    thread::spawn(move || {
+        let stdin = io::stdin();
+        for line in stdin.lock().lines() {
+            let line = match line {
+                Ok(line) => line,
+                Err(_) => break,
+            };
+            let words: Vec<&str> = line.split_whitespace().collect();
+            match parse_command(&words) {
+                Ok(Some(command)) => {
+                    if commands.send(command).is_err() {
+                        // Simulation has shut down
+                        break;
+                    }
+                }
+                Ok(None) => (),
+                Err(err) => println!("{}", err),
+            }
+        }
+    });
+    println!("Reading commands from the console, type `help` to list them");
+}
+
+fn parse_command(words: &[&str]) -> Result<Option<Command>, String> {
+    let command = match *words {
+        [] => return Ok(None),
+        ["help"] => {
+            println!("{}", HELP);
+            return Ok(None);
+        }
+        ["set", parameter, value] => Command::Set {
+            parameter: parameter.parse()?,
+            value: parse_number(value)?,
+        },
+        ["spawn", count] => Command::Spawn {
+            count: parse_number(count)?,
+            position: None,
+        },
+        ["spawn", count, x, y] => Command::Spawn {
+            count: parse_number(count)?,
+            position: Some((parse_number(x)?, parse_number(y)?)),
+        },
+        ["mouse", x, y] => Command::Mouse {
+            x: parse_number(x)?,
+            y: parse_number(y)?,
+        },
+        ["save", path] => Command::Save {
+            path: path.to_string(),
+        },
+        ["pause"] => Command::Pause,
+        ["resume"] => Command::Resume,
+        ["randomise"] => Command::Randomise,
+        ["centralise"] => Command::Centralise,
+        ["zeroise"] => Command::Zeroise,
+        ["form"] => Command::Form,
+        ["disperse"] => Command::Disperse,
+        _ => return Err(format!("Unknown command '{}', try `help`", words.join(" "))),
+    };
+    Ok(Some(command))
+}
+
+fn parse_number<T: std::str::FromStr>(word: &str) -> Result<T, String> {
+    word.parse()
+        .map_err(|_| format!("'{}' is not a valid number", word))
+}
@@ -0,0 +1,88 @@
+// Demo mode for unattended displays, cycling through built-in presets every `interval` seconds
+// and easing the flocking parameters from one preset to the next over `transition` seconds.
+// Time is counted in ticks rather than read from the clock, so recordings and replays of a demo
+// see the same parameters on the same ticks.
+
+use crate::system::{FlockingSystem, Parameter};
+use crate::timeline::Easing;
+
+const TICKS_PER_SECOND: f64 = 60.;
+
+const PARAMETERS: [Parameter; 8] = [
+    Parameter::MaxSpeed,
+    Parameter::MaxForce,
+    Parameter::SepWeight,
+    Parameter::AliWeight,
+    Parameter::CohWeight,
+    Parameter::SepRadius,
+    Parameter::AliRadius,
+    Parameter::CohRadius,
+];
+
+struct Preset {
+    name: &'static str,
+    // In the same order as PARAMETERS
+    values: [f32; 8],
+}
+
+const PRESETS: [Preset; 5] = [
+    Preset {
+        name: "murmuration",
+        values: [2.5, 0.4, 1.5, 1.0, 1.0, 6., 11.5, 11.5],
+    },
+    Preset {
+        name: "schools",
+        values: [2.0, 0.3, 1.2, 2.5, 1.5, 5., 14., 14.],
+    },
+    Preset {
+        name: "swarm",
+        values: [3.5, 0.6, 1.0, 0.2, 1.2, 5., 8., 16.],
+    },
+    Preset {
+        name: "clusters",
+        values: [1.5, 0.4, 0.8, 0.8, 3.0, 4., 10., 18.],
+    },
+    Preset {
+        name: "scatter",
+        values: [3.0, 0.5, 3.0, 0.5, 0.3, 12., 10., 10.],
+    },
+];
+
+pub struct Demo {
+    interval: f32,
+    transition: f32,
+    current: Option<usize>,
+}
+
+impl Demo {
+    pub fn new(interval: f32, transition: f32) -> Demo {
+        Demo {
+            interval: interval.max(1.),
+            transition: transition.max(0.).min(interval),
+            current: None,
+        }
+    }
+
+    pub fn poll(&mut self, simulation: &mut FlockingSystem) {
+        let elapsed = simulation.tick() as f64 / TICKS_PER_SECOND;
+        let period = (elapsed / f64::from(self.interval)) as usize;
+        let index = period % PRESETS.len();
+        if self.current != Some(index) {
+            println!("Demo preset: {}", PRESETS[index].name);
+            self.current = Some(index);
+        }
+
+        let to = &PRESETS[index];
+        let from = &PRESETS[(index + PRESETS.len() - 1) % PRESETS.len()];
+        let into_period = (elapsed - period as f64 * f64::from(self.interval)) as f32;
+        let t = if period == 0 || into_period >= self.transition {
+            1.
+        } else {
+            Easing::Smooth.apply(into_period / self.transition)
+        };
+        for (i, &parameter) in PARAMETERS.iter().enumerate() {
+            let value = from.values[i] + (to.values[i] - from.values[i]) * t;
+            simulation.set_parameter(parameter, value);
+        }
+    }
+}
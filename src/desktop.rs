@@ -0,0 +1,36 @@
+// Lets mouse clicks pass through the window to whatever is underneath, for flying over the desktop.
+// Only X11 supports this for now, elsewhere the window stays clickable.
+
+use glutin::GlWindow;
+
+#[cfg(all(unix, not(target_os = "macos")))]
+pub fn click_through(window: &GlWindow) {
+    use glutin::os::unix::WindowExt;
+    use std::{os::raw::c_int, ptr};
+    use x11_dl::{xfixes, xlib};
+
+    // Shape kind of the input region, from the X shape extension
+    const SHAPE_INPUT: c_int = 2;
+
+    let (display, xwindow) = match (window.get_xlib_display(), window.get_xlib_window()) {
+        (Some(display), Some(xwindow)) => (display as *mut xlib::Display, xwindow),
+        _ => return println!("Click-through needs X11, the window will catch clicks"),
+    };
+    let libs = xfixes::Xlib::open().and_then(|fixes| Ok((fixes, xlib::Xlib::open()?)));
+    let (fixes, xlib) = match libs {
+        Ok(libs) => libs,
+        Err(err) => return println!("Click-through needs libXfixes, {}", err),
+    };
+    // An empty input region means the window never receives pointer events
+    unsafe {
+        let region = (fixes.XFixesCreateRegion)(display, ptr::null_mut(), 0);
+        (fixes.XFixesSetWindowShapeRegion)(display, xwindow, SHAPE_INPUT, 0, 0, region);
+        (fixes.XFixesDestroyRegion)(display, region);
+        (xlib.XFlush)(display);
+    }
+}
+
+#[cfg(not(all(unix, not(target_os = "macos"))))]
+pub fn click_through(_window: &GlWindow) {
+    println!("Click-through isn't supported on this platform, the window will catch clicks");
+}
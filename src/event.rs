@@ -1,21 +1,90 @@
 use glutin::{self, dpi, VirtualKeyCode};
 
+// Controls are bound to physical keys, named after where they are on a US QWERTY keyboard, so
+// they stay in the same place on other layouts. Virtual keys are only used by platforms that
+// don't report scancodes.
+#[rustfmt::skip]
+const BINDINGS: [(u32, VirtualKeyCode, BoidControlEvent); 10] = [
+    (scancode::ESCAPE, VirtualKeyCode::Escape, BoidControlEvent::Stop),
+    (scancode::Q, VirtualKeyCode::Q, BoidControlEvent::Stop),
+    (scancode::SPACE, VirtualKeyCode::Space, BoidControlEvent::Pause),
+    (scancode::R, VirtualKeyCode::R, BoidControlEvent::Randomise),
+    (scancode::F, VirtualKeyCode::F, BoidControlEvent::Zeroise),
+    (scancode::C, VirtualKeyCode::C, BoidControlEvent::Centralise),
+    (scancode::T, VirtualKeyCode::T, BoidControlEvent::ToggleFormation),
+    (scancode::P, VirtualKeyCode::P, BoidControlEvent::ToggleProfiler),
+    (scancode::COMMA, VirtualKeyCode::Comma, BoidControlEvent::StepBack),
+    (scancode::PERIOD, VirtualKeyCode::Period, BoidControlEvent::StepForward),
+];
+
+// Linux and Windows both report PC set 1 scancodes
+#[cfg(not(target_os = "macos"))]
+mod scancode {
+    pub const ESCAPE: u32 = 0x01;
+    pub const Q: u32 = 0x10;
+    pub const R: u32 = 0x13;
+    pub const T: u32 = 0x14;
+    pub const P: u32 = 0x19;
+    pub const F: u32 = 0x21;
+    pub const C: u32 = 0x2e;
+    pub const COMMA: u32 = 0x33;
+    pub const PERIOD: u32 = 0x34;
+    pub const SPACE: u32 = 0x39;
+}
+
+#[cfg(target_os = "macos")]
+mod scancode {
+    pub const ESCAPE: u32 = 0x35;
+    pub const Q: u32 = 0x0c;
+    pub const R: u32 = 0x0f;
+    pub const T: u32 = 0x11;
+    pub const P: u32 = 0x23;
+    pub const F: u32 = 0x03;
+    pub const C: u32 = 0x08;
+    pub const COMMA: u32 = 0x2b;
+    pub const PERIOD: u32 = 0x2f;
+    pub const SPACE: u32 = 0x31;
+}
+
+#[derive(Clone, Debug, PartialEq)]
 pub enum BoidControlEvent {
     Stop,
     Pause,
-    Key(VirtualKeyCode),
+    Randomise,
+    Zeroise,
+    Centralise,
     MouseMove(f32, f32),
     MousePress,
     MouseRelease,
+    ToggleFormation,
+    ToggleProfiler,
+    StepBack,
+    StepForward,
 }
 
 pub struct EventFilter {
     hidpi_factor: f64,
+    offset: (f32, f32),
+    view: (f32, f32),
+    scale: (f32, f32),
 }
 
 impl EventFilter {
-    pub fn new(hidpi_factor: f64) -> Self {
-        EventFilter { hidpi_factor }
+    // Mouse positions are mapped from the window onto the region of the world it shows
+    pub fn new(hidpi_factor: f64, window: (f32, f32), view: (f32, f32, f32, f32)) -> Self {
+        let (x, y, width, height) = view;
+        EventFilter {
+            hidpi_factor,
+            offset: (x, y),
+            view: (width, height),
+            scale: (width / window.0, height / window.1),
+        }
+    }
+
+    // The same region of the world is kept in view when the window changes size or monitor
+    pub fn resize(&mut self, hidpi_factor: f64, window: (f32, f32)) {
+        self.hidpi_factor = hidpi_factor;
+        self.scale = (self.view.0 / window.0, self.view.1 / window.1);
     }
 
     pub fn process(&self, event: glutin::Event) -> Option<BoidControlEvent> {
@@ -32,19 +101,25 @@ impl EventFilter {
                 input:
                     KeyboardInput {
                         state: ElementState::Pressed,
-                        virtual_keycode: Some(key),
+                        scancode,
+                        virtual_keycode,
                         ..
                     },
                 ..
-            } => match key {
-                VirtualKeyCode::Escape | VirtualKeyCode::Q => Some(BoidControlEvent::Stop),
-                VirtualKeyCode::Space => Some(BoidControlEvent::Pause),
-                _ => Some(BoidControlEvent::Key(key)),
-            },
+            } => BINDINGS
+                .iter()
+                .find(|&&(code, key, _)| match scancode {
+                    0 => virtual_keycode == Some(key),
+                    _ => scancode == code,
+                })
+                .map(|(_, _, event)| event.clone()),
 
             WindowEvent::CursorMoved { position: pos, .. } => {
                 let dpi::PhysicalPosition { x, y } = pos.to_physical(self.hidpi_factor);
-                Some(BoidControlEvent::MouseMove(x as f32, y as f32))
+                Some(BoidControlEvent::MouseMove(
+                    self.offset.0 + x as f32 * self.scale.0,
+                    self.offset.1 + y as f32 * self.scale.1,
+                ))
             }
 
             WindowEvent::MouseInput {
@@ -0,0 +1,140 @@
+// Headless A/B experiments and parameter sweeps.
+// Runs each config, or each value of a parameter, for the same number of ticks from the same
+// seed and reports flock statistics, so the effect of a change can be measured rather than
+// eyeballed.
+
+use std::{
+    fs::File,
+    io::{prelude::*, BufWriter},
+    time::{Duration, Instant},
+};
+
+use crate::boids::{self, Experiment, SimulationConfig, SimulatorError, Sweep, WindowSize};
+use crate::system::{FlockingConfig, FlockingSystem, Parameter};
+use crate::timeline::Timeline;
+
+// Used for the world size of fullscreen configs, as there is no screen to measure
+const HEADLESS_SIZE: (f32, f32) = (800., 800.);
+
+struct Report {
+    boid_count: usize,
+    average_speed: f32,
+    polarization: f32,
+    mean_polarization: f32,
+    tick_duration: Duration,
+}
+
+pub fn run(seed: u64, experiment: &Experiment) -> Result<(), SimulatorError> {
+    println!(
+        "Comparing {} configs over {} ticks from seed {}",
+        experiment.variants.len(),
+        experiment.ticks,
+        seed
+    );
+    println!(
+        "{:<24} {:>8} {:>10} {:>13} {:>18} {:>10}",
+        "config", "boids", "speed", "polarization", "mean polarization", "ms/tick"
+    );
+    for (name, config) in &experiment.variants {
+        let report = run_variant(config, seed, experiment.ticks, None)?;
+        println!(
+            "{:<24} {:>8} {:>10.3} {:>13.3} {:>18.3} {:>10.3}",
+            name,
+            report.boid_count,
+            report.average_speed,
+            report.polarization,
+            report.mean_polarization,
+            report.tick_duration.as_secs_f64() * 1000.
+        );
+    }
+    Ok(())
+}
+
+// Writes a CSV row of flock statistics for each value of the swept parameter
+pub fn sweep(seed: u64, config: &SimulationConfig, sweep: &Sweep) -> Result<(), SimulatorError> {
+    let (from, to) = match sweep.range {
+        Some(range) => range,
+        // Up to twice the configured value
+        None => {
+            let simulation = FlockingSystem::new(flocking_config(config, seed));
+            (0., 2. * simulation.parameter(sweep.parameter))
+        }
+    };
+    let name = sweep.parameter.name();
+    println!(
+        "Sweeping {} from {} to {} in {} steps of {} ticks from seed {}",
+        name, from, to, sweep.steps, sweep.ticks, seed
+    );
+    let mut writer = BufWriter::new(File::create(&sweep.output)?);
+    writeln!(
+        writer,
+        "{},boids,average_speed,polarization,mean_polarization,ms_per_tick",
+        name
+    )?;
+    let steps = sweep.steps.max(1);
+    for step in 0..=steps {
+        let value = from + (to - from) * step as f32 / steps as f32;
+        let report = run_variant(config, seed, sweep.ticks, Some((sweep.parameter, value)))?;
+        writeln!(
+            writer,
+            "{},{},{},{},{},{:.3}",
+            value,
+            report.boid_count,
+            report.average_speed,
+            report.polarization,
+            report.mean_polarization,
+            report.tick_duration.as_secs_f64() * 1000.
+        )?;
+        println!(
+            "{} = {}: polarization {:.3}",
+            name, value, report.polarization
+        );
+    }
+    writer.flush()?;
+    println!("Wrote sweep results to {}", sweep.output);
+    Ok(())
+}
+
+fn flocking_config(config: &SimulationConfig, seed: u64) -> FlockingConfig {
+    let world = match config.window_size {
+        WindowSize::Dimensions((width, height)) => (width as f32, height as f32),
+        WindowSize::Fullscreen
+        | WindowSize::Borderless
+        | WindowSize::Desktop
+        | WindowSize::Span(_) => HEADLESS_SIZE,
+    };
+    boids::build_flocking_config(config, world, seed)
+}
+
+fn run_variant(
+    config: &SimulationConfig,
+    seed: u64,
+    ticks: u64,
+    setting: Option<(Parameter, f32)>,
+) -> Result<Report, SimulatorError> {
+    let mut simulation = FlockingSystem::new(flocking_config(config, seed));
+    boids::init_flock(&mut simulation, config)?;
+    if let Some((parameter, value)) = setting {
+        simulation.set_parameter(parameter, value);
+    }
+    let timeline = Timeline::new(&config.timeline);
+
+    let mut update_time = Duration::new(0, 0);
+    let mut polarization_sum = 0.;
+    for _ in 0..ticks {
+        timeline.apply(&mut simulation);
+        let tick_start = Instant::now();
+        simulation.update();
+        update_time += tick_start.elapsed();
+        polarization_sum += simulation.stats().polarization;
+    }
+
+    let stats = simulation.stats();
+    Ok(Report {
+        boid_count: stats.boid_count,
+        average_speed: stats.average_speed,
+        polarization: stats.polarization,
+        mean_polarization: polarization_sum / ticks.max(1) as f32,
+        tick_duration: update_time / ticks.max(1) as u32,
+    })
+}
@@ -0,0 +1,51 @@
+use std::{
+    fs::File,
+    io::{self, prelude::*, BufWriter},
+};
+
+use crate::system::FlockingSystem;
+
+// Writes every boid's position and velocity to a CSV file every `interval` ticks
+pub struct TrajectoryWriter {
+    writer: BufWriter<File>,
+    interval: u64,
+    last_written: Option<u64>,
+}
+
+impl TrajectoryWriter {
+    pub fn create(path: &str, interval: u64) -> io::Result<TrajectoryWriter> {
+        let mut writer = BufWriter::new(File::create(path)?);
+        writeln!(writer, "tick,id,x,y,vx,vy")?;
+        Ok(TrajectoryWriter {
+            writer,
+            interval: interval.max(1),
+            last_written: None,
+        })
+    }
+
+    pub fn poll(&mut self, simulation: &FlockingSystem) -> io::Result<()> {
+        let tick = simulation.tick();
+        if !tick.is_multiple_of(self.interval) || self.last_written == Some(tick) {
+            return Ok(());
+        }
+        self.last_written = Some(tick);
+        for boid in simulation.boids() {
+            let (p, v) = (boid.position(), boid.velocity());
+            writeln!(
+                self.writer,
+                "{},{},{},{},{},{}",
+                tick,
+                boid.id(),
+                p.x,
+                p.y,
+                v.x,
+                v.y
+            )?;
+        }
+        Ok(())
+    }
+
+    pub fn finish(mut self) -> io::Result<()> {
+        self.writer.flush()
+    }
+}
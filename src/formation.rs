@@ -0,0 +1,34 @@
+// Formation targets for the flock, from the filled pixels of an image mask or some text.
+// The shape is scaled to fill most of the world while keeping its aspect ratio, and centred.
+
+use std::io;
+
+use cgmath::Point2;
+
+use crate::boids::FormationShape;
+use crate::mask::Mask;
+use crate::system::FlockingSystem;
+
+// Fraction of the world the shape fills
+const FILL: f32 = 0.8;
+
+pub fn load(shape: &FormationShape, simulation: &mut FlockingSystem) -> io::Result<()> {
+    let mask = match *shape {
+        FormationShape::Image(ref path) => Mask::load(path)?,
+        FormationShape::Text(ref text) => Mask::from_text(text),
+    };
+    let (width, height) = simulation.dimensions();
+    let cell_size = (FILL * width / mask.width as f32).min(FILL * height / mask.height as f32);
+    let left = (width - cell_size * mask.width as f32) / 2.;
+    let top = (height - cell_size * mask.height as f32) / 2.;
+    let cells: Vec<Point2<f32>> = mask
+        .filled_pixels()
+        .map(|(x, y)| Point2::new(left + x as f32 * cell_size, top + y as f32 * cell_size))
+        .collect();
+    if cells.is_empty() {
+        println!("Formation shape has no filled pixels, ignoring it");
+        return Ok(());
+    }
+    simulation.set_targets(&cells, cell_size);
+    Ok(())
+}
@@ -1,6 +1,11 @@
-use std::time::{Duration, Instant};
+use std::{
+    hint, thread,
+    time::{Duration, Instant},
+};
 
 const NUM_SAMPLES: usize = 20;
+// Sleeps can overshoot by a millisecond or so, so the end of each frame is spun out instead
+const SPIN_MARGIN: Duration = Duration::from_millis(2);
 
 pub struct FpsCache {
     cache_interval: Duration,
@@ -81,3 +86,31 @@ impl FpsCounter {
         self.current_sample %= self.samples.len();
     }
 }
+
+// Holds the frame rate down to a target when vsync isn't doing it
+pub struct FrameLimiter {
+    frame: Duration,
+    next_frame: Instant,
+}
+
+impl FrameLimiter {
+    pub fn new(fps: u32) -> FrameLimiter {
+        let frame = Duration::from_secs(1) / fps.max(1);
+        FrameLimiter {
+            frame,
+            next_frame: Instant::now() + frame,
+        }
+    }
+
+    pub fn wait(&mut self) {
+        let now = Instant::now();
+        if self.next_frame > now + SPIN_MARGIN {
+            thread::sleep(self.next_frame - now - SPIN_MARGIN);
+        }
+        while Instant::now() < self.next_frame {
+            hint::spin_loop();
+        }
+        // Frames that run late start a fresh schedule rather than rushing to catch up
+        self.next_frame = self.next_frame.max(Instant::now()) + self.frame;
+    }
+}
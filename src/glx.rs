@@ -4,10 +4,13 @@ use cgmath::Matrix3;
 use gl::{self, types::*};
 use std::{
     error,
-    ffi::{CStr, CString},
-    fmt, ptr,
+    ffi::{c_void, CStr, CString},
+    fmt, ptr, slice,
 };
 
+// Stops a context that keeps failing from flooding the terminal every frame
+const MAX_REPORTED_ERRORS: usize = 16;
+
 pub fn get_gl_extensions() -> Vec<String> {
     let mut results = vec![];
     for i in 0..get_gl_int(gl::NUM_EXTENSIONS) {
@@ -39,17 +42,121 @@ unsafe fn read_gl_str(ptr: *const u8) -> String {
         .to_owned()
 }
 
-pub fn vtx_transform_2d(width: f32, height: f32) -> Matrix3<f32> {
-    Matrix3::new(2. / width, 0., 0., 0., -2. / height, 0., -1., 1., 1.)
+// Maps the rectangle at (x, y) with the given width & height onto the whole screen
+pub fn vtx_transform_2d(x: f32, y: f32, width: f32, height: f32) -> Matrix3<f32> {
+    let (sx, sy) = (2. / width, -2. / height);
+    Matrix3::new(sx, 0., 0., 0., sy, 0., -1. - x * sx, 1. - y * sy, 1.)
+}
+
+// Reads the RGBA pixels of the back buffer, bottom row first
+pub fn read_pixels(width: u32, height: u32) -> Vec<u8> {
+    let mut pixels = vec![0u8; width as usize * height as usize * 4];
+    unsafe {
+        gl::PixelStorei(gl::PACK_ALIGNMENT, 1);
+        gl::ReadPixels(
+            0,
+            0,
+            width as GLsizei,
+            height as GLsizei,
+            gl::RGBA,
+            gl::UNSIGNED_BYTE,
+            pixels.as_mut_ptr() as *mut GLvoid,
+        );
+    }
+    pixels
+}
+
+pub fn set_viewport(width: u32, height: u32) {
+    unsafe {
+        gl::Viewport(0, 0, width as GLsizei, height as GLsizei);
+    }
 }
 
-pub fn clear_screen(r: GLfloat, g: GLfloat, b: GLfloat) {
+pub fn clear_screen(r: GLfloat, g: GLfloat, b: GLfloat, a: GLfloat) {
     unsafe {
-        gl::ClearColor(r, g, b, 1.0);
+        gl::ClearColor(r * a, g * a, b * a, a);
         gl::Clear(gl::COLOR_BUFFER_BIT);
     }
 }
 
+// Has the driver print its messages as they happen, false if it can't
+pub fn enable_debug_output() -> bool {
+    if !gl::DebugMessageCallback::is_loaded() {
+        return false;
+    }
+    unsafe {
+        gl::Enable(gl::DEBUG_OUTPUT);
+        // Messages are reported from the call that caused them
+        gl::Enable(gl::DEBUG_OUTPUT_SYNCHRONOUS);
+        gl::DebugMessageCallback(print_debug_message, ptr::null());
+        // Some drivers describe every buffer upload
+        gl::DebugMessageControl(
+            gl::DONT_CARE,
+            gl::DONT_CARE,
+            gl::DEBUG_SEVERITY_NOTIFICATION,
+            0,
+            ptr::null(),
+            gl::FALSE,
+        );
+    }
+    true
+}
+
+extern "system" fn print_debug_message(
+    _source: GLenum,
+    kind: GLenum,
+    _id: GLuint,
+    severity: GLenum,
+    length: GLsizei,
+    message: *const GLchar,
+    _user: *mut c_void,
+) {
+    let message = unsafe {
+        if length < 0 {
+            CStr::from_ptr(message).to_string_lossy()
+        } else {
+            String::from_utf8_lossy(slice::from_raw_parts(message as *const u8, length as usize))
+        }
+    };
+    let severity = match severity {
+        gl::DEBUG_SEVERITY_HIGH => "high",
+        gl::DEBUG_SEVERITY_MEDIUM => "medium",
+        gl::DEBUG_SEVERITY_LOW => "low",
+        _ => "info",
+    };
+    let kind = match kind {
+        gl::DEBUG_TYPE_ERROR => "error",
+        gl::DEBUG_TYPE_DEPRECATED_BEHAVIOR => "deprecated",
+        gl::DEBUG_TYPE_UNDEFINED_BEHAVIOR => "undefined behaviour",
+        gl::DEBUG_TYPE_PERFORMANCE => "performance",
+        gl::DEBUG_TYPE_PORTABILITY => "portability",
+        _ => "message",
+    };
+    println!(
+        "GL {} ({} severity): {}",
+        kind,
+        severity,
+        message.trim_end()
+    );
+}
+
+// Prints the errors raised since the last check, for drivers without debug output
+pub fn report_errors(during: &str) {
+    for _ in 0..MAX_REPORTED_ERRORS {
+        let error = unsafe { gl::GetError() };
+        let name = match error {
+            gl::NO_ERROR => return,
+            gl::INVALID_ENUM => "invalid enum",
+            gl::INVALID_VALUE => "invalid value",
+            gl::INVALID_OPERATION => "invalid operation",
+            gl::INVALID_FRAMEBUFFER_OPERATION => "invalid framebuffer operation",
+            gl::OUT_OF_MEMORY => "out of memory",
+            _ => "unknown error",
+        };
+        println!("GL error while {}: {} (0x{:x})", during, name, error);
+    }
+}
+
 #[derive(Debug)]
 pub enum ShaderError {
     Compilation(String),
@@ -137,6 +244,34 @@ impl Drop for Buffer {
     }
 }
 
+pub struct Texture {
+    texture_id: GLuint,
+}
+
+impl Texture {
+    pub fn new() -> Texture {
+        let mut texture_id = 0;
+        unsafe {
+            gl::GenTextures(1, &mut texture_id);
+        }
+        Texture { texture_id }
+    }
+
+    pub fn bind(&self, target: GLenum) {
+        unsafe {
+            gl::BindTexture(target, self.texture_id);
+        }
+    }
+}
+
+impl Drop for Texture {
+    fn drop(&mut self) {
+        unsafe {
+            gl::DeleteTextures(1, &self.texture_id);
+        }
+    }
+}
+
 pub struct ShaderProgram {
     program_id: GLuint,
 }
@@ -215,9 +350,7 @@ unsafe fn compile_shader(src: &str, shader_type: GLenum) -> Result<GLuint, Shade
     if status != (gl::TRUE as GLint) {
         let mut len = 0;
         gl::GetShaderiv(shader, gl::INFO_LOG_LENGTH, &mut len);
-        let mut buf = Vec::with_capacity(len as usize);
-        // subract 1 to skip the trailing null character
-        buf.set_len((len as usize) - 1);
+        let mut buf = vec![0u8; len as usize];
         gl::GetShaderInfoLog(
             shader,
             len,
@@ -225,6 +358,8 @@ unsafe fn compile_shader(src: &str, shader_type: GLenum) -> Result<GLuint, Shade
             buf.as_mut_ptr() as *mut GLchar,
         );
 
+        // skip the trailing null character
+        buf.truncate((len as usize).saturating_sub(1));
         let err_msg = String::from_utf8(buf).expect("ProgramInfoLog not valid utf8");
         Err(ShaderError::Compilation(err_msg))
     } else {
@@ -249,9 +384,7 @@ unsafe fn link_program(vrtx_shader: GLuint, frag_shader: GLuint) -> Result<GLuin
     if status != (gl::TRUE as GLint) {
         let mut len = 0;
         gl::GetProgramiv(program, gl::INFO_LOG_LENGTH, &mut len);
-        let mut buf = Vec::with_capacity(len as usize);
-        // subract 1 to skip the trailing null character
-        buf.set_len((len as usize) - 1);
+        let mut buf = vec![0u8; len as usize];
         gl::GetProgramInfoLog(
             program,
             len,
@@ -259,6 +392,8 @@ unsafe fn link_program(vrtx_shader: GLuint, frag_shader: GLuint) -> Result<GLuin
             buf.as_mut_ptr() as *mut GLchar,
         );
 
+        // skip the trailing null character
+        buf.truncate((len as usize).saturating_sub(1));
         let err_msg = String::from_utf8(buf).expect("ProgramInfoLog not valid utf8");
         Err(ShaderError::Linking(err_msg))
     } else {
@@ -0,0 +1,64 @@
+// Keeps ticks under a time budget on slow machines, by having boids look at fewer neighbours
+// while ticks run over budget and at more again once there's headroom.
+
+use std::time::Duration;
+
+use crate::system::FlockingSystem;
+
+// Ticks to average over before adjusting
+const SAMPLE_TICKS: u32 = 30;
+// Fraction of the budget ticks must fall under before quality is restored
+const HEADROOM: f64 = 0.6;
+const MIN_NEIGHBOURS: usize = 3;
+
+pub struct Governor {
+    budget: Duration,
+    max_neighbours: usize,
+    ticks: u32,
+    tick_time: Duration,
+}
+
+impl Governor {
+    pub fn new(budget_ms: f32, simulation: &FlockingSystem) -> Governor {
+        println!(
+            "Reducing quality when ticks take longer than {} ms",
+            budget_ms
+        );
+        Governor {
+            // Budgets too long to represent are never exceeded anyway
+            budget: Duration::try_from_secs_f32(budget_ms / 1000.).unwrap_or(Duration::MAX),
+            max_neighbours: simulation.neighbour_cap(),
+            ticks: 0,
+            tick_time: Duration::new(0, 0),
+        }
+    }
+
+    // Call once a tick with how long it took
+    pub fn poll(&mut self, simulation: &mut FlockingSystem, tick_duration: Duration) {
+        self.ticks += 1;
+        self.tick_time += tick_duration;
+        if self.ticks < SAMPLE_TICKS {
+            return;
+        }
+        let average = self.tick_time / self.ticks;
+        self.ticks = 0;
+        self.tick_time = Duration::new(0, 0);
+
+        let cap = simulation.neighbour_cap();
+        let new_cap = if average > self.budget && cap > MIN_NEIGHBOURS {
+            cap - 1
+        } else if average.as_secs_f64() < self.budget.as_secs_f64() * HEADROOM
+            && cap < self.max_neighbours
+        {
+            cap + 1
+        } else {
+            return;
+        };
+        simulation.set_neighbour_cap(new_cap);
+        println!(
+            "Ticks averaging {:.2} ms, boids now look at {} neighbours",
+            average.as_secs_f64() * 1000.,
+            new_cap
+        );
+    }
+}
@@ -0,0 +1,93 @@
+// Exports how often boids visit each part of the world as a false colour PNG, written at the end
+// of a run. Each pixel is a unit of the world, counting the boids found there on every sampled
+// tick. Counts are shown on a log scale, as the busiest spots are visited far more than the rest.
+
+use std::{
+    fs::File,
+    io::{self, BufWriter},
+};
+
+use png::{BitDepth, ColorType, Encoder};
+
+use crate::system::FlockingSystem;
+
+// Colours from no visits up to the most, evenly spaced along the log scale
+const PALETTE: [[f32; 3]; 6] = [
+    [0., 0., 0.],
+    [40., 10., 90.],
+    [150., 30., 110.],
+    [230., 80., 50.],
+    [250., 180., 30.],
+    [255., 255., 220.],
+];
+
+pub struct HeatmapWriter {
+    path: String,
+    interval: u64,
+    last_sampled: Option<u64>,
+    width: usize,
+    height: usize,
+    counts: Vec<u32>,
+}
+
+impl HeatmapWriter {
+    pub fn new(path: &str, interval: u64, simulation: &FlockingSystem) -> HeatmapWriter {
+        let (width, height) = simulation.dimensions();
+        let (width, height) = (width.ceil() as usize, height.ceil() as usize);
+        HeatmapWriter {
+            path: path.to_string(),
+            interval: interval.max(1),
+            last_sampled: None,
+            width,
+            height,
+            counts: vec![0; width * height],
+        }
+    }
+
+    pub fn poll(&mut self, simulation: &FlockingSystem) {
+        let tick = simulation.tick();
+        if !tick.is_multiple_of(self.interval) || self.last_sampled == Some(tick) {
+            return;
+        }
+        self.last_sampled = Some(tick);
+        for boid in simulation.boids() {
+            let p = boid.position();
+            // Boids sit exactly on the far edges now and then
+            let x = (p.x.max(0.) as usize).min(self.width - 1);
+            let y = (p.y.max(0.) as usize).min(self.height - 1);
+            self.counts[y * self.width + x] += 1;
+        }
+    }
+
+    pub fn finish(self) -> io::Result<()> {
+        let max = self.counts.iter().cloned().max().unwrap_or(0);
+        let scale = 1. / (max as f32).ln_1p().max(f32::MIN_POSITIVE);
+        let mut pixels = Vec::with_capacity(self.counts.len() * 3);
+        for &count in &self.counts {
+            pixels.extend_from_slice(&colour((count as f32).ln_1p() * scale));
+        }
+
+        let writer = BufWriter::new(File::create(&self.path)?);
+        let mut encoder = Encoder::new(writer, self.width as u32, self.height as u32);
+        encoder.set_color(ColorType::Rgb);
+        encoder.set_depth(BitDepth::Eight);
+        let mut writer = encoder.write_header()?;
+        writer.write_image_data(&pixels)?;
+        writer.finish()?;
+        println!("Wrote density heatmap to {}", self.path);
+        Ok(())
+    }
+}
+
+// Interpolates the palette at `level`, from 0 to 1
+fn colour(level: f32) -> [u8; 3] {
+    let position = level.clamp(0., 1.) * (PALETTE.len() - 1) as f32;
+    let index = (position as usize).min(PALETTE.len() - 2);
+    let t = position - index as f32;
+    let (from, to) = (PALETTE[index], PALETTE[index + 1]);
+    let mut rgb = [0; 3];
+    for (channel, value) in rgb.iter_mut().enumerate() {
+        *value = (from[channel] + (to[channel] - from[channel]) * t).round() as u8;
+    }
+    rgb
+}
@@ -0,0 +1,157 @@
+// A ring buffer of recent states of the flock, to rewind through.
+// Snapshots are taken several times a second. Stepping back restores an older one, and the flock
+// carries on from there, replacing the snapshots after it as it goes. Until it does, stepping
+// forward again goes back through them.
+
+use std::collections::VecDeque;
+
+use crate::system::{FlockingSystem, Snapshot};
+
+const SNAPSHOT_INTERVAL: u64 = 6;
+// Snapshots in a second's worth of ticks at 60 fps, which is how far each step goes
+const SNAPSHOTS_PER_STEP: usize = 10;
+
+pub struct History {
+    snapshots: VecDeque<Snapshot>,
+    capacity: usize,
+    // The snapshot last rewound to, while the flock hasn't moved on from it
+    position: Option<usize>,
+}
+
+impl History {
+    pub fn new(seconds: f32) -> History {
+        let capacity = ((seconds.max(0.) * SNAPSHOTS_PER_STEP as f32) as usize).max(1);
+        History {
+            snapshots: VecDeque::with_capacity(capacity),
+            capacity,
+            position: None,
+        }
+    }
+
+    // Called after every tick
+    pub fn poll(&mut self, simulation: &FlockingSystem) {
+        if !simulation.tick().is_multiple_of(SNAPSHOT_INTERVAL) {
+            return;
+        }
+        // The flock has moved on from where it was rewound to, so what came after is gone
+        if let Some(position) = self.position.take() {
+            self.snapshots.truncate(position + 1);
+        }
+        if self.snapshots.len() == self.capacity {
+            self.snapshots.pop_front();
+        }
+        self.snapshots.push_back(simulation.snapshot());
+    }
+
+    pub fn back(&mut self, simulation: &mut FlockingSystem) {
+        let current = self.position.unwrap_or(self.snapshots.len());
+        self.restore(current.saturating_sub(SNAPSHOTS_PER_STEP), simulation);
+    }
+
+    pub fn forward(&mut self, simulation: &mut FlockingSystem) {
+        if let Some(position) = self.position {
+            let last = self.snapshots.len() - 1;
+            self.restore((position + SNAPSHOTS_PER_STEP).min(last), simulation);
+        }
+    }
+
+    fn restore(&mut self, position: usize, simulation: &mut FlockingSystem) {
+        if let Some(snapshot) = self.snapshots.get(position) {
+            simulation.rewind(snapshot);
+            self.position = Some(position);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::*;
+    use crate::system::tests::config;
+
+    // Runs the flock for `ticks`, keeping history, and returns its checksum after each tick
+    fn run(
+        simulation: &mut FlockingSystem,
+        history: &mut History,
+        ticks: u64,
+    ) -> HashMap<u64, u64> {
+        let mut checksums = HashMap::new();
+        for _ in 0..ticks {
+            simulation.update();
+            history.poll(simulation);
+            checksums.insert(simulation.tick(), simulation.checksum());
+        }
+        checksums
+    }
+
+    fn flock() -> FlockingSystem {
+        let mut simulation = FlockingSystem::new(config(100));
+        simulation.randomise();
+        simulation
+    }
+
+    #[test]
+    fn back_from_the_newest() {
+        let mut simulation = flock();
+        let mut history = History::new(5.);
+        let checksums = run(&mut simulation, &mut history, 120);
+        // Snapshots were taken on ticks 6 to 120
+        history.back(&mut simulation);
+        assert_eq!(simulation.tick(), 66);
+        assert_eq!(simulation.checksum(), checksums[&66]);
+        history.back(&mut simulation);
+        assert_eq!(simulation.tick(), 6);
+        history.back(&mut simulation);
+        assert_eq!(simulation.tick(), 6);
+        assert_eq!(simulation.checksum(), checksums[&6]);
+    }
+
+    #[test]
+    fn forward_stops_at_the_last() {
+        let mut simulation = flock();
+        let mut history = History::new(5.);
+        let checksums = run(&mut simulation, &mut history, 120);
+        history.forward(&mut simulation);
+        assert_eq!(simulation.tick(), 120);
+        history.back(&mut simulation);
+        history.back(&mut simulation);
+        history.forward(&mut simulation);
+        assert_eq!(simulation.tick(), 66);
+        history.forward(&mut simulation);
+        assert_eq!(simulation.tick(), 120);
+        history.forward(&mut simulation);
+        assert_eq!(simulation.tick(), 120);
+        assert_eq!(simulation.checksum(), checksums[&120]);
+    }
+
+    #[test]
+    fn moving_on_drops_later_snapshots() {
+        let mut simulation = flock();
+        let mut history = History::new(5.);
+        run(&mut simulation, &mut history, 120);
+        history.back(&mut simulation);
+        let checksums = run(&mut simulation, &mut history, 6);
+        // The snapshots after tick 66 were replaced by one at tick 72
+        assert_eq!(history.snapshots.len(), 12);
+        history.forward(&mut simulation);
+        assert_eq!(simulation.tick(), 72);
+        history.back(&mut simulation);
+        assert_eq!(simulation.tick(), 18);
+        history.forward(&mut simulation);
+        assert_eq!(simulation.tick(), 72);
+        assert_eq!(simulation.checksum(), checksums[&72]);
+    }
+
+    #[test]
+    fn keeps_only_its_capacity() {
+        let mut simulation = flock();
+        let mut history = History::new(1.);
+        run(&mut simulation, &mut history, 120);
+        assert_eq!(history.snapshots.len(), 10);
+        history.back(&mut simulation);
+        assert_eq!(simulation.tick(), 66);
+        history.back(&mut simulation);
+        assert_eq!(simulation.tick(), 66);
+    }
+}
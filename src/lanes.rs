@@ -0,0 +1,188 @@
+// Sums for the flocking rules over a boid's neighbours. With the `simd` feature they're worked
+// out several neighbours at a time: neighbours are loaded into struct of arrays batches, and every
+// lane of a batch is always computed with masks rather than branches, so the compiler turns each
+// lane loop into SIMD.
+
+#[cfg(any(not(feature = "simd"), test))]
+use cgmath::InnerSpace;
+use cgmath::{Point2, Vector2};
+
+use crate::system::{Boid, Real};
+
+#[cfg(feature = "simd")]
+const LANES: usize = 8;
+
+pub struct RuleSums {
+    pub dodge: Vector2<Real>,
+    pub velocity: Vector2<Real>,
+    pub velocity_count: Real,
+    pub position: Vector2<Real>,
+    pub position_count: Real,
+}
+
+// Squared radii of the separation, alignment and cohesion rules
+pub struct Radii {
+    pub separation: Real,
+    pub alignment: Real,
+    pub cohesion: Real,
+}
+
+#[cfg(feature = "simd")]
+#[derive(Default)]
+struct Lanes([Real; LANES]);
+
+#[cfg(feature = "simd")]
+impl Lanes {
+    fn sum(&self) -> Real {
+        self.0.iter().sum()
+    }
+}
+
+#[cfg(not(feature = "simd"))]
+pub fn sum_rules(position: Point2<Real>, neighbours: &[Boid], radii: &Radii) -> RuleSums {
+    sum_rules_scalar(position, neighbours, radii)
+}
+
+// One neighbour at a time, also built for tests of the SIMD version to check against
+#[cfg(any(not(feature = "simd"), test))]
+fn sum_rules_scalar(position: Point2<Real>, neighbours: &[Boid], radii: &Radii) -> RuleSums {
+    let mut sums = RuleSums {
+        dodge: Vector2::new(0., 0.),
+        velocity: Vector2::new(0., 0.),
+        velocity_count: 0.,
+        position: Vector2::new(0., 0.),
+        position_count: 0.,
+    };
+    for other in neighbours {
+        let from_neighbour = position - other.exact_position();
+        let dist_squared = from_neighbour.magnitude2();
+        if dist_squared > 0. {
+            if dist_squared < radii.separation {
+                let repulse = 1. / dist_squared.sqrt();
+                sums.dodge += from_neighbour.normalize_to(repulse);
+            }
+            if dist_squared < radii.alignment {
+                sums.velocity += other.exact_velocity();
+                sums.velocity_count += 1.;
+            }
+            if dist_squared < radii.cohesion {
+                sums.position.x += other.exact_position().x;
+                sums.position.y += other.exact_position().y;
+                sums.position_count += 1.;
+            }
+        }
+    }
+    sums
+}
+
+#[cfg(feature = "simd")]
+pub fn sum_rules(position: Point2<Real>, neighbours: &[Boid], radii: &Radii) -> RuleSums {
+    let (x, y) = (position.x, position.y);
+    let (mut dodge_x, mut dodge_y) = (Lanes::default(), Lanes::default());
+    let (mut vel_x, mut vel_y, mut vel_n) = (Lanes::default(), Lanes::default(), Lanes::default());
+    let (mut pos_x, mut pos_y, mut pos_n) = (Lanes::default(), Lanes::default(), Lanes::default());
+
+    for batch in neighbours.chunks(LANES) {
+        // Unused lanes sit on the boid itself, which every rule ignores
+        let (mut px, mut py) = ([x; LANES], [y; LANES]);
+        let (mut vx, mut vy) = ([0.; LANES], [0.; LANES]);
+        for (i, other) in batch.iter().enumerate() {
+            let (p, v) = (other.exact_position(), other.exact_velocity());
+            px[i] = p.x;
+            py[i] = p.y;
+            vx[i] = v.x;
+            vy[i] = v.y;
+        }
+
+        for i in 0..LANES {
+            let dx = x - px[i];
+            let dy = y - py[i];
+            let dist_2 = dx * dx + dy * dy;
+            let near = dist_2 > 0.;
+
+            // Pushed away by 1 / distance, along the unit vector from the neighbour
+            let repulse = if near && dist_2 < radii.separation {
+                1. / dist_2
+            } else {
+                0.
+            };
+            dodge_x.0[i] += dx * repulse;
+            dodge_y.0[i] += dy * repulse;
+
+            let aligns = if near && dist_2 < radii.alignment {
+                1.
+            } else {
+                0.
+            };
+            vel_x.0[i] += vx[i] * aligns;
+            vel_y.0[i] += vy[i] * aligns;
+            vel_n.0[i] += aligns;
+
+            let coheres = if near && dist_2 < radii.cohesion {
+                1.
+            } else {
+                0.
+            };
+            pos_x.0[i] += px[i] * coheres;
+            pos_y.0[i] += py[i] * coheres;
+            pos_n.0[i] += coheres;
+        }
+    }
+
+    RuleSums {
+        dodge: Vector2::new(dodge_x.sum(), dodge_y.sum()),
+        velocity: Vector2::new(vel_x.sum(), vel_y.sum()),
+        velocity_count: vel_n.sum(),
+        position: Vector2::new(pos_x.sum(), pos_y.sum()),
+        position_count: pos_n.sum(),
+    }
+}
+
+#[cfg(all(test, feature = "simd"))]
+mod tests {
+    use cgmath::Point2;
+
+    use super::*;
+    use crate::system::tests::config;
+    use crate::system::FlockingSystem;
+
+    fn assert_close(simd: Vector2<Real>, scalar: Vector2<Real>, what: &str) {
+        let error = (simd - scalar).magnitude();
+        let tolerance = 1e-4 * scalar.magnitude().max(1.);
+        assert!(error <= tolerance, "{}: {:?} vs {:?}", what, simd, scalar);
+    }
+
+    #[test]
+    fn simd_matches_scalar() {
+        let radii = Radii {
+            separation: 36.,
+            alignment: 100.,
+            cohesion: 225.,
+        };
+        let mut simulation = FlockingSystem::new(config(400));
+        simulation.randomise();
+        simulation.update();
+        let mut partial_batches = 0;
+        for boid in &simulation.boids()[..100] {
+            let position = boid.exact_position();
+            let point = Point2::new(position.x as f32, position.y as f32);
+            // Always holds the boid itself, which sits exactly on it
+            let neighbours: Vec<Boid> = simulation
+                .boids_within(point, 20.)
+                .into_iter()
+                .cloned()
+                .collect();
+            if neighbours.len() > LANES && !neighbours.len().is_multiple_of(LANES) {
+                partial_batches += 1;
+            }
+            let simd = sum_rules(position, &neighbours, &radii);
+            let scalar = sum_rules_scalar(position, &neighbours, &radii);
+            assert_close(simd.dodge, scalar.dodge, "dodge");
+            assert_close(simd.velocity, scalar.velocity, "velocity");
+            assert_close(simd.position, scalar.position, "position");
+            assert_eq!(simd.velocity_count, scalar.velocity_count);
+            assert_eq!(simd.position_count, scalar.position_count);
+        }
+        assert!(partial_batches > 0);
+    }
+}
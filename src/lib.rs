@@ -1,9 +1,21 @@
 extern crate cgmath;
+extern crate flate2;
 extern crate gl;
 extern crate glutin;
+extern crate png;
 extern crate rand;
+extern crate rayon;
 extern crate toml;
 
+#[cfg(feature = "audio")]
+extern crate cpal;
+#[cfg(feature = "midi")]
+extern crate midir;
+#[cfg(feature = "remote")]
+extern crate serde_json;
+#[cfg(all(unix, not(target_os = "macos")))]
+extern crate x11_dl;
+
 #[macro_use]
 extern crate clap;
 
@@ -15,7 +27,43 @@ pub mod boids;
 pub mod config;
 pub mod system;
 
+mod analysis;
+mod checkpoint;
+mod command;
+mod console;
+mod demo;
+mod desktop;
 mod event;
+mod experiment;
+mod export;
+mod font;
+mod formation;
 mod fps;
 mod glx;
+mod governor;
+mod heatmap;
+mod history;
+mod lanes;
+mod mask;
+mod math;
+#[cfg(feature = "metrics")]
+mod metrics;
+#[cfg(feature = "midi")]
+mod midi;
+mod osc;
+mod overlay;
+mod profiler;
+#[cfg(feature = "remote")]
+mod remote;
 mod render;
+mod replay;
+#[cfg(feature = "audio")]
+mod sonify;
+mod state;
+mod stress;
+mod svg;
+mod sync;
+mod teaching;
+mod timeline;
+mod verify;
+mod video;
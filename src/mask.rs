@@ -0,0 +1,95 @@
+// Black and white masks loaded from PNG images or drawn from text with the built-in font.
+// A PNG pixel is filled if it is dark, or if the image has transparency, if it is opaque.
+
+use std::{fs::File, io};
+
+use crate::font::{glyph, GLYPH_HEIGHT, GLYPH_WIDTH};
+
+pub struct Mask {
+    pub width: usize,
+    pub height: usize,
+    pub filled: Vec<bool>,
+}
+
+impl Mask {
+    pub fn load(path: &str) -> io::Result<Mask> {
+        let mut decoder = png::Decoder::new(File::open(path)?);
+        decoder.set_transformations(png::Transformations::normalize_to_color8());
+        let mut reader = decoder.read_info().map_err(png_error)?;
+        let mut buffer = vec![0; reader.output_buffer_size()];
+        let frame = reader.next_frame(&mut buffer).map_err(png_error)?;
+        let channels = frame.color_type.samples();
+        let pixels: Vec<(u8, u8)> = buffer[..frame.buffer_size()]
+            .chunks(channels)
+            .map(|p| match *p {
+                [luma] => (luma, 255),
+                [luma, alpha] => (luma, alpha),
+                [r, g, b] => (luminance(r, g, b), 255),
+                [r, g, b, alpha] => (luminance(r, g, b), alpha),
+                _ => (255, 255),
+            })
+            .collect();
+
+        let transparent = pixels.iter().any(|&(_, alpha)| alpha < 128);
+        let filled = pixels
+            .iter()
+            .map(|&(luma, alpha)| {
+                if transparent {
+                    alpha >= 128
+                } else {
+                    luma < 128
+                }
+            })
+            .collect();
+        Ok(Mask {
+            width: frame.width as usize,
+            height: frame.height as usize,
+            filled,
+        })
+    }
+
+    // Draws text one pixel per font pixel, lines are separated by newlines
+    pub fn from_text(text: &str) -> Mask {
+        let lines: Vec<&str> = text.lines().collect();
+        let columns = lines.iter().map(|l| l.chars().count()).max().unwrap_or(0);
+        let width = (columns * (GLYPH_WIDTH + 1)).saturating_sub(1);
+        let height = (lines.len() * (GLYPH_HEIGHT + 2)).saturating_sub(2);
+        let mut filled = vec![false; width * height];
+        for (line_index, line) in lines.iter().enumerate() {
+            for (char_index, c) in line.chars().enumerate() {
+                let rows = glyph(c.to_ascii_uppercase());
+                for (row, bits) in rows.iter().enumerate() {
+                    for col in 0..GLYPH_WIDTH {
+                        if bits & (1 << (GLYPH_WIDTH - 1 - col)) != 0 {
+                            let x = char_index * (GLYPH_WIDTH + 1) + col;
+                            let y = line_index * (GLYPH_HEIGHT + 2) + row;
+                            filled[x + y * width] = true;
+                        }
+                    }
+                }
+            }
+        }
+        Mask {
+            width,
+            height,
+            filled,
+        }
+    }
+
+    pub fn filled_pixels(&self) -> impl Iterator<Item = (usize, usize)> + '_ {
+        let width = self.width;
+        self.filled
+            .iter()
+            .enumerate()
+            .filter(|&(_, &filled)| filled)
+            .map(move |(i, _)| (i % width, i / width))
+    }
+}
+
+fn luminance(r: u8, g: u8, b: u8) -> u8 {
+    (0.299 * f32::from(r) + 0.587 * f32::from(g) + 0.114 * f32::from(b)) as u8
+}
+
+fn png_error(err: png::DecodingError) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, err.to_string())
+}
@@ -0,0 +1,444 @@
+// The transcendental functions the simulation uses. The standard library leaves these to the
+// platform's maths library, whose results can differ in the last bit from one machine to the
+// next, so with the `deterministic` feature they're worked out here instead, in f64 using only
+// arithmetic and square roots, which IEEE 754 requires to be exact on every machine.
+
+#[cfg(not(feature = "deterministic"))]
+use crate::system::Real;
+
+#[cfg(not(feature = "deterministic"))]
+pub fn sin_cos(x: Real) -> (Real, Real) {
+    x.sin_cos()
+}
+
+#[cfg(not(feature = "deterministic"))]
+pub fn atan2(y: Real, x: Real) -> Real {
+    y.atan2(x)
+}
+
+#[cfg(not(feature = "deterministic"))]
+pub fn exp(x: Real) -> Real {
+    x.exp()
+}
+
+#[cfg(not(feature = "deterministic"))]
+pub fn powf(x: Real, y: Real) -> Real {
+    x.powf(y)
+}
+
+#[cfg(feature = "deterministic")]
+pub use self::portable::{atan2, exp, powf, sin_cos};
+
+#[cfg(feature = "deterministic")]
+mod portable {
+    use std::f64::consts::{FRAC_PI_2, LN_2, PI};
+
+    use crate::system::Real;
+
+    // Pi over two split in two, so reducing large angles by it loses less precision
+    const FRAC_PI_2_HI: f64 = 1.570_796_326_734_125_6;
+    const FRAC_PI_2_LO: f64 = 6.077_100_506_506_192e-11;
+    // Ln 2 split the same way, for reducing exp's argument
+    const LN_2_HI: f64 = 0.693_147_180_369_123_8;
+    const LN_2_LO: f64 = 1.908_214_929_270_587_7e-10;
+    // Past these, exp overflows or underflows an f64
+    const EXP_MAX: f64 = 709.782_712_893_384;
+    const EXP_MIN: f64 = -745.133_219_101_941_2;
+
+    pub fn sin_cos(x: Real) -> (Real, Real) {
+        let (sin, cos) = sin_cos64(wide(x));
+        (sin as Real, cos as Real)
+    }
+
+    pub fn atan2(y: Real, x: Real) -> Real {
+        atan2_64(wide(y), wide(x)) as Real
+    }
+
+    pub fn exp(x: Real) -> Real {
+        exp64(wide(x)) as Real
+    }
+
+    pub fn powf(x: Real, y: Real) -> Real {
+        powf64(wide(x), wide(y)) as Real
+    }
+
+    #[allow(clippy::useless_conversion)]
+    fn wide(x: Real) -> f64 {
+        f64::from(x)
+    }
+
+    fn sin_cos64(x: f64) -> (f64, f64) {
+        if !x.is_finite() {
+            return (f64::NAN, f64::NAN);
+        }
+        if x == 0. {
+            // Reducing would lose the sign of -0
+            return (x, 1.);
+        }
+        // Reduce to within a quarter turn of zero, keeping track of which quarter it was in
+        let quadrant = (x / FRAC_PI_2).round();
+        let r = (x - quadrant * FRAC_PI_2_HI) - quadrant * FRAC_PI_2_LO;
+        let (s, c) = (sin_series(r), cos_series(r));
+        match (quadrant as i64).rem_euclid(4) {
+            0 => (s, c),
+            1 => (c, -s),
+            2 => (-s, -c),
+            _ => (-c, s),
+        }
+    }
+
+    fn atan2_64(y: f64, x: f64) -> f64 {
+        if y.is_nan() || x.is_nan() {
+            return f64::NAN;
+        }
+        if x == 0. && y == 0. {
+            if x.is_sign_negative() {
+                PI.copysign(y)
+            } else {
+                0f64.copysign(y)
+            }
+        } else if x.abs() >= y.abs() {
+            let a = atan(y / x);
+            if x > 0. {
+                a
+            } else {
+                a + PI.copysign(y)
+            }
+        } else {
+            FRAC_PI_2.copysign(y) - atan(x / y)
+        }
+    }
+
+    fn powf64(x: f64, y: f64) -> f64 {
+        let whole = y.fract() == 0.;
+        if y == 0. || x == 1. || (x == -1. && y.is_infinite()) {
+            1.
+        } else if x.is_nan() || y.is_nan() {
+            f64::NAN
+        } else if x < 0. && !whole && y.is_finite() {
+            // Negative bases only have real powers for whole exponents
+            f64::NAN
+        } else {
+            let power = if x == 0. {
+                if y > 0. {
+                    0.
+                } else {
+                    f64::INFINITY
+                }
+            } else {
+                exp64(y * ln(x.abs()))
+            };
+            // Odd powers keep the sign of the base
+            if whole && (y / 2.).fract() != 0. {
+                power.copysign(x)
+            } else {
+                power
+            }
+        }
+    }
+
+    // Taylor series for |x| up to an eighth of a turn, summed from the smallest term up so
+    // rounding errors stay small
+    fn sin_series(x: f64) -> f64 {
+        let x2 = x * x;
+        let mut sum = 1.;
+        for n in (1..=9).rev() {
+            sum = 1. - x2 / f64::from((2 * n) * (2 * n + 1)) * sum;
+        }
+        x * sum
+    }
+
+    fn cos_series(x: f64) -> f64 {
+        let x2 = x * x;
+        let mut sum = 1.;
+        for n in (1..=9).rev() {
+            sum = 1. - x2 / f64::from((2 * n - 1) * (2 * n)) * sum;
+        }
+        sum
+    }
+
+    // For |x| up to 1, halving the angle twice to bring it under 0.2 so the series converges fast
+    fn atan(x: f64) -> f64 {
+        let mut t = x;
+        for _ in 0..2 {
+            t /= 1. + (1. + t * t).sqrt();
+        }
+        let t2 = t * t;
+        let mut sum = 1. / 25.;
+        for n in (0..12).rev() {
+            sum = 1. / f64::from(2 * n + 1) - t2 * sum;
+        }
+        4. * t * sum
+    }
+
+    // Splits off a power of two, leaving a Taylor series for |r| up to half of ln 2
+    fn exp64(x: f64) -> f64 {
+        if x.is_nan() {
+            return x;
+        }
+        if x > EXP_MAX {
+            return f64::INFINITY;
+        }
+        if x < EXP_MIN {
+            return 0.;
+        }
+        let k = (x / LN_2).round();
+        let r = (x - k * LN_2_HI) - k * LN_2_LO;
+        let mut sum = 1.;
+        for n in (1..=14).rev() {
+            sum = 1. + r / f64::from(n) * sum;
+        }
+        scale_by_power_of_two(sum, k as i32)
+    }
+
+    // Multiplies in two steps so subnormal results and 2^1024 aren't out of reach
+    fn scale_by_power_of_two(x: f64, k: i32) -> f64 {
+        let half = k / 2;
+        x * power_of_two(half) * power_of_two(k - half)
+    }
+
+    fn power_of_two(k: i32) -> f64 {
+        f64::from_bits(((k + 1023) as u64) << 52)
+    }
+
+    // Writes x as m * 2^e with m between 1/sqrt(2) and sqrt(2), then ln m = 2 atanh(s) with
+    // s = (m - 1) / (m + 1), whose series converges quickly as |s| is under 0.18
+    fn ln(x: f64) -> f64 {
+        if x.is_infinite() {
+            return x;
+        }
+        // Bring subnormals up into the normal range first
+        let (x, bias) = if x < f64::MIN_POSITIVE {
+            (x * power_of_two(54), -54)
+        } else {
+            (x, 0)
+        };
+        let bits = x.to_bits();
+        let mut e = ((bits >> 52) & 0x7ff) as i32 - 1023 + bias;
+        let mut m = f64::from_bits((bits & 0x000f_ffff_ffff_ffff) | (1023 << 52));
+        if m > std::f64::consts::SQRT_2 {
+            m /= 2.;
+            e += 1;
+        }
+        let s = (m - 1.) / (m + 1.);
+        let s2 = s * s;
+        let mut sum = 1. / 21.;
+        for n in (0..10).rev() {
+            sum = 1. / f64::from(2 * n + 1) + s2 * sum;
+        }
+        let e = f64::from(e);
+        e * LN_2_HI + (e * LN_2_LO + 2. * s * sum)
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+
+        // Steps between two floats, counting both zeros as one value
+        fn ulps(a: f64, b: f64) -> u64 {
+            let ordered = |x: f64| {
+                let bits = x.to_bits() as i64;
+                if bits < 0 {
+                    i64::MIN - bits
+                } else {
+                    bits
+                }
+            };
+            (ordered(a) - ordered(b)).unsigned_abs()
+        }
+
+        fn assert_close(actual: f64, expected: f64, max_ulps: u64, what: &str) {
+            if expected.is_nan() {
+                assert!(actual.is_nan(), "{}: {} isn't NaN", what, actual);
+                return;
+            }
+            assert!(
+                ulps(actual, expected) <= max_ulps,
+                "{}: {} differs from {} by {} ulps",
+                what,
+                actual,
+                expected,
+                ulps(actual, expected)
+            );
+        }
+
+        // Around a zero of sine or cosine, the error in reducing the angle outweighs the value
+        fn assert_near(actual: f64, expected: f64, what: &str) {
+            let close = ulps(actual, expected) <= 2 || (actual - expected).abs() < 1e-16;
+            assert!(close, "{}: {} differs from {}", what, actual, expected);
+        }
+
+        #[test]
+        fn sin_cos_matches_std() {
+            let mut angles = vec![0., 1e-300, 1e-8, 0.5, 1., 2., 3., 100., 1e3, 1e5, 1e6];
+            // Quadrant boundaries, and either side of them
+            for k in -16..=16 {
+                let boundary = f64::from(k) * FRAC_PI_2 / 2.;
+                angles.extend(&[boundary, next_up(boundary), next_down(boundary)]);
+            }
+            for i in 0..1000 {
+                angles.push(f64::from(i) * 0.0137 - 7.);
+            }
+            for &x in &angles {
+                for &x in &[x, -x] {
+                    let (sin, cos) = sin_cos64(x);
+                    assert_near(sin, x.sin(), &format!("sin {}", x));
+                    assert_near(cos, x.cos(), &format!("cos {}", x));
+                }
+            }
+            assert!(sin_cos64(-0.).0.is_sign_negative());
+            for &x in &[f64::INFINITY, f64::NEG_INFINITY, f64::NAN] {
+                let (sin, cos) = sin_cos64(x);
+                assert!(sin.is_nan() && cos.is_nan());
+            }
+        }
+
+        #[test]
+        fn atan2_matches_std() {
+            let values = [0., 1e-300, 0.3, 1., 1.7, 1e10, f64::INFINITY];
+            for &y in &values {
+                for &x in &values {
+                    for &(y, x) in &[(y, x), (-y, x), (y, -x), (-y, -x)] {
+                        if x.is_infinite() || y.is_infinite() {
+                            continue;
+                        }
+                        let what = format!("atan2({}, {})", y, x);
+                        let angle = atan2_64(y, x);
+                        assert_close(angle, y.atan2(x), 4, &what);
+                        // Signed zeros pick the side of the branch cut, and the sign of zero
+                        assert_eq!(
+                            angle.is_sign_negative(),
+                            y.atan2(x).is_sign_negative(),
+                            "{}",
+                            what
+                        );
+                    }
+                }
+            }
+            for i in 0..360 {
+                let (y, x) = (f64::from(i) * 0.0174).sin_cos();
+                assert_close(
+                    atan2_64(y, x),
+                    y.atan2(x),
+                    4,
+                    &format!("atan2({}, {})", y, x),
+                );
+            }
+            assert!(atan2_64(f64::NAN, 1.).is_nan());
+            assert!(atan2_64(1., f64::NAN).is_nan());
+        }
+
+        #[test]
+        fn exp_matches_std() {
+            let mut values = vec![0., -0., 1e-10, 0.5, 1., -1., 10., -10., 100., -100., 700.];
+            // Either side of overflow and underflow, including subnormal results
+            values.extend(&[709., EXP_MAX, next_up(EXP_MAX), 709.78, 709.79, 710.]);
+            values.extend(&[
+                -708.,
+                -720.,
+                -740.,
+                -745.,
+                EXP_MIN,
+                next_down(EXP_MIN),
+                -746.,
+            ]);
+            for i in 0..1000 {
+                values.push(f64::from(i) * 1.37 - 690.);
+            }
+            for &x in &values {
+                let expected = x.exp();
+                // Subnormal results have fewer bits to get right
+                let max_ulps = if expected < f64::MIN_POSITIVE { 1 } else { 4 };
+                assert_close(exp64(x), expected, max_ulps, &format!("exp {}", x));
+            }
+            assert_eq!(exp64(f64::INFINITY), f64::INFINITY);
+            assert_eq!(exp64(f64::NEG_INFINITY), 0.);
+            assert!(exp64(f64::NAN).is_nan());
+        }
+
+        #[test]
+        fn ln_matches_std() {
+            let mut values = vec![f64::MIN_POSITIVE, f64::MAX, 1e-300, 0.5, 1., 2., 10., 1e300];
+            // Subnormals, down to the smallest
+            values.extend(&[
+                f64::from_bits(1),
+                f64::from_bits(12345),
+                f64::MIN_POSITIVE / 3.,
+            ]);
+            values.extend(&[std::f64::consts::SQRT_2, next_up(std::f64::consts::SQRT_2)]);
+            for i in 1..1000 {
+                values.push(f64::from(i) * 0.0123);
+            }
+            for &x in &values {
+                assert_close(ln(x), x.ln(), 2, &format!("ln {}", x));
+            }
+            assert_eq!(ln(f64::INFINITY), f64::INFINITY);
+        }
+
+        // Powers are worked out as exp(y ln x), which turns an ulp or so of error in y ln x into
+        // an error of up to twice |y ln x| ulps in the result
+        fn powf_ulps(x: f64, y: f64) -> u64 {
+            4 + (2. * y * x.abs().ln()).abs().min(1e6).ceil() as u64
+        }
+
+        #[test]
+        fn powf_matches_std() {
+            let bases = [
+                0.,
+                -0.,
+                0.25,
+                1.,
+                1.5,
+                2.,
+                10.,
+                -1.,
+                -2.,
+                -0.5,
+                f64::INFINITY,
+            ];
+            let exponents = [0., -0., 0.5, 1., 1.5, 2., 3., -1., -1.5, -2., -3., 7.];
+            for &x in &bases {
+                for &y in &exponents {
+                    let what = format!("powf({}, {})", x, y);
+                    let expected = x.powf(y);
+                    assert_close(powf64(x, y), expected, powf_ulps(x, y), &what);
+                    // Odd powers of negative bases and zeros stay negative
+                    if !expected.is_nan() {
+                        let negative = powf64(x, y).is_sign_negative();
+                        assert_eq!(negative, expected.is_sign_negative(), "{}", what);
+                    }
+                }
+            }
+            for &(x, y) in &[
+                (-1., f64::INFINITY),
+                (-2., f64::INFINITY),
+                (-0.5, f64::NEG_INFINITY),
+                (f64::NAN, 0.),
+                (1., f64::NAN),
+                (f64::NAN, 1.),
+            ] {
+                assert_close(powf64(x, y), x.powf(y), 0, &format!("powf({}, {})", x, y));
+            }
+            // The simulation's own uses, for gravity wells and drag
+            for i in 1..1000 {
+                let x = f64::from(i) * 0.37;
+                let what = format!("powf({}, 1.5)", x);
+                assert_close(powf64(x, 1.5), x.powf(1.5), powf_ulps(x, 1.5), &what);
+                let keep = 1. - f64::from(i) / 1000.;
+                assert_close(powf64(keep, 0.25), keep.powf(0.25), 4, "drag");
+            }
+        }
+
+        fn next_up(x: f64) -> f64 {
+            if x >= 0. {
+                f64::from_bits(x.to_bits() + 1)
+            } else {
+                f64::from_bits(x.to_bits() - 1)
+            }
+        }
+
+        fn next_down(x: f64) -> f64 {
+            -next_up(-x)
+        }
+    }
+}
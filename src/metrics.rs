@@ -0,0 +1,127 @@
+// Prometheus metrics endpoint, enabled with the `metrics` feature
+
+use std::{
+    fmt::Write as FmtWrite,
+    io::{self, prelude::*, BufReader},
+    net::{TcpListener, TcpStream},
+    sync::{Arc, Mutex},
+    thread,
+    time::{Duration, Instant},
+};
+
+use crate::system::FlockingSystem;
+
+const REFRESH_MS: u64 = 500;
+
+#[derive(Clone, Copy, Default)]
+struct Snapshot {
+    fps: u32,
+    tick_seconds: f64,
+    boid_count: usize,
+    average_speed: f32,
+    polarization: f32,
+}
+
+pub struct MetricsServer {
+    snapshot: Arc<Mutex<Snapshot>>,
+    refresh_interval: Duration,
+    last_updated: Instant,
+}
+
+impl MetricsServer {
+    pub fn start(address: &str) -> io::Result<MetricsServer> {
+        let listener = TcpListener::bind(address)?;
+        let snapshot = Arc::new(Mutex::new(Snapshot::default()));
+        let server_snapshot = Arc::clone(&snapshot);
+        thread::spawn(move || serve(&listener, &server_snapshot));
+        println!("Serving metrics on http://{}/metrics", address);
+
+        Ok(MetricsServer {
+            snapshot,
+            refresh_interval: Duration::from_millis(REFRESH_MS),
+            last_updated: Instant::now(),
+        })
+    }
+
+    // Flock statistics are O(n), so only refresh them every so often
+    pub fn poll(&mut self, fps: u32, tick: Duration, simulation: &FlockingSystem) {
+        if self.last_updated.elapsed() < self.refresh_interval {
+            return;
+        }
+        self.last_updated = Instant::now();
+        let stats = simulation.stats();
+        let mut snapshot = self.snapshot.lock().unwrap();
+        *snapshot = Snapshot {
+            fps,
+            tick_seconds: tick.as_secs_f64(),
+            boid_count: stats.boid_count,
+            average_speed: stats.average_speed,
+            polarization: stats.polarization,
+        };
+    }
+}
+
+fn serve(listener: &TcpListener, snapshot: &Mutex<Snapshot>) {
+    for stream in listener.incoming() {
+        let result = stream.and_then(|s| respond(s, snapshot));
+        if let Err(err) = result {
+            println!("Metrics request failed: {}", err);
+        }
+    }
+}
+
+fn respond(stream: TcpStream, snapshot: &Mutex<Snapshot>) -> io::Result<()> {
+    let mut reader = BufReader::new(stream.try_clone()?);
+    let mut request_line = String::new();
+    reader.read_line(&mut request_line)?;
+
+    let (status, body) = if request_line.starts_with("GET /metrics ") {
+        let current = *snapshot.lock().unwrap();
+        ("200 OK", exposition(&current))
+    } else {
+        ("404 Not Found", String::new())
+    };
+
+    let mut stream = stream;
+    write!(
+        stream,
+        "HTTP/1.1 {}\r\nContent-Type: text/plain; version=0.0.4\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status,
+        body.len(),
+        body
+    )
+}
+
+fn exposition(s: &Snapshot) -> String {
+    let mut body = String::new();
+    let mut gauge = |name: &str, help: &str, value: f64| {
+        // Writing to a String can't fail
+        let _ = write!(
+            body,
+            "# HELP {0} {1}\n# TYPE {0} gauge\n{0} {2}\n",
+            name, help, value
+        );
+    };
+    gauge("boids_fps", "Frames rendered per second", f64::from(s.fps));
+    gauge(
+        "boids_tick_duration_seconds",
+        "Time taken by the last simulation update",
+        s.tick_seconds,
+    );
+    gauge(
+        "boids_count",
+        "Number of boids simulated",
+        s.boid_count as f64,
+    );
+    gauge(
+        "boids_average_speed",
+        "Mean speed of the flock",
+        f64::from(s.average_speed),
+    );
+    gauge(
+        "boids_polarization",
+        "Alignment of the flock, from 0 (disordered) to 1 (aligned)",
+        f64::from(s.polarization),
+    );
+    body
+}
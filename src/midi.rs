@@ -0,0 +1,69 @@
+// MIDI control input, enabled with the `midi` feature.
+// Control change messages are mapped onto flocking parameters by the `[midi]` config section.
+
+use std::{io, sync::mpsc::Sender};
+
+use midir::{MidiInput, MidiInputConnection};
+
+use crate::boids::MidiConfig;
+use crate::command::Command;
+
+const CLIENT_NAME: &str = "rusty-boids";
+const CONTROL_CHANGE: u8 = 0xB0;
+
+// Dropping this closes the connection
+pub struct MidiListener {
+    _connection: MidiInputConnection<()>,
+}
+
+pub fn start(config: MidiConfig, commands: Sender<Command>) -> io::Result<MidiListener> {
+    let input = MidiInput::new(CLIENT_NAME).map_err(io::Error::other)?;
+
+    let port = input
+        .ports()
+        .into_iter()
+        .find(|p| match (&config.port, input.port_name(p)) {
+            (Some(wanted), Ok(name)) => name.contains(wanted.as_str()),
+            (None, _) => true,
+            _ => false,
+        })
+        .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "no matching MIDI input port"))?;
+    let port_name = input.port_name(&port).map_err(io::Error::other)?;
+
+    let connection = input
+        .connect(
+            &port,
+            CLIENT_NAME,
+            move |_timestamp, message, _| {
+                if let Some(command) = translate(&config, message) {
+                    // Errors mean the simulation has shut down
+                    let _ = commands.send(command);
+                }
+            },
+            (),
+        )
+        .map_err(|err| io::Error::other(err.to_string()))?;
+    println!("Listening for MIDI on '{}'", port_name);
+
+    Ok(MidiListener {
+        _connection: connection,
+    })
+}
+
+fn translate(config: &MidiConfig, message: &[u8]) -> Option<Command> {
+    match *message {
+        [status, controller, value] if status & 0xF0 == CONTROL_CHANGE => {
+            let channel = status & 0x0F;
+            let mapping = config
+                .mappings
+                .iter()
+                .find(|m| m.controller == controller && m.channel.is_none_or(|c| c == channel))?;
+            let (min, max) = mapping.range;
+            Some(Command::Set {
+                parameter: mapping.parameter,
+                value: min + (f32::from(value) / 127.) * (max - min),
+            })
+        }
+        _ => None,
+    }
+}
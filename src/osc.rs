@@ -0,0 +1,248 @@
+// OSC (Open Sound Control) input over UDP.
+// Besides the configured mappings, a few addresses are always understood:
+//   /boids/<parameter> <value>   e.g. /boids/sep_weight 2.0
+//   /boids/mouse <x> <y>         position as a fraction of the window
+//   /boids/pause, /boids/resume, /boids/randomise, /boids/centralise, /boids/zeroise
+
+use std::{convert::TryFrom, io, net::UdpSocket, str, sync::mpsc::Sender, thread};
+
+use crate::boids::OscConfig;
+use crate::command::Command;
+
+const MAX_PACKET_SIZE: usize = 65_507;
+const BUILTIN_PREFIX: &str = "/boids/";
+
+struct Message {
+    address: String,
+    args: Vec<f32>,
+}
+
+pub fn start(config: OscConfig, commands: Sender<Command>) -> io::Result<()> {
+    let socket = UdpSocket::bind(&config.address)?;
+    println!("Listening for OSC messages on {}", config.address);
+    thread::spawn(move || {
+        let mut buf = vec![0; MAX_PACKET_SIZE];
+        loop {
+            let len = match socket.recv(&mut buf) {
+                Ok(len) => len,
+                Err(err) => {
+                    println!("OSC receive failed: {}", err);
+                    continue;
+                }
+            };
+            // Bundles are applied whole or not at all
+            let messages = match parse(&buf[..len]) {
+                Some(messages) => messages,
+                None => {
+                    println!("Ignoring malformed OSC packet");
+                    continue;
+                }
+            };
+            for message in &messages {
+                if let Some(command) = translate(&config, message) {
+                    if commands.send(command).is_err() {
+                        return; // Simulation has shut down
+                    }
+                }
+            }
+        }
+    });
+    Ok(())
+}
+
+fn translate(config: &OscConfig, message: &Message) -> Option<Command> {
+    let args = &message.args;
+    if config.mouse_path.as_ref() == Some(&message.address) && args.len() >= 2 {
+        return Some(Command::Mouse {
+            x: args[0],
+            y: args[1],
+        });
+    }
+
+    if let Some(mapping) = config.mappings.iter().find(|m| m.path == message.address) {
+        let input = *args.first()?;
+        let value = match mapping.range {
+            Some((min, max)) => min + input * (max - min),
+            None => input,
+        };
+        return Some(Command::Set {
+            parameter: mapping.parameter,
+            value,
+        });
+    }
+
+    if !message.address.starts_with(BUILTIN_PREFIX) {
+        return None;
+    }
+    match &message.address[BUILTIN_PREFIX.len()..] {
+        "mouse" if args.len() >= 2 => Some(Command::Mouse {
+            x: args[0],
+            y: args[1],
+        }),
+        "pause" => Some(Command::Pause),
+        "resume" => Some(Command::Resume),
+        "randomise" => Some(Command::Randomise),
+        "centralise" => Some(Command::Centralise),
+        "zeroise" => Some(Command::Zeroise),
+        name => Some(Command::Set {
+            parameter: name.parse().ok()?,
+            value: *args.first()?,
+        }),
+    }
+}
+
+fn parse(packet: &[u8]) -> Option<Vec<Message>> {
+    let mut messages = Vec::new();
+    parse_packet(packet, &mut messages)?;
+    Some(messages)
+}
+
+fn parse_packet(packet: &[u8], messages: &mut Vec<Message>) -> Option<()> {
+    if packet.starts_with(b"#bundle\0") {
+        // Skip the time tag, bundle elements are applied straight away
+        let mut pos = 16;
+        while pos < packet.len() {
+            let size = usize::try_from(read_i32(packet, pos)?).ok()?;
+            pos += 4;
+            parse_packet(packet.get(pos..pos.checked_add(size)?)?, messages)?;
+            pos += size;
+        }
+        Some(())
+    } else {
+        messages.push(parse_message(packet)?);
+        Some(())
+    }
+}
+
+fn parse_message(packet: &[u8]) -> Option<Message> {
+    let (address, mut pos) = read_string(packet, 0)?;
+    let mut args = Vec::new();
+    if pos >= packet.len() {
+        // Messages without a type tag string have no arguments
+        return Some(Message { address, args });
+    }
+
+    let (type_tags, args_start) = read_string(packet, pos)?;
+    pos = args_start;
+    for tag in type_tags.chars().skip(1) {
+        match tag {
+            'f' => {
+                args.push(f32::from_bits(read_i32(packet, pos)? as u32));
+                pos += 4;
+            }
+            'i' => {
+                args.push(read_i32(packet, pos)? as f32);
+                pos += 4;
+            }
+            'd' => {
+                let hi = u64::from(read_i32(packet, pos)? as u32);
+                let lo = u64::from(read_i32(packet, pos + 4)? as u32);
+                args.push(f64::from_bits(hi << 32 | lo) as f32);
+                pos += 8;
+            }
+            'T' => args.push(1.),
+            'F' => args.push(0.),
+            's' => pos = read_string(packet, pos)?.1,
+            _ => return None,
+        }
+    }
+    Some(Message { address, args })
+}
+
+// OSC strings are null terminated and padded to a multiple of 4 bytes
+fn read_string(packet: &[u8], pos: usize) -> Option<(String, usize)> {
+    let rest = packet.get(pos..)?;
+    let len = rest.iter().position(|&b| b == 0)?;
+    let s = str::from_utf8(&rest[..len]).ok()?.to_string();
+    let next = pos + ((len + 4) & !3);
+    Some((s, next))
+}
+
+fn read_i32(packet: &[u8], pos: usize) -> Option<i32> {
+    let bytes = packet.get(pos..pos + 4)?;
+    Some(i32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Pads to a multiple of 4 bytes with at least one null, as OSC strings are
+    fn string(s: &str) -> Vec<u8> {
+        let mut bytes = s.as_bytes().to_vec();
+        bytes.resize((s.len() + 4) & !3, 0);
+        bytes
+    }
+
+    fn message(address: &str, args: &[f32]) -> Vec<u8> {
+        let mut bytes = string(address);
+        bytes.extend(string(&format!(",{}", "f".repeat(args.len()))));
+        for arg in args {
+            bytes.extend_from_slice(&arg.to_bits().to_be_bytes());
+        }
+        bytes
+    }
+
+    fn bundle(elements: &[Vec<u8>]) -> Vec<u8> {
+        let mut bytes = string("#bundle");
+        bytes.extend_from_slice(&1u64.to_be_bytes());
+        for element in elements {
+            bytes.extend_from_slice(&(element.len() as i32).to_be_bytes());
+            bytes.extend_from_slice(element);
+        }
+        bytes
+    }
+
+    #[test]
+    fn single_message() {
+        let messages = parse(&message("/boids/mouse", &[0.25, 0.75])).unwrap();
+        assert_eq!(messages.len(), 1);
+        assert_eq!(messages[0].address, "/boids/mouse");
+        assert_eq!(messages[0].args, vec![0.25, 0.75]);
+    }
+
+    #[test]
+    fn message_without_type_tags() {
+        let messages = parse(&string("/boids/pause")).unwrap();
+        assert_eq!(messages[0].address, "/boids/pause");
+        assert!(messages[0].args.is_empty());
+    }
+
+    #[test]
+    fn nested_bundles() {
+        let inner = bundle(&[message("/boids/sep_weight", &[2.])]);
+        let packet = bundle(&[message("/boids/pause", &[]), inner]);
+        let messages = parse(&packet).unwrap();
+        let addresses: Vec<_> = messages.iter().map(|m| m.address.as_str()).collect();
+        assert_eq!(addresses, ["/boids/pause", "/boids/sep_weight"]);
+        assert_eq!(messages[1].args, vec![2.]);
+    }
+
+    #[test]
+    fn malformed_bundles() {
+        let element = message("/boids/pause", &[]);
+        let mut packet = bundle(std::slice::from_ref(&element));
+        let size_at = 16;
+
+        // Sizes past the end of the packet, negative or large enough to overflow
+        for &size in &[element.len() as i32 + 4, -1, i32::MAX, i32::MIN] {
+            packet[size_at..size_at + 4].copy_from_slice(&size.to_be_bytes());
+            assert!(parse(&packet).is_none(), "size {}", size);
+        }
+
+        // A size cut short
+        let mut packet = bundle(&[]);
+        packet.extend_from_slice(&[0, 0]);
+        assert!(parse(&packet).is_none());
+
+        // A second element cut short, after a good first one
+        let mut packet = bundle(&[element.clone(), element.clone()]);
+        packet.truncate(packet.len() - 2);
+        assert!(parse(&packet).is_none());
+
+        // An unknown type tag
+        let mut packet = string("/boids/pause");
+        packet.extend(string(",x"));
+        assert!(parse(&bundle(&[packet])).is_none());
+    }
+}
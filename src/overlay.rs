@@ -0,0 +1,193 @@
+// Overlay drawn on top of the boids: coloured lines in world coordinates or window pixels and
+// text in window pixels, using a built-in 5x7 bitmap font. Lines and text are queued up during a frame and
+// drawn (and cleared) by `draw`.
+
+use std::{mem, ptr};
+
+use cgmath::{Matrix, Matrix3, Point2, Vector2};
+use gl::{self, types::*};
+
+use crate::font::{glyph, GLYPH_HEIGHT, GLYPH_WIDTH};
+use crate::glx::{self, Buffer, ShaderProgram, VertexArray};
+use crate::render::RendererConfig;
+
+static VS_SRC: &str = "
+    #version 330 core
+    layout (location = 0) in vec2 position;
+    layout (location = 1) in vec3 colour;
+
+    uniform mat3 transform;
+    uniform float pointSize;
+
+    out vec4 vertexColour;
+
+    void main() {
+        vertexColour = vec4(colour, 1.0);
+        gl_PointSize = pointSize;
+        gl_Position = vec4(transform * vec3(position, 1.0), 1.0);
+    }";
+
+static FS_SRC: &str = "
+    #version 330 core
+    out vec4 frag_colour;
+
+    in vec4 vertexColour;
+
+    void main() {
+        frag_colour = vertexColour;
+    }";
+
+// Floats per vertex: x, y, r, g, b
+const VERTEX_SIZE: usize = 5;
+
+pub type Colour = [f32; 3];
+
+pub struct Overlay {
+    world_transform: Matrix3<f32>,
+    screen_transform: Matrix3<f32>,
+    window: (f32, f32),
+    pixel_size: f32,
+    program: ShaderProgram,
+    transform_loc: GLint,
+    point_size_loc: GLint,
+    vao: VertexArray,
+    vbo: Buffer,
+    lines: Vec<f32>,
+    screen_lines: Vec<f32>,
+    text: Vec<f32>,
+}
+
+impl Overlay {
+    // Text pixels are scaled up by `pixel_size` window pixels
+    pub fn new(view: &RendererConfig, window: (f32, f32), pixel_size: f32) -> Overlay {
+        let program = ShaderProgram::new(VS_SRC, FS_SRC).expect("Problem creating shader program");
+        let transform_loc = program
+            .get_uniform_location("transform")
+            .expect("Could not find uniform");
+        let point_size_loc = program
+            .get_uniform_location("pointSize")
+            .expect("Could not find uniform");
+        let overlay = Overlay {
+            world_transform: glx::vtx_transform_2d(view.x, view.y, view.width, view.height),
+            screen_transform: glx::vtx_transform_2d(0., 0., window.0, window.1),
+            window,
+            pixel_size,
+            program,
+            transform_loc,
+            point_size_loc,
+            vao: VertexArray::new(),
+            vbo: Buffer::new(),
+            lines: vec![],
+            screen_lines: vec![],
+            text: vec![],
+        };
+        overlay.init_pipeline();
+        overlay
+    }
+
+    fn init_pipeline(&self) {
+        self.vao.bind();
+        self.vbo.bind(gl::ARRAY_BUFFER);
+        let stride = (VERTEX_SIZE * mem::size_of::<f32>()) as GLsizei;
+        unsafe {
+            gl::EnableVertexAttribArray(0);
+            gl::VertexAttribPointer(0, 2, gl::FLOAT, gl::FALSE, stride, ptr::null());
+            gl::EnableVertexAttribArray(1);
+            gl::VertexAttribPointer(
+                1,
+                3,
+                gl::FLOAT,
+                gl::FALSE,
+                stride,
+                (2 * mem::size_of::<f32>()) as *const GLvoid,
+            );
+        }
+    }
+
+    pub fn resize(&mut self, window: (f32, f32), pixel_size: f32) {
+        self.screen_transform = glx::vtx_transform_2d(0., 0., window.0, window.1);
+        self.window = window;
+        self.pixel_size = pixel_size;
+    }
+
+    pub fn window_size(&self) -> (f32, f32) {
+        self.window
+    }
+
+    pub fn line(&mut self, from: Point2<f32>, vector: Vector2<f32>, colour: Colour) {
+        let to = from + vector;
+        for &(x, y) in &[(from.x, from.y), (to.x, to.y)] {
+            self.lines
+                .extend_from_slice(&[x, y, colour[0], colour[1], colour[2]]);
+        }
+    }
+
+    // Draws a line between two points in window pixels
+    pub fn screen_line(&mut self, from: (f32, f32), to: (f32, f32), colour: Colour) {
+        for &(x, y) in &[from, to] {
+            self.screen_lines
+                .extend_from_slice(&[x, y, colour[0], colour[1], colour[2]]);
+        }
+    }
+
+    // Draws text with its top left corner at (x, y) window pixels, lower case is drawn as upper
+    pub fn text(&mut self, x: f32, y: f32, text: &str, colour: Colour) {
+        let advance = (GLYPH_WIDTH + 1) as f32 * self.pixel_size;
+        for (i, c) in text.chars().enumerate() {
+            let rows = glyph(c.to_ascii_uppercase());
+            for (row, bits) in rows.iter().enumerate() {
+                for col in 0..GLYPH_WIDTH {
+                    if bits & (1 << (GLYPH_WIDTH - 1 - col)) != 0 {
+                        let px = x + i as f32 * advance + (col as f32 + 0.5) * self.pixel_size;
+                        let py = y + (row as f32 + 0.5) * self.pixel_size;
+                        self.text
+                            .extend_from_slice(&[px, py, colour[0], colour[1], colour[2]]);
+                    }
+                }
+            }
+        }
+    }
+
+    pub fn text_width(&self, text: &str) -> f32 {
+        (text.chars().count() * (GLYPH_WIDTH + 1)) as f32 * self.pixel_size
+    }
+
+    // Height of a line of text in window pixels, including spacing
+    pub fn line_height(&self) -> f32 {
+        (GLYPH_HEIGHT + 3) as f32 * self.pixel_size
+    }
+
+    pub fn draw(&mut self) {
+        if self.lines.is_empty() && self.screen_lines.is_empty() && self.text.is_empty() {
+            return;
+        }
+        self.vao.bind();
+        self.vbo.bind(gl::ARRAY_BUFFER);
+        self.program.activate();
+        unsafe {
+            gl::Uniform1f(self.point_size_loc, self.pixel_size);
+        }
+        self.draw_vertices(&self.lines, gl::LINES, &self.world_transform);
+        self.draw_vertices(&self.screen_lines, gl::LINES, &self.screen_transform);
+        self.draw_vertices(&self.text, gl::POINTS, &self.screen_transform);
+        self.lines.clear();
+        self.screen_lines.clear();
+        self.text.clear();
+    }
+
+    fn draw_vertices(&self, vertices: &[f32], mode: GLenum, transform: &Matrix3<f32>) {
+        if vertices.is_empty() {
+            return;
+        }
+        unsafe {
+            gl::UniformMatrix3fv(self.transform_loc, 1, gl::FALSE, transform.as_ptr());
+            gl::BufferData(
+                gl::ARRAY_BUFFER,
+                mem::size_of_val(vertices) as GLsizeiptr,
+                vertices.as_ptr() as *const _,
+                gl::STREAM_DRAW,
+            );
+            gl::DrawArrays(mode, 0, (vertices.len() / VERTEX_SIZE) as GLsizei);
+        }
+    }
+}
@@ -0,0 +1,102 @@
+// Breakdown of where each frame's time goes, drawn over the flock as rolling averages and a
+// graph of the last few seconds. Times are measured on the CPU, so GPU work mostly shows up as
+// time spent waiting to swap buffers.
+
+use std::{collections::VecDeque, time::Duration};
+
+use crate::overlay::{Colour, Overlay};
+
+const SECTIONS: [(&str, Colour); 6] = [
+    ("Neighbours", [0.9, 0.4, 0.3]),
+    ("Forces", [0.9, 0.8, 0.3]),
+    ("Integration", [0.4, 0.9, 0.4]),
+    ("Upload", [0.3, 0.7, 0.9]),
+    ("Draw", [0.6, 0.4, 0.9]),
+    ("Swap", [0.6, 0.6, 0.6]),
+];
+// Weight of the newest frame in the rolling averages
+const SMOOTHING: f32 = 0.05;
+// Frames shown in the graph, one pixel wide each
+const HISTORY: usize = 240;
+// Graph pixels per millisecond, with a line across at the 60 fps budget
+const GRAPH_SCALE: f32 = 4.;
+const FRAME_BUDGET_MS: f32 = 1000. / 60.;
+const TEXT_COLOUR: Colour = [1., 1., 1.];
+
+// Time spent on each section of a frame, in the order of `SECTIONS`
+pub type FrameTimings = [Duration; 6];
+
+pub struct Profiler {
+    visible: bool,
+    averages: [f32; 6],
+    history: VecDeque<[f32; 6]>,
+}
+
+impl Profiler {
+    pub fn new() -> Profiler {
+        Profiler {
+            visible: false,
+            averages: [0.; 6],
+            history: VecDeque::with_capacity(HISTORY),
+        }
+    }
+
+    pub fn toggle(&mut self) {
+        self.visible = !self.visible;
+    }
+
+    pub fn record(&mut self, frame: FrameTimings) {
+        let ms = frame.map(|d| d.as_secs_f32() * 1000.);
+        for (average, &t) in self.averages.iter_mut().zip(ms.iter()) {
+            *average += (t - *average) * SMOOTHING;
+        }
+        if self.history.len() == HISTORY {
+            self.history.pop_front();
+        }
+        self.history.push_back(ms);
+    }
+
+    pub fn draw(&self, overlay: &mut Overlay) {
+        if !self.visible {
+            return;
+        }
+        let line = overlay.line_height();
+        let (width, height) = overlay.window_size();
+
+        // Averages, in the top right corner
+        let total: f32 = self.averages.iter().sum();
+        let mut rows: Vec<(String, Colour)> = SECTIONS
+            .iter()
+            .zip(self.averages.iter())
+            .map(|(&(name, colour), average)| (format!("{} {:.2} ms", name, average), colour))
+            .collect();
+        rows.push((format!("Total {:.2} ms", total), TEXT_COLOUR));
+        let text_width = rows
+            .iter()
+            .map(|(text, _)| overlay.text_width(text))
+            .fold(0., f32::max);
+        for (i, (text, colour)) in rows.iter().enumerate() {
+            overlay.text(
+                width - line - text_width,
+                line * (i + 1) as f32,
+                text,
+                *colour,
+            );
+        }
+
+        // Recent frames stacked section by section, in the bottom right corner
+        let left = width - line - HISTORY as f32;
+        let bottom = height - line;
+        for (i, frame) in self.history.iter().enumerate() {
+            let x = left + i as f32;
+            let mut y = bottom;
+            for (&ms, &(_, colour)) in frame.iter().zip(SECTIONS.iter()) {
+                let top = y - ms * GRAPH_SCALE;
+                overlay.screen_line((x, y), (x, top), colour);
+                y = top;
+            }
+        }
+        let budget = bottom - FRAME_BUDGET_MS * GRAPH_SCALE;
+        overlay.screen_line((left, budget), (left + HISTORY as f32, budget), TEXT_COLOUR);
+    }
+}
@@ -0,0 +1,59 @@
+// Remote control server, enabled with the `remote` feature.
+// Clients send one JSON command per line, e.g. {"command": "set", "parameter": "sep_weight", "value": 2.0}
+// and receive one JSON reply per line.
+
+use std::{
+    io::{self, prelude::*, BufReader},
+    net::{TcpListener, TcpStream},
+    sync::mpsc::Sender,
+    thread,
+};
+
+use crate::command::Command;
+
+pub fn start(address: &str, commands: Sender<Command>) -> io::Result<()> {
+    let listener = TcpListener::bind(address)?;
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            match stream {
+                Ok(stream) => {
+                    let commands = commands.clone();
+                    thread::spawn(move || {
+                        if let Err(err) = handle_client(stream, &commands) {
+                            println!("Remote client disconnected: {}", err);
+                        }
+                    });
+                }
+                Err(err) => println!("Remote connection failed: {}", err),
+            }
+        }
+    });
+    println!("Accepting remote commands on {}", address);
+    Ok(())
+}
+
+fn handle_client(stream: TcpStream, commands: &Sender<Command>) -> io::Result<()> {
+    let reader = BufReader::new(stream.try_clone()?);
+    let mut writer = stream;
+    for line in reader.lines() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let reply = match serde_json::from_str::<Command>(&line) {
+            Ok(command) => {
+                if commands.send(command).is_err() {
+                    // Simulation has shut down
+                    return Ok(());
+                }
+                "{\"ok\":true}".to_string()
+            }
+            Err(err) => format!(
+                "{{\"ok\":false,\"error\":{}}}",
+                serde_json::to_string(&err.to_string()).unwrap()
+            ),
+        };
+        writeln!(writer, "{}", reply)?;
+    }
+    Ok(())
+}
@@ -1,20 +1,32 @@
-use std::{mem, ptr};
+use std::{
+    mem, ptr,
+    time::{Duration, Instant},
+};
 
 use crate::system::Boid;
 use cgmath::{Matrix, Matrix3, Point2};
 use gl::{self, types::*};
 
-use crate::glx::{self, Buffer, ShaderProgram, VertexArray};
+use crate::glx::{self, Buffer, ShaderProgram, Texture, VertexArray};
 
 // Shader sources
-static VS_SRC: &'static str = "
+static VS_SRC: &str = "
     #version 330 core
     layout (location = 0) in vec2 position;
     layout (location = 1) in vec2 velocity;
+    layout (location = 2) in uint id;
+    layout (location = 3) in uint age;
 
     uniform mat3 transform;
     uniform float pointSize;
     uniform float maxSpeedSquared;
+    uniform vec2 speedSizes;
+    uniform float sizeCurve;
+    uniform float speciesSizes[8];
+    uniform uint speciesCount;
+    uniform vec2 positionScale;
+    uniform float velocityScale;
+    uniform float ageRamp;
 
     out vec4 pointColor;
 
@@ -29,16 +41,24 @@ static VS_SRC: &'static str = "
         return c.z * mix(vec3(1.0), rgb, c.y);
     }
 
-    float mag_2 = pow(velocity.x, 2) + pow(velocity.y, 2);
-
-    float a = atan(velocity.y, velocity.x);
     void main() {
-        pointColor = vec4(rgb_from_hsb(vec3(a/two_pi, 1 - (mag_2 / maxSpeedSquared), 1.0)), 1.0);
-        gl_PointSize = pointSize;
-        gl_Position = vec4(transform * vec3(position, 1.0), 1.0);
+        vec2 vel = velocity * velocityScale;
+        float mag_2 = pow(vel.x, 2) + pow(vel.y, 2);
+        float a = atan(vel.y, vel.x);
+        if (ageRamp > 0.0) {
+            // Young boids are bright green, fading through yellow to a dim red as they age
+            float old = min(float(age) / ageRamp, 1.0);
+            pointColor = vec4(rgb_from_hsb(vec3(mix(0.33, 0.0, old), 1.0, mix(1.0, 0.5, old))), 1.0);
+        } else {
+            pointColor = vec4(rgb_from_hsb(vec3(a/two_pi, 1 - (mag_2 / maxSpeedSquared), 1.0)), 1.0);
+        }
+        float speed = pow(clamp(mag_2 / maxSpeedSquared, 0.0, 1.0), 0.5 * sizeCurve);
+        float speedSize = mix(speedSizes.x, speedSizes.y, speed);
+        gl_PointSize = pointSize * speedSize * speciesSizes[id % speciesCount];
+        gl_Position = vec4(transform * vec3(position * positionScale, 1.0), 1.0);
     }";
 
-static FS_SRC: &'static str = "
+static FS_SRC: &str = "
     #version 330 core
     out vec4 frag_colour;
 
@@ -48,23 +68,142 @@ static FS_SRC: &'static str = "
         frag_colour = pointColor;
     }";
 
-//TODO: Handle resizing of screen
+static BACKDROP_VS_SRC: &str = "
+    #version 330 core
+    layout (location = 0) in vec2 position;
+    layout (location = 1) in vec2 texCoord;
+
+    uniform mat3 transform;
+
+    out vec2 uv;
+
+    void main() {
+        uv = texCoord;
+        gl_Position = vec4(transform * vec3(position, 1.0), 1.0);
+    }";
+
+static BACKDROP_FS_SRC: &str = "
+    #version 330 core
+    out vec4 frag_colour;
+
+    in vec2 uv;
+
+    uniform sampler2D mask;
+
+    void main() {
+        frag_colour = vec4(mix(vec3(0.1), vec3(0.35), texture(mask, uv).r), 1.0);
+    }";
+
+// Most species the vertex shader has room for
+const MAX_SPECIES: usize = 8;
+// Boids this far outside the view are still drawn, so ones partly on screen aren't culled
+const CULL_MARGIN: f32 = 10.;
+
 //TODO: How to run at different resolutions
 
+// The region of the world to draw is given by x, y, width & height
 pub struct RendererConfig {
+    pub x: f32,
+    pub y: f32,
     pub width: f32,
     pub height: f32,
     pub boid_size: f32,
     pub max_speed: f32,
+    // Boid size multipliers at rest and at full speed, eased between with a power curve
+    pub speed_sizes: (f32, f32),
+    pub size_curve: f32,
+    // Size multipliers of each species, boids are split between them by id
+    pub species_sizes: Vec<f32>,
+    // Leave the background see through, for windows over the desktop
+    pub transparent: bool,
+    // Skip uploading boids outside the region, worth it when it's a small part of the world
+    pub cull: bool,
+    // Upload boids as normalised shorts relative to the world size and max speed, halving
+    // the bandwidth needed at the cost of some precision
+    pub compact_vertices: bool,
+    pub world: (f32, f32),
+    // Colour boids by age, reaching the end of the ramp at this many ticks, instead of heading
+    pub age_ramp: Option<u32>,
+}
+
+// A boid in the compact vertex format
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct CompactVertex {
+    position: [u16; 2],
+    velocity: [i16; 2],
+    id: u32,
+    age: u32,
+}
+
+impl CompactVertex {
+    fn new(boid: &Boid, (width, height): (f32, f32), max_speed: f32) -> Self {
+        let (p, v) = (boid.position(), boid.velocity());
+        let unsigned = |x: f32| (x.clamp(0., 1.) * f32::from(u16::MAX)).round() as u16;
+        let signed = |x: f32| (x.clamp(-1., 1.) * f32::from(i16::MAX)).round() as i16;
+        CompactVertex {
+            position: [unsigned(p.x / width), unsigned(p.y / height)],
+            velocity: [signed(v.x / max_speed), signed(v.y / max_speed)],
+            id: boid.id(),
+            age: boid.age(),
+        }
+    }
+}
+
+// Boids are uploaded as they are, unless the simulation runs in f64, when they're converted to
+// single precision vertices laid out the same way
+#[cfg(not(feature = "f64"))]
+type Vertex = Boid;
+
+#[cfg(feature = "f64")]
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct Vertex {
+    position: [f32; 2],
+    velocity: [f32; 2],
+    id: u32,
+    // Where boids keep their ticks left perched
+    rest: u32,
+    age: u32,
+}
+
+#[cfg(feature = "f64")]
+impl Vertex {
+    fn new(boid: &Boid) -> Self {
+        let (p, v) = (boid.position(), boid.velocity());
+        Vertex {
+            position: [p.x, p.y],
+            velocity: [v.x, v.y],
+            id: boid.id(),
+            rest: 0,
+            age: boid.age(),
+        }
+    }
 }
 
 pub struct Renderer {
     transform: Matrix3<f32>,
     boid_size: f32,
     max_speed: f32,
+    speed_sizes: (f32, f32),
+    size_curve: f32,
+    species_sizes: Vec<f32>,
+    age_ramp: Option<u32>,
+    transparent: bool,
+    cull_region: Option<(f32, f32, f32, f32)>,
+    visible: Vec<Boid>,
+    // World size, when uploading compact vertices
+    compact_world: Option<(f32, f32)>,
+    compact: Vec<CompactVertex>,
+    #[cfg(feature = "f64")]
+    vertices: Vec<Vertex>,
     program: ShaderProgram,
     vao: VertexArray,
     vbo: Buffer,
+    backdrop: Option<Backdrop>,
+    // Time the last frame spent getting boids to the GPU, and drawing everything else
+    upload_time: Duration,
+    draw_time: Duration,
 }
 
 impl Renderer {
@@ -72,15 +211,59 @@ impl Renderer {
         let program = ShaderProgram::new(VS_SRC, FS_SRC).expect("Problem creating shader program");
 
         Renderer {
-            transform: glx::vtx_transform_2d(config.width, config.height),
+            transform: glx::vtx_transform_2d(config.x, config.y, config.width, config.height),
             boid_size: config.boid_size,
             max_speed: config.max_speed,
+            speed_sizes: config.speed_sizes,
+            size_curve: config.size_curve,
+            species_sizes: config.species_sizes,
+            age_ramp: config.age_ramp,
+            transparent: config.transparent,
+            cull_region: if config.cull {
+                Some((
+                    config.x - CULL_MARGIN,
+                    config.y - CULL_MARGIN,
+                    config.x + config.width + CULL_MARGIN,
+                    config.y + config.height + CULL_MARGIN,
+                ))
+            } else {
+                None
+            },
+            visible: vec![],
+            compact_world: if config.compact_vertices {
+                Some(config.world)
+            } else {
+                None
+            },
+            compact: vec![],
+            #[cfg(feature = "f64")]
+            vertices: vec![],
             program,
             vao: VertexArray::new(),
             vbo: Buffer::new(),
+            backdrop: None,
+            upload_time: Duration::default(),
+            draw_time: Duration::default(),
         }
     }
 
+    // Draws a `width` by `height` mask stretched over the world behind the boids,
+    // where a coverage of 255 is fully covered
+    pub fn set_backdrop(
+        &mut self,
+        world: (f32, f32),
+        width: usize,
+        height: usize,
+        coverage: &[u8],
+    ) {
+        self.backdrop = Some(Backdrop::new(
+            &self.transform,
+            world,
+            (width, height),
+            coverage,
+        ));
+    }
+
     pub fn init_pipeline(&self) {
         unsafe {
             self.vao.bind();
@@ -108,7 +291,83 @@ impl Renderer {
                 .expect("Could not find uniform");
             gl::Uniform1f(max_speed_loc, self.max_speed.powi(2) as GLfloat);
 
-            // Specify the layout of the vertex data
+            // Set how boids are sized
+            let speed_sizes_loc = self
+                .program
+                .get_uniform_location("speedSizes")
+                .expect("Could not find uniform");
+            gl::Uniform2f(speed_sizes_loc, self.speed_sizes.0, self.speed_sizes.1);
+            let curve_loc = self
+                .program
+                .get_uniform_location("sizeCurve")
+                .expect("Could not find uniform");
+            gl::Uniform1f(curve_loc, self.size_curve);
+            let species_sizes: Vec<GLfloat> = if self.species_sizes.is_empty() {
+                vec![1.]
+            } else {
+                self.species_sizes
+                    .iter()
+                    .take(MAX_SPECIES)
+                    .cloned()
+                    .collect()
+            };
+            let species_sizes_loc = self
+                .program
+                .get_uniform_location("speciesSizes")
+                .expect("Could not find uniform");
+            gl::Uniform1fv(
+                species_sizes_loc,
+                species_sizes.len() as GLsizei,
+                species_sizes.as_ptr(),
+            );
+            let species_count_loc = self
+                .program
+                .get_uniform_location("speciesCount")
+                .expect("Could not find uniform");
+            gl::Uniform1ui(species_count_loc, species_sizes.len() as GLuint);
+            let age_ramp_loc = self
+                .program
+                .get_uniform_location("ageRamp")
+                .expect("Could not find uniform");
+            gl::Uniform1f(age_ramp_loc, self.age_ramp.map_or(0., |ramp| ramp as f32));
+
+            // Specify the layout of the vertex data, compact vertices are scaled back up to
+            // world positions and velocities in the shader
+            let (position_scale, velocity_scale) = match self.compact_world {
+                Some(world) => (world, self.max_speed),
+                None => ((1., 1.), 1.),
+            };
+            let position_scale_loc = self
+                .program
+                .get_uniform_location("positionScale")
+                .expect("Could not find uniform");
+            gl::Uniform2f(position_scale_loc, position_scale.0, position_scale.1);
+            let velocity_scale_loc = self
+                .program
+                .get_uniform_location("velocityScale")
+                .expect("Could not find uniform");
+            gl::Uniform1f(velocity_scale_loc, velocity_scale);
+
+            let (stride, position, velocity, id, age) = if self.compact_world.is_some() {
+                (
+                    mem::size_of::<CompactVertex>(),
+                    (gl::UNSIGNED_SHORT, gl::TRUE, 0),
+                    (gl::SHORT, gl::TRUE, 2 * mem::size_of::<u16>()),
+                    4 * mem::size_of::<u16>(),
+                    4 * mem::size_of::<u16>() + mem::size_of::<u32>(),
+                )
+            } else {
+                // After the id and the ticks left perched
+                let point = mem::size_of::<Point2<f32>>();
+                (
+                    mem::size_of::<Vertex>(),
+                    (gl::FLOAT, gl::FALSE, 0),
+                    (gl::FLOAT, gl::FALSE, point),
+                    2 * point,
+                    2 * point + 2 * mem::size_of::<u32>(),
+                )
+            };
+
             let pos_loc = self
                 .program
                 .get_atrib_location("position")
@@ -117,10 +376,10 @@ impl Renderer {
             gl::VertexAttribPointer(
                 pos_loc,
                 2,
-                gl::FLOAT,
-                gl::FALSE,
-                mem::size_of::<Boid>() as GLsizei,
-                ptr::null(),
+                position.0,
+                position.1,
+                stride as GLsizei,
+                position.2 as *const GLvoid,
             );
 
             let vel_loc = self
@@ -131,10 +390,36 @@ impl Renderer {
             gl::VertexAttribPointer(
                 vel_loc,
                 2,
-                gl::FLOAT,
-                gl::FALSE,
-                mem::size_of::<Boid>() as GLsizei,
-                mem::size_of::<Point2<f32>>() as *const GLvoid,
+                velocity.0,
+                velocity.1,
+                stride as GLsizei,
+                velocity.2 as *const GLvoid,
+            );
+
+            let id_loc = self
+                .program
+                .get_atrib_location("id")
+                .expect("could not find id");
+            gl::EnableVertexAttribArray(id_loc);
+            gl::VertexAttribIPointer(
+                id_loc,
+                1,
+                gl::UNSIGNED_INT,
+                stride as GLsizei,
+                id as *const GLvoid,
+            );
+
+            let age_loc = self
+                .program
+                .get_atrib_location("age")
+                .expect("could not find age");
+            gl::EnableVertexAttribArray(age_loc);
+            gl::VertexAttribIPointer(
+                age_loc,
+                1,
+                gl::UNSIGNED_INT,
+                stride as GLsizei,
+                age as *const GLvoid,
             );
 
             // Allow shader to specify point size
@@ -142,20 +427,197 @@ impl Renderer {
         }
     }
 
-    pub fn render(&self, boids: &[Boid]) {
-        glx::clear_screen(0.1, 0.1, 0.1);
+    // Boids are sized in window pixels, so need resizing when the hidpi factor changes
+    pub fn set_boid_size(&mut self, boid_size: f32) {
+        self.boid_size = boid_size;
+        self.program.activate();
+        let size_loc = self
+            .program
+            .get_uniform_location("pointSize")
+            .expect("Could not find uniform");
+        unsafe {
+            gl::Uniform1f(size_loc, boid_size as GLfloat);
+        }
+    }
+
+    pub fn timings(&self) -> (Duration, Duration) {
+        (self.upload_time, self.draw_time)
+    }
+
+    pub fn render(&mut self, boids: &[Boid]) {
+        let start = Instant::now();
+        let boids = match self.cull_region {
+            Some((left, top, right, bottom)) => {
+                self.visible.clear();
+                self.visible.extend(
+                    boids
+                        .iter()
+                        .filter(|b| {
+                            let p = b.position();
+                            p.x >= left && p.x <= right && p.y >= top && p.y <= bottom
+                        })
+                        .cloned(),
+                );
+                &self.visible
+            }
+            None => boids,
+        };
+        let culled = Instant::now();
+        let alpha = if self.transparent { 0. } else { 1. };
+        glx::clear_screen(0.1, 0.1, 0.1, alpha);
+        if let Some(ref backdrop) = self.backdrop {
+            backdrop.draw();
+        }
+        if boids.is_empty() {
+            self.upload_time = culled - start;
+            self.draw_time = culled.elapsed();
+            return;
+        }
+        // Other pipelines may have been bound since the last frame
+        self.vao.bind();
+        self.vbo.bind(gl::ARRAY_BUFFER);
+        self.program.activate();
+        let uploading = Instant::now();
         unsafe {
             // This _should_ implement buffer orphaning
             gl::BufferData(gl::ARRAY_BUFFER, 0, ptr::null(), gl::STREAM_DRAW);
 
+            match self.compact_world {
+                Some(world) => {
+                    let max_speed = self.max_speed;
+                    self.compact.clear();
+                    self.compact.extend(
+                        boids
+                            .iter()
+                            .map(|b| CompactVertex::new(b, world, max_speed)),
+                    );
+                    gl::BufferData(
+                        gl::ARRAY_BUFFER,
+                        mem::size_of_val(&self.compact[..]) as GLsizeiptr,
+                        self.compact.as_ptr() as *const _,
+                        gl::STREAM_DRAW,
+                    );
+                }
+                None => {
+                    #[cfg(not(feature = "f64"))]
+                    let vertices = boids;
+                    #[cfg(feature = "f64")]
+                    let vertices = {
+                        self.vertices.clear();
+                        self.vertices.extend(boids.iter().map(Vertex::new));
+                        &self.vertices[..]
+                    };
+                    gl::BufferData(
+                        gl::ARRAY_BUFFER,
+                        mem::size_of_val(vertices) as GLsizeiptr,
+                        vertices.as_ptr() as *const _,
+                        gl::STREAM_DRAW,
+                    )
+                }
+            }
+            let uploaded = Instant::now();
+
+            gl::DrawArrays(gl::POINTS, 0, boids.len() as i32);
+            self.upload_time = (culled - start) + (uploaded - uploading);
+            self.draw_time = (uploading - culled) + uploaded.elapsed();
+        }
+    }
+}
+
+struct Backdrop {
+    program: ShaderProgram,
+    vao: VertexArray,
+    vbo: Buffer,
+    texture: Texture,
+}
+
+impl Backdrop {
+    fn new(
+        transform: &Matrix3<f32>,
+        (world_width, world_height): (f32, f32),
+        (width, height): (usize, usize),
+        coverage: &[u8],
+    ) -> Backdrop {
+        let program = ShaderProgram::new(BACKDROP_VS_SRC, BACKDROP_FS_SRC)
+            .expect("Problem creating shader program");
+        let backdrop = Backdrop {
+            program,
+            vao: VertexArray::new(),
+            vbo: Buffer::new(),
+            texture: Texture::new(),
+        };
+        // A triangle strip covering the world: x, y, u, v
+        #[rustfmt::skip]
+        let vertices: [GLfloat; 16] = [
+            0., 0., 0., 0.,
+            world_width, 0., 1., 0.,
+            0., world_height, 0., 1.,
+            world_width, world_height, 1., 1.,
+        ];
+        let stride = 4 * mem::size_of::<GLfloat>() as GLsizei;
+        backdrop.vao.bind();
+        backdrop.vbo.bind(gl::ARRAY_BUFFER);
+        backdrop.program.activate();
+        backdrop.texture.bind(gl::TEXTURE_2D);
+        unsafe {
             gl::BufferData(
                 gl::ARRAY_BUFFER,
-                (boids.len() * mem::size_of::<Boid>()) as GLsizeiptr,
-                boids.as_ptr() as *const _,
-                gl::STREAM_DRAW,
+                mem::size_of_val(&vertices) as GLsizeiptr,
+                vertices.as_ptr() as *const _,
+                gl::STATIC_DRAW,
+            );
+            gl::EnableVertexAttribArray(0);
+            gl::VertexAttribPointer(0, 2, gl::FLOAT, gl::FALSE, stride, ptr::null());
+            gl::EnableVertexAttribArray(1);
+            gl::VertexAttribPointer(
+                1,
+                2,
+                gl::FLOAT,
+                gl::FALSE,
+                stride,
+                (2 * mem::size_of::<GLfloat>()) as *const GLvoid,
             );
 
-            gl::DrawArrays(gl::POINTS, 0, boids.len() as i32);
+            let trans_loc = backdrop
+                .program
+                .get_uniform_location("transform")
+                .expect("Could not find uniform");
+            gl::UniformMatrix3fv(trans_loc, 1, gl::FALSE, transform.as_ptr());
+
+            gl::PixelStorei(gl::UNPACK_ALIGNMENT, 1);
+            gl::TexImage2D(
+                gl::TEXTURE_2D,
+                0,
+                gl::R8 as GLint,
+                width as GLsizei,
+                height as GLsizei,
+                0,
+                gl::RED,
+                gl::UNSIGNED_BYTE,
+                coverage.as_ptr() as *const _,
+            );
+            gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MIN_FILTER, gl::LINEAR as GLint);
+            gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MAG_FILTER, gl::NEAREST as GLint);
+            gl::TexParameteri(
+                gl::TEXTURE_2D,
+                gl::TEXTURE_WRAP_S,
+                gl::CLAMP_TO_EDGE as GLint,
+            );
+            gl::TexParameteri(
+                gl::TEXTURE_2D,
+                gl::TEXTURE_WRAP_T,
+                gl::CLAMP_TO_EDGE as GLint,
+            );
+        }
+        backdrop
+    }
+
+    fn draw(&self) {
+        self.vao.bind();
+        self.program.activate();
+        self.texture.bind(gl::TEXTURE_2D);
+        unsafe {
+            gl::DrawArrays(gl::TRIANGLE_STRIP, 0, 4);
         }
     }
 }
@@ -0,0 +1,416 @@
+// Recording and playback of user input.
+// Recordings are plain text, starting with the seed and world size the simulation was run with,
+// and the seconds of history kept to rewind through, if any, followed by one event per line
+// prefixed with the simulation tick it happened on:
+//
+//   seed 1234
+//   world 800 800
+//   rewind 5
+//   12 mouse_move 310.5 204
+//   40 mouse_press
+//
+// Recordings also hold a checksum of the flock after every tick, so a replay can be checked
+// against the run that was recorded:
+//
+//   41 checksum 9c1d63a7e0b2f458
+//
+// Commands from the console, remote control, OSC and MIDI are recorded too, after the events of
+// the tick they were applied on:
+//
+//   41 command set sep_weight 2.5
+//   41 command spawn 20 400 300
+
+use std::{
+    fs::File,
+    io::{self, prelude::*, BufReader, BufWriter},
+};
+
+use crate::command::Command;
+use crate::event::BoidControlEvent;
+
+pub struct Recorder<W: Write> {
+    writer: W,
+}
+
+impl Recorder<BufWriter<File>> {
+    pub fn create(path: &str, seed: u64, width: f32, height: f32) -> io::Result<Self> {
+        Recorder::new(BufWriter::new(File::create(path)?), seed, width, height)
+    }
+}
+
+impl<W: Write> Recorder<W> {
+    pub fn new(mut writer: W, seed: u64, width: f32, height: f32) -> io::Result<Self> {
+        writeln!(writer, "seed {}", seed)?;
+        writeln!(writer, "world {} {}", width, height)?;
+        Ok(Recorder { writer })
+    }
+
+    // Stepping back replays differently depending on how much history there is
+    pub fn rewind(&mut self, seconds: f32) -> io::Result<()> {
+        writeln!(self.writer, "rewind {}", seconds)
+    }
+
+    pub fn writer(&mut self) -> &mut W {
+        &mut self.writer
+    }
+
+    pub fn record(&mut self, tick: u64, event: &BoidControlEvent) -> io::Result<()> {
+        match *event {
+            BoidControlEvent::MouseMove(x, y) => {
+                writeln!(self.writer, "{} mouse_move {} {}", tick, x, y)
+            }
+            ref other => writeln!(self.writer, "{} {}", tick, event_name(other)),
+        }
+    }
+
+    pub fn command(&mut self, tick: u64, command: &Command) -> io::Result<()> {
+        match *command {
+            Command::Set { parameter, value } => {
+                writeln!(
+                    self.writer,
+                    "{} command set {} {}",
+                    tick,
+                    parameter.name(),
+                    value
+                )
+            }
+            Command::Spawn { count, position } => match position {
+                Some((x, y)) => {
+                    writeln!(self.writer, "{} command spawn {} {} {}", tick, count, x, y)
+                }
+                None => writeln!(self.writer, "{} command spawn {}", tick, count),
+            },
+            Command::Mouse { x, y } => writeln!(self.writer, "{} command mouse {} {}", tick, x, y),
+            // Saving doesn't change the flock, and shouldn't write files again on playback
+            Command::Save { .. } => Ok(()),
+            ref other => writeln!(self.writer, "{} command {}", tick, command_name(other)),
+        }
+    }
+
+    pub fn checksum(&mut self, tick: u64, checksum: u64) -> io::Result<()> {
+        writeln!(self.writer, "{} checksum {:016x}", tick, checksum)
+    }
+}
+
+pub enum Line {
+    Blank,
+    Seed(u64),
+    World(f32, f32),
+    Rewind(f32),
+    Event(u64, BoidControlEvent),
+    Command(u64, Command),
+    Checksum(u64, u64),
+}
+
+// Input to replay, in the order it was recorded
+#[derive(Clone, Debug, PartialEq)]
+pub enum Input {
+    Event(BoidControlEvent),
+    Command(Command),
+}
+
+pub fn parse_line(line: &str) -> Option<Line> {
+    let fields: Vec<&str> = line.split_whitespace().collect();
+    match fields.as_slice() {
+        [] => Some(Line::Blank),
+        ["seed", s] => s.parse().ok().map(Line::Seed),
+        ["world", w, h] => parse_pair(w, h).map(|(w, h)| Line::World(w, h)),
+        ["rewind", s] => s.parse().ok().map(Line::Rewind),
+        [tick, "checksum", c] => Some(Line::Checksum(
+            tick.parse().ok()?,
+            u64::from_str_radix(c, 16).ok()?,
+        )),
+        [tick, "command", rest @ ..] => {
+            Some(Line::Command(tick.parse().ok()?, parse_command(rest)?))
+        }
+        [tick, rest @ ..] => Some(Line::Event(tick.parse().ok()?, parse_event(rest)?)),
+    }
+}
+
+pub struct Player {
+    seed: u64,
+    world: (f32, f32),
+    rewind: Option<f32>,
+    events: Vec<(u64, Input)>,
+    next_event: usize,
+    checksums: Vec<(u64, u64)>,
+}
+
+impl Player {
+    pub fn open(path: &str) -> io::Result<Player> {
+        Player::read(BufReader::new(File::open(path)?))
+    }
+
+    fn read<R: BufRead>(reader: R) -> io::Result<Player> {
+        let mut seed = None;
+        let mut world = None;
+        let mut rewind = None;
+        let mut events = vec![];
+        let mut checksums = vec![];
+        for (number, line) in reader.lines().enumerate() {
+            let line = line?;
+            match parse_line(&line) {
+                Some(Line::Blank) => (),
+                Some(Line::Seed(s)) => seed = Some(s),
+                Some(Line::World(w, h)) => world = Some((w, h)),
+                Some(Line::Rewind(s)) => rewind = Some(s),
+                Some(Line::Event(tick, event)) => events.push((tick, Input::Event(event))),
+                Some(Line::Command(tick, command)) => events.push((tick, Input::Command(command))),
+                Some(Line::Checksum(tick, checksum)) => checksums.push((tick, checksum)),
+                None => return Err(invalid(&format!("bad line {}: '{}'", number + 1, line))),
+            }
+        }
+
+        Ok(Player {
+            seed: seed.ok_or_else(|| invalid("recording has no seed"))?,
+            world: world.ok_or_else(|| invalid("recording has no world size"))?,
+            rewind,
+            events,
+            next_event: 0,
+            checksums,
+        })
+    }
+
+    pub fn seed(&self) -> u64 {
+        self.seed
+    }
+
+    pub fn world(&self) -> (f32, f32) {
+        self.world
+    }
+
+    // Seconds of history the recording was made with, if rewinding was on
+    pub fn rewind(&self) -> Option<f32> {
+        self.rewind
+    }
+
+    // The flock's checksum after each recorded tick, in tick order
+    pub fn checksums(&self) -> &[(u64, u64)] {
+        &self.checksums
+    }
+
+    // Yields the recorded input that happened up to and including `tick`
+    pub fn replay<F>(&mut self, tick: u64, mut handler: F)
+    where
+        F: FnMut(Input),
+    {
+        while let Some(&(event_tick, ref input)) = self.events.get(self.next_event) {
+            if event_tick > tick {
+                break;
+            }
+            handler(input.clone());
+            self.next_event += 1;
+        }
+    }
+}
+
+fn event_name(event: &BoidControlEvent) -> &'static str {
+    match *event {
+        BoidControlEvent::Stop => "stop",
+        BoidControlEvent::Pause => "pause",
+        BoidControlEvent::Randomise => "randomise",
+        BoidControlEvent::Zeroise => "zeroise",
+        BoidControlEvent::Centralise => "centralise",
+        BoidControlEvent::MousePress => "mouse_press",
+        BoidControlEvent::MouseRelease => "mouse_release",
+        BoidControlEvent::MouseMove(..) => "mouse_move",
+        BoidControlEvent::ToggleFormation => "toggle_formation",
+        BoidControlEvent::ToggleProfiler => "toggle_profiler",
+        BoidControlEvent::StepBack => "step_back",
+        BoidControlEvent::StepForward => "step_forward",
+    }
+}
+
+fn parse_event(fields: &[&str]) -> Option<BoidControlEvent> {
+    match *fields {
+        ["stop"] => Some(BoidControlEvent::Stop),
+        ["pause"] => Some(BoidControlEvent::Pause),
+        ["randomise"] => Some(BoidControlEvent::Randomise),
+        ["zeroise"] => Some(BoidControlEvent::Zeroise),
+        ["centralise"] => Some(BoidControlEvent::Centralise),
+        ["mouse_press"] => Some(BoidControlEvent::MousePress),
+        ["mouse_release"] => Some(BoidControlEvent::MouseRelease),
+        ["toggle_formation"] => Some(BoidControlEvent::ToggleFormation),
+        ["toggle_profiler"] => Some(BoidControlEvent::ToggleProfiler),
+        ["step_back"] => Some(BoidControlEvent::StepBack),
+        ["step_forward"] => Some(BoidControlEvent::StepForward),
+        ["mouse_move", x, y] => parse_pair(x, y).map(|(x, y)| BoidControlEvent::MouseMove(x, y)),
+        _ => None,
+    }
+}
+
+fn command_name(command: &Command) -> &'static str {
+    match *command {
+        Command::Set { .. } => "set",
+        Command::Pause => "pause",
+        Command::Resume => "resume",
+        Command::Randomise => "randomise",
+        Command::Centralise => "centralise",
+        Command::Zeroise => "zeroise",
+        Command::Form => "form",
+        Command::Disperse => "disperse",
+        Command::Spawn { .. } => "spawn",
+        Command::Mouse { .. } => "mouse",
+        Command::Save { .. } => "save",
+    }
+}
+
+fn parse_command(fields: &[&str]) -> Option<Command> {
+    match *fields {
+        ["set", parameter, value] => Some(Command::Set {
+            parameter: parameter.parse().ok()?,
+            value: value.parse().ok()?,
+        }),
+        ["pause"] => Some(Command::Pause),
+        ["resume"] => Some(Command::Resume),
+        ["randomise"] => Some(Command::Randomise),
+        ["centralise"] => Some(Command::Centralise),
+        ["zeroise"] => Some(Command::Zeroise),
+        ["form"] => Some(Command::Form),
+        ["disperse"] => Some(Command::Disperse),
+        ["spawn", count] => Some(Command::Spawn {
+            count: count.parse().ok()?,
+            position: None,
+        }),
+        ["spawn", count, x, y] => Some(Command::Spawn {
+            count: count.parse().ok()?,
+            position: Some(parse_pair(x, y)?),
+        }),
+        ["mouse", x, y] => parse_pair(x, y).map(|(x, y)| Command::Mouse { x, y }),
+        _ => None,
+    }
+}
+
+fn parse_pair(a: &str, b: &str) -> Option<(f32, f32)> {
+    Some((a.parse().ok()?, b.parse().ok()?))
+}
+
+pub fn invalid(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::system::Parameter;
+
+    fn events() -> Vec<BoidControlEvent> {
+        vec![
+            BoidControlEvent::Stop,
+            BoidControlEvent::Pause,
+            BoidControlEvent::Randomise,
+            BoidControlEvent::Zeroise,
+            BoidControlEvent::Centralise,
+            BoidControlEvent::MouseMove(310.5, -0.125),
+            BoidControlEvent::MousePress,
+            BoidControlEvent::MouseRelease,
+            BoidControlEvent::ToggleFormation,
+            BoidControlEvent::ToggleProfiler,
+            BoidControlEvent::StepBack,
+            BoidControlEvent::StepForward,
+        ]
+    }
+
+    fn commands() -> Vec<Command> {
+        vec![
+            Command::Set {
+                parameter: Parameter::SepWeight,
+                value: 0.1,
+            },
+            Command::Pause,
+            Command::Resume,
+            Command::Randomise,
+            Command::Centralise,
+            Command::Zeroise,
+            Command::Form,
+            Command::Disperse,
+            Command::Spawn {
+                count: 20,
+                position: None,
+            },
+            Command::Spawn {
+                count: 1,
+                position: Some((400.25, 1e-3)),
+            },
+            Command::Mouse { x: 0.3, y: 1. / 3. },
+        ]
+    }
+
+    #[test]
+    fn round_trip() {
+        let mut recorder = Recorder::new(vec![], 1234, 800., 600.5).unwrap();
+        recorder.rewind(2.5).unwrap();
+        let mut expected = vec![];
+        for (tick, event) in events().into_iter().enumerate() {
+            recorder.record(tick as u64, &event).unwrap();
+            expected.push((tick as u64, Input::Event(event)));
+        }
+        for (tick, command) in commands().into_iter().enumerate() {
+            recorder.command(tick as u64 + 100, &command).unwrap();
+            expected.push((tick as u64 + 100, Input::Command(command)));
+        }
+        recorder.checksum(7, 0x9c1d_63a7_e0b2_f458).unwrap();
+        recorder.checksum(u64::MAX, 0).unwrap();
+
+        let player = Player::read(&recorder.writer()[..]).unwrap();
+        assert_eq!(player.seed(), 1234);
+        assert_eq!(player.world(), (800., 600.5));
+        assert_eq!(player.rewind(), Some(2.5));
+        assert_eq!(player.events, expected);
+        assert_eq!(
+            player.checksums(),
+            &[(7, 0x9c1d_63a7_e0b2_f458), (u64::MAX, 0)]
+        );
+    }
+
+    #[test]
+    fn saves_are_not_recorded() {
+        let mut recorder = Recorder::new(vec![], 1, 10., 10.).unwrap();
+        let save = Command::Save {
+            path: "state.bin".to_string(),
+        };
+        recorder.command(3, &save).unwrap();
+        let player = Player::read(&recorder.writer()[..]).unwrap();
+        assert!(player.events.is_empty());
+    }
+
+    #[test]
+    fn replays_up_to_the_tick() {
+        let recording = "seed 1\nworld 10 10\n\n2 pause\n2 command form\n5 mouse_press\n";
+        let mut player = Player::read(recording.as_bytes()).unwrap();
+        let mut inputs = vec![];
+        player.replay(1, |input| inputs.push(input));
+        assert!(inputs.is_empty());
+        player.replay(4, |input| inputs.push(input));
+        assert_eq!(
+            inputs,
+            [
+                Input::Event(BoidControlEvent::Pause),
+                Input::Command(Command::Form)
+            ]
+        );
+        player.replay(5, |input| inputs.push(input));
+        assert_eq!(inputs[2], Input::Event(BoidControlEvent::MousePress));
+    }
+
+    #[test]
+    fn bad_recordings() {
+        for recording in &[
+            "world 10 10\n",
+            "seed 1\n",
+            "seed 1\nworld 10 10\n3 fly_away\n",
+            "seed 1\nworld 10 10\nthree pause\n",
+            "seed 1\nworld 10 10\n3 command set no_such_parameter 1\n",
+            "seed 1\nworld 10 10\n3 command spawn 1 2\n",
+            "seed 1\nworld 10 10\n3 checksum xyz\n",
+        ] {
+            let err = Player::read(recording.as_bytes()).err();
+            assert_eq!(
+                err.map(|e| e.kind()),
+                Some(io::ErrorKind::InvalidData),
+                "{:?}",
+                recording
+            );
+        }
+    }
+}
@@ -0,0 +1,196 @@
+// Sonification of the flock, enabled with the `audio` feature.
+// A small additive synth plays a drone whose pitch follows the flock's average speed, whose
+// brightness follows its polarization, and which gains a voice of a chord for each cluster.
+
+use std::{
+    f32::consts::PI,
+    io,
+    sync::{
+        atomic::{AtomicU32, Ordering},
+        Arc,
+    },
+};
+
+use cpal::{
+    traits::{DeviceTrait, HostTrait, StreamTrait},
+    FromSample, SampleFormat, SizedSample, Stream, StreamConfig,
+};
+
+use crate::system::{FlockingSystem, Parameter};
+
+// Ticks between measurements of the flock
+const POLL_INTERVAL: u64 = 6;
+// Boids closer than this are in the same cluster, measured on a grid of this cell size
+const CLUSTER_CELL: f32 = 20.;
+// Cells with fewer boids than this don't count towards clusters
+const CLUSTER_MIN_BOIDS: u32 = 3;
+const BASE_FREQUENCY: f32 = 110.;
+// Frequency ratios of the chord voices added for each cluster
+const VOICE_RATIOS: [f32; 6] = [1., 1.5, 2., 2.5, 3., 4.];
+const VOLUME: f32 = 0.08;
+// Per sample smoothing of synth parameters, to avoid clicks
+const SMOOTHING: f32 = 0.0005;
+
+// Synth parameters as f32 bits, shared with the audio thread
+#[derive(Default)]
+struct SynthParams {
+    speed: AtomicU32,
+    polarization: AtomicU32,
+    voices: AtomicU32,
+}
+
+impl SynthParams {
+    fn load(value: &AtomicU32) -> f32 {
+        f32::from_bits(value.load(Ordering::Relaxed))
+    }
+
+    fn store(value: &AtomicU32, v: f32) {
+        value.store(v.to_bits(), Ordering::Relaxed);
+    }
+}
+
+// Dropping this stops the sound
+pub struct Sonifier {
+    params: Arc<SynthParams>,
+    last_polled: Option<u64>,
+    _stream: Stream,
+}
+
+pub fn start() -> io::Result<Sonifier> {
+    let device = cpal::default_host()
+        .default_output_device()
+        .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "no audio output device"))?;
+    let supported = device
+        .default_output_config()
+        .map_err(|err| io::Error::other(err.to_string()))?;
+    let params = Arc::new(SynthParams::default());
+    let config = supported.config();
+    let stream = match supported.sample_format() {
+        SampleFormat::F32 => build_stream::<f32>(&device, &config, params.clone()),
+        SampleFormat::I16 => build_stream::<i16>(&device, &config, params.clone()),
+        SampleFormat::U16 => build_stream::<u16>(&device, &config, params.clone()),
+        format => {
+            let msg = format!("unsupported audio sample format {}", format);
+            return Err(io::Error::other(msg));
+        }
+    }
+    .map_err(|err| io::Error::other(err.to_string()))?;
+    stream
+        .play()
+        .map_err(|err| io::Error::other(err.to_string()))?;
+    println!(
+        "Sonifying the flock on '{}'",
+        device.name().unwrap_or_default()
+    );
+
+    Ok(Sonifier {
+        params,
+        last_polled: None,
+        _stream: stream,
+    })
+}
+
+impl Sonifier {
+    pub fn poll(&mut self, simulation: &FlockingSystem) {
+        let tick = simulation.tick();
+        if matches!(self.last_polled, Some(last) if tick < last + POLL_INTERVAL) {
+            return;
+        }
+        self.last_polled = Some(tick);
+        let stats = simulation.stats();
+        let max_speed = simulation.parameter(Parameter::MaxSpeed).max(f32::EPSILON);
+        let voices = count_clusters(simulation).clamp(1, VOICE_RATIOS.len()) as f32;
+        SynthParams::store(&self.params.speed, stats.average_speed / max_speed);
+        SynthParams::store(&self.params.polarization, stats.polarization);
+        SynthParams::store(&self.params.voices, voices);
+    }
+}
+
+fn build_stream<T>(
+    device: &cpal::Device,
+    config: &StreamConfig,
+    params: Arc<SynthParams>,
+) -> Result<Stream, cpal::BuildStreamError>
+where
+    T: SizedSample + FromSample<f32>,
+{
+    let sample_rate = config.sample_rate.0 as f32;
+    let channels = config.channels as usize;
+    let mut phases = [0f32; 6];
+    let (mut speed, mut brightness, mut voices) = (0f32, 0f32, 1f32);
+    device.build_output_stream(
+        config,
+        move |data: &mut [T], _| {
+            let target_speed = SynthParams::load(&params.speed);
+            let target_brightness = SynthParams::load(&params.polarization);
+            let target_voices = SynthParams::load(&params.voices);
+            for frame in data.chunks_mut(channels) {
+                speed += (target_speed - speed) * SMOOTHING;
+                brightness += (target_brightness - brightness) * SMOOTHING;
+                voices += (target_voices - voices) * SMOOTHING;
+
+                let frequency = BASE_FREQUENCY * 2f32.powf(2. * speed.clamp(0., 1.));
+                let mut sample = 0.;
+                for (voice, (phase, ratio)) in
+                    phases.iter_mut().zip(VOICE_RATIOS.iter()).enumerate()
+                {
+                    let gain = (voices - voice as f32).clamp(0., 1.);
+                    *phase = (*phase + frequency * ratio / sample_rate).fract();
+                    let p = 2. * PI * *phase;
+                    let overtones =
+                        0.5 * (2. * p).sin() + 0.33 * (3. * p).sin() + 0.25 * (4. * p).sin();
+                    sample += gain * (p.sin() + brightness * overtones);
+                }
+                let value = T::from_sample(sample * VOLUME);
+                for out in frame.iter_mut() {
+                    *out = value;
+                }
+            }
+        },
+        |err| println!("Audio output error: {}", err),
+        None,
+    )
+}
+
+// Counts groups of busy grid cells that touch, wrapping around the edges of the world
+fn count_clusters(simulation: &FlockingSystem) -> usize {
+    let (width, height) = simulation.dimensions();
+    let columns = ((width / CLUSTER_CELL).ceil() as usize).max(1);
+    let rows = ((height / CLUSTER_CELL).ceil() as usize).max(1);
+    let mut counts = vec![0u32; columns * rows];
+    for boid in simulation.boids() {
+        let p = boid.position();
+        let column = ((p.x / CLUSTER_CELL) as usize).min(columns - 1);
+        let row = ((p.y / CLUSTER_CELL) as usize).min(rows - 1);
+        counts[column + row * columns] += 1;
+    }
+
+    let mut visited = vec![false; columns * rows];
+    let mut clusters = 0;
+    let mut stack = vec![];
+    for start in 0..counts.len() {
+        if visited[start] || counts[start] < CLUSTER_MIN_BOIDS {
+            continue;
+        }
+        clusters += 1;
+        visited[start] = true;
+        stack.push(start);
+        while let Some(cell) = stack.pop() {
+            let (column, row) = (cell % columns, cell / columns);
+            let neighbours = [
+                ((column + 1) % columns, row),
+                ((column + columns - 1) % columns, row),
+                (column, (row + 1) % rows),
+                (column, (row + rows - 1) % rows),
+            ];
+            for &(c, r) in &neighbours {
+                let next = c + r * columns;
+                if !visited[next] && counts[next] >= CLUSTER_MIN_BOIDS {
+                    visited[next] = true;
+                    stack.push(next);
+                }
+            }
+        }
+    }
+    clusters
+}
@@ -0,0 +1,209 @@
+// Saving and loading the state of the flock.
+// Saved states are binary: a magic header, the tick, the boid count and then each boid's
+// position and velocity as little endian f32s. CSV files with `x`, `y`, `vx` and `vy` columns
+// (such as trajectory exports) can also be loaded, in which case the last tick present is used.
+// Either may be gzip compressed, as checkpoints are.
+
+use std::{
+    fs::File,
+    io::{self, prelude::*, BufReader, BufWriter},
+    path::Path,
+};
+
+use cgmath::{Point2, Vector2};
+use flate2::{bufread::GzDecoder, write::GzEncoder, Compression};
+
+use crate::system::FlockingSystem;
+
+const MAGIC: &[u8; 8] = b"BOIDS\0\0\x01";
+const GZIP_MAGIC: &[u8; 2] = &[0x1f, 0x8b];
+// Boids to make room for up front, the count comes from the file and may be corrupt
+const MAX_PREALLOCATED: u64 = 1 << 20;
+
+pub struct SavedState {
+    pub tick: u64,
+    pub boids: Vec<(Point2<f32>, Vector2<f32>)>,
+}
+
+impl SavedState {
+    pub fn capture(simulation: &FlockingSystem) -> SavedState {
+        SavedState {
+            tick: simulation.tick(),
+            boids: simulation
+                .boids()
+                .iter()
+                .map(|b| (b.position(), b.velocity()))
+                .collect(),
+        }
+    }
+}
+
+pub fn save(path: &str, simulation: &FlockingSystem) -> io::Result<()> {
+    let mut writer = BufWriter::new(File::create(path)?);
+    write_state(&mut writer, &SavedState::capture(simulation))?;
+    writer.flush()
+}
+
+pub fn write_compressed(path: &Path, state: &SavedState) -> io::Result<()> {
+    let mut encoder = GzEncoder::new(BufWriter::new(File::create(path)?), Compression::fast());
+    write_state(&mut encoder, state)?;
+    let mut writer = encoder.finish()?;
+    writer.flush()?;
+    writer.get_ref().sync_all()
+}
+
+pub fn load<P: AsRef<Path>>(path: P) -> io::Result<SavedState> {
+    let mut reader = BufReader::new(File::open(path)?);
+    if reader.fill_buf()?.starts_with(GZIP_MAGIC) {
+        read_any(BufReader::new(GzDecoder::new(reader)))
+    } else {
+        read_any(reader)
+    }
+}
+
+fn read_any<R: BufRead>(mut reader: R) -> io::Result<SavedState> {
+    if reader.fill_buf()?.starts_with(MAGIC) {
+        read_state(&mut reader)
+    } else {
+        read_csv(reader)
+    }
+}
+
+pub fn write_state<W: Write>(writer: &mut W, state: &SavedState) -> io::Result<()> {
+    writer.write_all(MAGIC)?;
+    writer.write_all(&state.tick.to_le_bytes())?;
+    writer.write_all(&(state.boids.len() as u64).to_le_bytes())?;
+    for &(p, v) in &state.boids {
+        for value in &[p.x, p.y, v.x, v.y] {
+            writer.write_all(&value.to_le_bytes())?;
+        }
+    }
+    Ok(())
+}
+
+pub fn read_state<R: Read>(reader: &mut R) -> io::Result<SavedState> {
+    let mut magic = [0; 8];
+    reader.read_exact(&mut magic)?;
+    if &magic != MAGIC {
+        return Err(invalid("not a saved boid state"));
+    }
+    let tick = read_u64(reader)?;
+    let count = read_u64(reader)?;
+    let mut boids = Vec::with_capacity(count.min(MAX_PREALLOCATED) as usize);
+    for _ in 0..count {
+        let mut values = [0.; 4];
+        for value in &mut values {
+            let mut bytes = [0; 4];
+            reader.read_exact(&mut bytes)?;
+            *value = f32::from_le_bytes(bytes);
+        }
+        boids.push((
+            Point2::new(values[0], values[1]),
+            Vector2::new(values[2], values[3]),
+        ));
+    }
+    Ok(SavedState { tick, boids })
+}
+
+fn read_u64<R: Read>(reader: &mut R) -> io::Result<u64> {
+    let mut bytes = [0; 8];
+    reader.read_exact(&mut bytes)?;
+    Ok(u64::from_le_bytes(bytes))
+}
+
+fn read_csv<R: BufRead>(reader: R) -> io::Result<SavedState> {
+    let mut lines = reader.lines();
+    let header = lines.next().ok_or_else(|| invalid("empty state file"))??;
+    let columns: Vec<&str> = header.split(',').map(str::trim).collect();
+    let column = |name: &str| {
+        columns
+            .iter()
+            .position(|&c| c == name)
+            .ok_or_else(|| invalid(&format!("state file has no '{}' column", name)))
+    };
+    let tick_column = column("tick").ok();
+    let (x, y, vx, vy) = (column("x")?, column("y")?, column("vx")?, column("vy")?);
+
+    let mut state = SavedState {
+        tick: 0,
+        boids: vec![],
+    };
+    for (number, line) in lines.enumerate() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let bad_line = || invalid(&format!("bad line {}: '{}'", number + 2, line));
+        let fields: Vec<f64> = line
+            .split(',')
+            .map(|f| f.trim().parse())
+            .collect::<Result<_, _>>()
+            .map_err(|_| bad_line())?;
+        let field = |i: usize| fields.get(i).cloned().ok_or_else(bad_line);
+
+        // Only keep the latest tick of a trajectory export
+        if let Some(t) = tick_column {
+            let tick = field(t)? as u64;
+            if tick != state.tick {
+                state.tick = tick;
+                state.boids.clear();
+            }
+        }
+        state.boids.push((
+            Point2::new(field(x)? as f32, field(y)? as f32),
+            Vector2::new(field(vx)? as f32, field(vy)? as f32),
+        ));
+    }
+    Ok(state)
+}
+
+fn invalid(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn state() -> SavedState {
+        SavedState {
+            tick: 42,
+            boids: vec![
+                (Point2::new(1., 2.), Vector2::new(0.5, -0.25)),
+                (Point2::new(799.5, 0.), Vector2::new(-2.5, 1e-7)),
+            ],
+        }
+    }
+
+    #[test]
+    fn round_trip() {
+        let mut bytes = vec![];
+        write_state(&mut bytes, &state()).unwrap();
+        let read = read_state(&mut &bytes[..]).unwrap();
+        assert_eq!(read.tick, 42);
+        assert_eq!(read.boids, state().boids);
+    }
+
+    #[test]
+    fn corrupt_count() {
+        let mut bytes = vec![];
+        write_state(&mut bytes, &state()).unwrap();
+        bytes[16..24].copy_from_slice(&u64::MAX.to_le_bytes());
+        let err = read_state(&mut &bytes[..]).err().unwrap();
+        assert_eq!(err.kind(), io::ErrorKind::UnexpectedEof);
+    }
+
+    #[test]
+    fn truncated() {
+        let mut bytes = vec![];
+        write_state(&mut bytes, &state()).unwrap();
+        bytes.truncate(bytes.len() - 3);
+        assert!(read_state(&mut &bytes[..]).is_err());
+    }
+
+    #[test]
+    fn not_a_state() {
+        let err = read_state(&mut &b"x,y,vx,vy\n"[..]).err().unwrap();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    }
+}
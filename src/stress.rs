@@ -0,0 +1,77 @@
+// Stress test that keeps adding boids until frames take longer than a budget, then reports the
+// largest flock that was sustained. Vsync is disabled while it runs so frame times are real.
+
+use std::time::{Duration, Instant};
+
+use crate::system::FlockingSystem;
+
+// Frames to let settle after spawning boids, then frames to average over
+const SETTLE_FRAMES: u32 = 10;
+const SAMPLE_FRAMES: u32 = 60;
+const MIN_STEP: u32 = 250;
+
+pub struct StressRamp {
+    budget: Duration,
+    last_frame: Instant,
+    frames: u32,
+    frame_time: Duration,
+    sustained: Option<usize>,
+}
+
+impl StressRamp {
+    pub fn new(budget_ms: f32) -> StressRamp {
+        println!(
+            "Adding boids until frames take longer than {} ms",
+            budget_ms
+        );
+        StressRamp {
+            // Budgets too long to represent are never exceeded anyway
+            budget: Duration::try_from_secs_f32(budget_ms / 1000.).unwrap_or(Duration::MAX),
+            last_frame: Instant::now(),
+            frames: 0,
+            frame_time: Duration::new(0, 0),
+            sustained: None,
+        }
+    }
+
+    // Call once a frame, returns false when the test has finished
+    pub fn poll(&mut self, simulation: &mut FlockingSystem) -> bool {
+        let elapsed = self.last_frame.elapsed();
+        self.last_frame = Instant::now();
+        self.frames += 1;
+        if self.frames <= SETTLE_FRAMES {
+            return true;
+        }
+        self.frame_time += elapsed;
+        if self.frames < SETTLE_FRAMES + SAMPLE_FRAMES {
+            return true;
+        }
+
+        let count = simulation.boids().len();
+        let average = self.frame_time / SAMPLE_FRAMES;
+        println!(
+            "{} boids: {:.2} ms/frame",
+            count,
+            average.as_secs_f64() * 1000.
+        );
+        self.frames = 0;
+        self.frame_time = Duration::new(0, 0);
+        if average > self.budget {
+            return false;
+        }
+        self.sustained = Some(count);
+        simulation.spawn((count as u32 / 10).max(MIN_STEP), None);
+        true
+    }
+
+    pub fn report(&self) {
+        match self.sustained {
+            Some(count) => println!(
+                "Maximum sustainable boid count: {} ({} ms frame budget)",
+                count,
+                self.budget.as_secs_f64() * 1000.
+            ),
+            None => println!("The starting flock is already over the frame budget"),
+        }
+    }
+}
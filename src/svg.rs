@@ -0,0 +1,98 @@
+// Exports boid trails as an SVG of polylines, written at the end of a run.
+// Only the first `max_boids` boids are traced to keep files a reasonable size, and a trail is
+// split into a new polyline whenever its boid wraps around an edge of the world.
+
+use std::{
+    fs::File,
+    io::{self, prelude::*, BufWriter},
+};
+
+use crate::system::FlockingSystem;
+
+pub struct TrailWriter {
+    path: String,
+    interval: u64,
+    max_boids: usize,
+    last_sampled: Option<u64>,
+    // Polylines of each traced boid, indexed by boid id
+    trails: Vec<Vec<Vec<(f32, f32)>>>,
+}
+
+impl TrailWriter {
+    pub fn new(path: &str, interval: u64, max_boids: usize) -> TrailWriter {
+        TrailWriter {
+            path: path.to_string(),
+            interval: interval.max(1),
+            max_boids,
+            last_sampled: None,
+            trails: vec![],
+        }
+    }
+
+    pub fn poll(&mut self, simulation: &FlockingSystem) {
+        let tick = simulation.tick();
+        if !tick.is_multiple_of(self.interval) || self.last_sampled == Some(tick) {
+            return;
+        }
+        self.last_sampled = Some(tick);
+        let (width, height) = simulation.dimensions();
+        let traced = self.max_boids.min(simulation.boids().len());
+        self.trails.resize(traced, vec![]);
+        for boid in simulation.boids() {
+            let id = boid.id() as usize;
+            if id >= traced {
+                continue;
+            }
+            let p = boid.position();
+            let polylines = &mut self.trails[id];
+            let wrapped = match polylines.last().and_then(|l| l.last()) {
+                Some(&(x, y)) => (p.x - x).abs() > width / 2. || (p.y - y).abs() > height / 2.,
+                None => true,
+            };
+            if wrapped {
+                polylines.push(vec![]);
+            }
+            polylines.last_mut().unwrap().push((p.x, p.y));
+        }
+    }
+
+    pub fn finish(self, simulation: &FlockingSystem) -> io::Result<()> {
+        let (width, height) = simulation.dimensions();
+        let mut writer = BufWriter::new(File::create(&self.path)?);
+        writeln!(
+            writer,
+            r#"<svg xmlns="http://www.w3.org/2000/svg" width="{w}" height="{h}" viewBox="0 0 {w} {h}">"#,
+            w = width,
+            h = height
+        )?;
+        writeln!(
+            writer,
+            r##"<rect width="100%" height="100%" fill="#1a1a1a"/>"##
+        )?;
+        writeln!(
+            writer,
+            r#"<g fill="none" stroke-width="0.5" stroke-linecap="round" stroke-linejoin="round">"#
+        )?;
+        let traced = self.trails.len().max(1);
+        for (id, polylines) in self.trails.iter().enumerate() {
+            let hue = id * 360 / traced;
+            for line in polylines.iter().filter(|l| l.len() > 1) {
+                write!(
+                    writer,
+                    r#"<polyline stroke="hsl({},80%,60%)" points=""#,
+                    hue
+                )?;
+                for (i, &(x, y)) in line.iter().enumerate() {
+                    let separator = if i == 0 { "" } else { " " };
+                    write!(writer, "{}{:.1},{:.1}", separator, x, y)?;
+                }
+                writeln!(writer, r#""/>"#)?;
+            }
+        }
+        writeln!(writer, "</g>")?;
+        writeln!(writer, "</svg>")?;
+        writer.flush()?;
+        println!("Wrote boid trails to {}", self.path);
+        Ok(())
+    }
+}
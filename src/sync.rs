@@ -0,0 +1,164 @@
+// Lockstep sync between instances, e.g. for video walls driven by several machines.
+// The master sends its seed and world size, then the input and commands applied on every frame
+// using the input recording format, with each frame terminated by a `frame` line holding the
+// master's tick. Followers simulate the same world and advance one frame for every frame the
+// master sends, stopping if they find themselves on a different tick.
+
+use std::{
+    io::{self, prelude::*, BufReader},
+    net::{TcpListener, TcpStream},
+};
+
+use crate::command::Command;
+use crate::event::BoidControlEvent;
+use crate::replay::{self, Input, Line, Recorder};
+
+const FRAME_END: &str = "frame";
+
+pub struct SyncMaster {
+    recorder: Recorder<Broadcast>,
+}
+
+impl SyncMaster {
+    // Blocks until all the followers have connected, so they all start from the same state
+    pub fn serve(
+        address: &str,
+        followers: usize,
+        seed: u64,
+        width: f32,
+        height: f32,
+    ) -> io::Result<SyncMaster> {
+        let listener = TcpListener::bind(address)?;
+        let mut streams = Vec::with_capacity(followers);
+        while streams.len() < followers {
+            println!(
+                "Waiting for sync followers on {} ({}/{})",
+                address,
+                streams.len(),
+                followers
+            );
+            let (stream, peer) = listener.accept()?;
+            stream.set_nodelay(true)?;
+            println!("Sync follower connected from {}", peer);
+            streams.push(stream);
+        }
+        let broadcast = Broadcast {
+            streams,
+            buffer: vec![],
+        };
+        let recorder = Recorder::new(broadcast, seed, width, height)?;
+        Ok(SyncMaster { recorder })
+    }
+
+    pub fn send(&mut self, tick: u64, event: &BoidControlEvent) -> io::Result<()> {
+        self.recorder.record(tick, event)
+    }
+
+    pub fn send_command(&mut self, tick: u64, command: &Command) -> io::Result<()> {
+        self.recorder.command(tick, command)
+    }
+
+    pub fn end_frame(&mut self, tick: u64) -> io::Result<()> {
+        let writer = self.recorder.writer();
+        writeln!(writer, "{} {}", FRAME_END, tick)?;
+        writer.flush()
+    }
+}
+
+pub struct SyncFollower {
+    reader: BufReader<TcpStream>,
+    seed: u64,
+    world: (f32, f32),
+    line: String,
+}
+
+impl SyncFollower {
+    pub fn connect(address: &str) -> io::Result<SyncFollower> {
+        let stream = TcpStream::connect(address)?;
+        stream.set_nodelay(true)?;
+        let mut follower = SyncFollower {
+            reader: BufReader::new(stream),
+            seed: 0,
+            world: (0., 0.),
+            line: String::new(),
+        };
+        match (follower.read_line()?, follower.read_line()?) {
+            (Some(Line::Seed(seed)), Some(Line::World(w, h))) => {
+                follower.seed = seed;
+                follower.world = (w, h);
+                Ok(follower)
+            }
+            _ => Err(replay::invalid("sync master sent an unexpected header")),
+        }
+    }
+
+    pub fn seed(&self) -> u64 {
+        self.seed
+    }
+
+    pub fn world(&self) -> (f32, f32) {
+        self.world
+    }
+
+    // Blocks until the master finishes its next frame, returns false if the master has gone away.
+    // Fails if the master ended the frame on a different tick to the follower's `tick`.
+    pub fn next_frame<F>(&mut self, tick: u64, mut handler: F) -> io::Result<bool>
+    where
+        F: FnMut(Input),
+    {
+        loop {
+            self.line.clear();
+            if self.reader.read_line(&mut self.line)? == 0 {
+                return Ok(false);
+            }
+            if let Some(master_tick) = self.line.trim().strip_prefix(FRAME_END) {
+                return match master_tick.trim().parse::<u64>() {
+                    Ok(t) if t == tick => Ok(true),
+                    Ok(t) => Err(replay::invalid(&format!(
+                        "out of sync, the master is on tick {} but this follower is on tick {}",
+                        t, tick
+                    ))),
+                    Err(_) => Err(replay::invalid("sync master sent a bad frame")),
+                };
+            }
+            match replay::parse_line(&self.line) {
+                Some(Line::Event(_, event)) => handler(Input::Event(event)),
+                Some(Line::Command(_, command)) => handler(Input::Command(command)),
+                Some(_) => (),
+                None => return Err(replay::invalid("sync master sent a bad event")),
+            }
+        }
+    }
+
+    fn read_line(&mut self) -> io::Result<Option<Line>> {
+        self.line.clear();
+        self.reader.read_line(&mut self.line)?;
+        Ok(replay::parse_line(&self.line))
+    }
+}
+
+// Buffers a frame and sends it to every follower on flush, dropping any that disconnect
+struct Broadcast {
+    streams: Vec<TcpStream>,
+    buffer: Vec<u8>,
+}
+
+impl Write for Broadcast {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.buffer.extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        let buffer = &self.buffer;
+        self.streams.retain(|mut s| match s.write_all(buffer) {
+            Ok(()) => true,
+            Err(err) => {
+                println!("Dropping sync follower: {}", err);
+                false
+            }
+        });
+        self.buffer.clear();
+        Ok(())
+    }
+}
@@ -1,21 +1,74 @@
+#[cfg(not(feature = "f64"))]
 use std::f32::consts::PI;
+#[cfg(feature = "f64")]
+use std::f64::consts::PI;
+use std::{
+    cmp::Ordering,
+    mem,
+    str::FromStr,
+    time::{Duration, Instant},
+};
 
-use cgmath::{Basis2, InnerSpace, Point2, Rad, Rotation, Rotation2, Vector2};
+use cgmath::{EuclideanSpace, InnerSpace, Point2, Vector2};
 use rand::{
-    self,
     distributions::{IndependentSample, Range},
-    ThreadRng,
+    Isaac64Rng, SeedableRng,
 };
+use rayon::prelude::*;
+
+use crate::lanes::{self, Radii};
+use crate::math;
 
-type Position = Point2<f32>;
-type Velocity = Vector2<f32>;
-type Force = Vector2<f32>;
+// Precision the simulation runs in, everything outside it works in f32
+#[cfg(not(feature = "f64"))]
+pub(crate) type Real = f32;
+#[cfg(feature = "f64")]
+pub(crate) type Real = f64;
 
-const TWO_PI: f32 = 2. * PI;
+pub(crate) type Position = Point2<Real>;
+pub(crate) type Velocity = Vector2<Real>;
+type Force = Vector2<Real>;
+
+const TWO_PI: Real = 2. * PI;
+// Distance from its target at which a forming boid starts to slow down
+const ARRIVE_RADIUS: Real = 40.;
+// Distance from an obstacle at which boids start to steer away from it
+const AVOID_RADIUS: Real = 30.;
+// Ticks in a burst and coast cycle, and how many of them are spent bursting
+const BURST_PERIOD: u64 = 40;
+const BURST_LENGTH: u64 = 8;
+// Bursts aim a little over cruising speed, closing this fraction of the gap each tick
+const BURST_OVERSHOOT: Real = 1.2;
+const BURST_THRUST: Real = 0.5;
+// Scatters boids' ids over the cycle
+const BURST_SPREAD: u32 = 2_654_435_761;
+// Number of grid cells in the neighbourhood lookup tables
+const NEIGHBOURHOOD_SIZE: usize = 10;
+// Boids move at most this fraction of the smallest rule radius in one step, so they can't pass
+// through each other or thin walls between looking around
+const MAX_STEP_FRACTION: Real = 0.5;
+const MAX_SUBSTEPS: u32 = 8;
+// How close people get to a destination before heading to the next one
+const DESTINATION_RADIUS: Real = 20.;
+// How much people react to others behind them, compared to those ahead
+const ANISOTROPY: Real = 0.5;
+// How far ahead boids look when following a path, and points sampled per span of a smoothed path
+const PATH_LOOKAHEAD: Real = 20.;
+const SPLINE_SAMPLES: usize = 8;
+// Ticks are taken to be a frame at 60 fps when turning rates in seconds into rates per tick
+const TICKS_PER_SECOND: Real = 60.;
+// Smallest world, in either direction, that boids can be spread over
+const MIN_WORLD_SIZE: f32 = 1.;
+// Boids are launched at random speeds up to the max, which needs a range to pick from
+const MIN_MAX_SPEED: Real = 0.001;
+// 64 bit FNV-1a, for checksums of the flock
+const FNV_OFFSET: u64 = 0xcbf2_9ce4_8422_2325;
+const FNV_PRIME: u64 = 0x0100_0000_01b3;
 const SHELL_GAPS: [usize; 9] = [1750, 701, 301, 132, 57, 23, 10, 4, 1];
 
 pub struct FlockingConfig {
     pub boid_count: u32,
+    pub seed: u64,
     pub width: f32,
     pub height: f32,
     pub max_speed: f32,
@@ -27,87 +80,504 @@ pub struct FlockingConfig {
     pub sep_radius: f32,
     pub ali_radius: f32,
     pub coh_radius: f32,
+    pub target_weight: f32,
+    pub obstacle_weight: f32,
+    pub drag: f32,
+    pub cruise_speed: f32,
+    pub perch_chance: f32,
+    pub perch_time: f32,
+    // Fastest boids can turn, in degrees per second (0 for no limit)
+    pub max_turn_rate: f32,
+    // Skin added to neighbour search radius when reusing neighbour lists (0 to use the grid)
+    pub verlet_skin: f32,
+    pub boundary: Boundary,
+}
+
+// How the mouse pushes boids around
+#[derive(Clone, Copy, Debug, PartialEq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum MouseForce {
+    // Straight away from the cursor, or towards it while the button is held
+    Push,
+    // Around the cursor as well, in a whirlpool
+    Vortex,
+}
+
+// What happens to boids that fly off the edge of the world
+#[derive(Clone, Copy, Debug, PartialEq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Boundary {
+    // Come back in on the opposite edge
+    Wrap,
+    // Bounce back in off the edge
+    Reflect,
+    // Stop at the edge, still moving along it
+    Slide,
+    // Start again from a random point on a random edge
+    Respawn,
+}
+
+impl FromStr for Boundary {
+    type Err = String;
+
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        match name {
+            "wrap" => Ok(Boundary::Wrap),
+            "reflect" => Ok(Boundary::Reflect),
+            "slide" => Ok(Boundary::Slide),
+            "respawn" => Ok(Boundary::Respawn),
+            _ => Err(format!("unknown boundary '{}'", name)),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Parameter {
+    MaxSpeed,
+    MaxForce,
+    MouseWeight,
+    SepWeight,
+    AliWeight,
+    CohWeight,
+    SepRadius,
+    AliRadius,
+    CohRadius,
+    TargetWeight,
+    ObstacleWeight,
+    Drag,
+    CruiseSpeed,
+    PerchChance,
+    PerchTime,
+    MaxTurnRate,
+}
+
+impl FromStr for Parameter {
+    type Err = String;
+
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        match name {
+            "max_speed" => Ok(Parameter::MaxSpeed),
+            "max_force" => Ok(Parameter::MaxForce),
+            "mouse_weight" => Ok(Parameter::MouseWeight),
+            "sep_weight" => Ok(Parameter::SepWeight),
+            "ali_weight" => Ok(Parameter::AliWeight),
+            "coh_weight" => Ok(Parameter::CohWeight),
+            "sep_radius" => Ok(Parameter::SepRadius),
+            "ali_radius" => Ok(Parameter::AliRadius),
+            "coh_radius" => Ok(Parameter::CohRadius),
+            "target_weight" => Ok(Parameter::TargetWeight),
+            "obstacle_weight" => Ok(Parameter::ObstacleWeight),
+            "drag" => Ok(Parameter::Drag),
+            "cruise_speed" => Ok(Parameter::CruiseSpeed),
+            "perch_chance" => Ok(Parameter::PerchChance),
+            "perch_time" => Ok(Parameter::PerchTime),
+            "max_turn_rate" => Ok(Parameter::MaxTurnRate),
+            _ => Err(format!("unknown parameter '{}'", name)),
+        }
+    }
+}
+
+impl Parameter {
+    // The name it goes by in configs and commands
+    pub fn name(self) -> &'static str {
+        match self {
+            Parameter::MaxSpeed => "max_speed",
+            Parameter::MaxForce => "max_force",
+            Parameter::MouseWeight => "mouse_weight",
+            Parameter::SepWeight => "sep_weight",
+            Parameter::AliWeight => "ali_weight",
+            Parameter::CohWeight => "coh_weight",
+            Parameter::SepRadius => "sep_radius",
+            Parameter::AliRadius => "ali_radius",
+            Parameter::CohRadius => "coh_radius",
+            Parameter::TargetWeight => "target_weight",
+            Parameter::ObstacleWeight => "obstacle_weight",
+            Parameter::Drag => "drag",
+            Parameter::CruiseSpeed => "cruise_speed",
+            Parameter::PerchChance => "perch_chance",
+            Parameter::PerchTime => "perch_time",
+            Parameter::MaxTurnRate => "max_turn_rate",
+        }
+    }
 }
 
 struct FlockingConstants {
-    max_speed: f32,
-    max_force: f32,
-    mouse_weight: f32,
-    sep_radius_2: f32,
-    ali_radius_2: f32,
-    coh_radius_2: f32,
-    sep_weight: f32,
-    ali_weight: f32,
-    coh_weight: f32,
+    max_speed: Real,
+    max_force: Real,
+    mouse_weight: Real,
+    sep_radius_2: Real,
+    ali_radius_2: Real,
+    coh_radius_2: Real,
+    sep_weight: Real,
+    ali_weight: Real,
+    coh_weight: Real,
+    target_weight: Real,
+    obstacle_weight: Real,
+    drag: Real,
+    cruise_speed: Real,
+    perch_chance: Real,
+    perch_time: Real,
+    max_turn_rate: Real,
 }
 
 impl FlockingConstants {
+    fn neighbour_radius(&self) -> Real {
+        self.sep_radius_2
+            .max(self.ali_radius_2)
+            .max(self.coh_radius_2)
+            .sqrt()
+    }
+
+    // Steps each tick is split into, more when boids are fast for their smallest radius
+    fn substeps(&self) -> u32 {
+        let smallest = self
+            .sep_radius_2
+            .min(self.ali_radius_2)
+            .min(self.coh_radius_2)
+            .sqrt();
+        if smallest <= 0. {
+            return 1;
+        }
+        let steps = (self.max_speed / (MAX_STEP_FRACTION * smallest)).ceil();
+        (steps as u32).clamp(1, MAX_SUBSTEPS)
+    }
+
     fn from_config(conf: FlockingConfig) -> Self {
         FlockingConstants {
-            max_speed: conf.max_speed,
-            max_force: conf.max_force,
-            mouse_weight: conf.mouse_weight,
-            sep_radius_2: conf.sep_radius.powi(2),
-            ali_radius_2: conf.ali_radius.powi(2),
-            coh_radius_2: conf.coh_radius.powi(2),
-            sep_weight: conf.sep_weight,
-            ali_weight: conf.ali_weight,
-            coh_weight: conf.coh_weight,
+            max_speed: real(conf.max_speed).max(MIN_MAX_SPEED),
+            max_force: real(conf.max_force),
+            mouse_weight: real(conf.mouse_weight),
+            sep_radius_2: real(conf.sep_radius).powi(2),
+            ali_radius_2: real(conf.ali_radius).powi(2),
+            coh_radius_2: real(conf.coh_radius).powi(2),
+            sep_weight: real(conf.sep_weight),
+            ali_weight: real(conf.ali_weight),
+            coh_weight: real(conf.coh_weight),
+            target_weight: real(conf.target_weight),
+            obstacle_weight: real(conf.obstacle_weight),
+            drag: real(conf.drag),
+            cruise_speed: real(conf.cruise_speed),
+            perch_chance: real(conf.perch_chance),
+            perch_time: real(conf.perch_time),
+            max_turn_rate: real(conf.max_turn_rate),
+        }
+    }
+
+    fn get(&self, param: Parameter) -> Real {
+        match param {
+            Parameter::MaxSpeed => self.max_speed,
+            Parameter::MaxForce => self.max_force,
+            Parameter::MouseWeight => self.mouse_weight,
+            Parameter::SepWeight => self.sep_weight,
+            Parameter::AliWeight => self.ali_weight,
+            Parameter::CohWeight => self.coh_weight,
+            Parameter::SepRadius => self.sep_radius_2.sqrt(),
+            Parameter::AliRadius => self.ali_radius_2.sqrt(),
+            Parameter::CohRadius => self.coh_radius_2.sqrt(),
+            Parameter::TargetWeight => self.target_weight,
+            Parameter::ObstacleWeight => self.obstacle_weight,
+            Parameter::Drag => self.drag,
+            Parameter::CruiseSpeed => self.cruise_speed,
+            Parameter::PerchChance => self.perch_chance,
+            Parameter::PerchTime => self.perch_time,
+            Parameter::MaxTurnRate => self.max_turn_rate,
+        }
+    }
+
+    fn set(&mut self, param: Parameter, value: Real) {
+        match param {
+            Parameter::MaxSpeed => self.max_speed = value.max(MIN_MAX_SPEED),
+            Parameter::MaxForce => self.max_force = value,
+            Parameter::MouseWeight => self.mouse_weight = value,
+            Parameter::SepWeight => self.sep_weight = value,
+            Parameter::AliWeight => self.ali_weight = value,
+            Parameter::CohWeight => self.coh_weight = value,
+            Parameter::SepRadius => self.sep_radius_2 = value.powi(2),
+            Parameter::AliRadius => self.ali_radius_2 = value.powi(2),
+            Parameter::CohRadius => self.coh_radius_2 = value.powi(2),
+            Parameter::TargetWeight => self.target_weight = value,
+            Parameter::ObstacleWeight => self.obstacle_weight = value,
+            Parameter::Drag => self.drag = value,
+            Parameter::CruiseSpeed => self.cruise_speed = value,
+            Parameter::PerchChance => self.perch_chance = value,
+            Parameter::PerchTime => self.perch_time = value,
+            Parameter::MaxTurnRate => self.max_turn_rate = value,
         }
     }
 }
 
+pub struct Steering {
+    pub separation: Vector2<f32>,
+    pub alignment: Vector2<f32>,
+    pub cohesion: Vector2<f32>,
+}
+
+// Steering in the simulation's own precision
+struct Rules {
+    separation: Force,
+    alignment: Force,
+    cohesion: Force,
+}
+
+// Time spent in each part of the updates since the timings were last taken
+#[derive(Clone, Copy, Default)]
+pub struct UpdateTimings {
+    pub neighbours: Duration,
+    pub forces: Duration,
+    pub integration: Duration,
+}
+
+pub struct FlockStats {
+    pub boid_count: usize,
+    pub average_speed: f32,
+    pub polarization: f32,
+}
+
+// Everything about the flock that changes as it moves, to go back to with `rewind`. Parameters
+// and input aren't included, so a rewound flock carries on with the current ones.
+pub struct Snapshot {
+    tick: u64,
+    dim_x: usize,
+    dim_y: usize,
+    boid_grid: Vec<Boid>,
+    rng: Isaac64Rng,
+    forming: bool,
+    verlet: Option<VerletLists>,
+    emitted: Vec<(f32, u32)>,
+    startles: Vec<(Position, u64)>,
+    destinations: Vec<usize>,
+    expiry: Vec<Option<(usize, u64)>>,
+}
+
+// Adds boids at a point, `rate` of them a tick, heading `direction` degrees clockwise from
+// straight up give or take half of `spread`, at `speed` or else the max speed. Boids from an
+// emitter with a `lifetime` are launched from it again once they've lived that many ticks, and
+// no more than `max` boids are ever added.
+#[derive(Clone)]
+pub struct Emitter {
+    pub position: (f32, f32),
+    pub rate: f32,
+    pub direction: f32,
+    pub spread: f32,
+    pub speed: Option<f32>,
+    pub lifetime: Option<u32>,
+    pub max: Option<u32>,
+}
+
+// A point mass pulling on every boid with a force of `mass` over the distance squared, softened
+// within `softening` of the centre so boids passing through it aren't flung off to infinity.
+// Unlike steering, the pull isn't limited by the max force. `damping` takes that fraction of the
+// speed towards or away from the well off each tick, so boids settle into circular orbits.
+#[derive(Clone)]
+pub struct Well {
+    pub position: (f32, f32),
+    pub mass: f32,
+    pub softening: f32,
+    pub damping: f32,
+}
+
+// Every so often, `interval` ticks apart on average, something startles the flock: a random boid,
+// or a random point unless `from_boid`, pushes boids within `radius` away for `duration` ticks
+// with a force of `strength` over the distance squared. Boids pass the fright on as they align.
+#[derive(Clone)]
+pub struct Startle {
+    pub interval: f32,
+    pub strength: f32,
+    pub radius: f32,
+    pub duration: u32,
+    pub from_boid: bool,
+}
+
+// A route through the world, as a polyline or a Catmull-Rom spline through `points` when
+// `smooth`. Boids steer with `weight` to stay within `radius` of the nearest path and move
+// along it in the order of its points, going back round to the start when it's `closed`.
+#[derive(Clone)]
+pub struct Path {
+    pub points: Vec<(f32, f32)>,
+    pub radius: f32,
+    pub weight: f32,
+    pub closed: bool,
+    pub smooth: bool,
+}
+
+// A path traced out into the polyline boids actually follow
+struct FollowedPath {
+    points: Vec<Position>,
+    radius: Real,
+    weight: Real,
+    closed: bool,
+}
+
+// Helbing's social force model of pedestrians, used in place of the flocking rules. People walk
+// towards their destinations at `desired_speed`, adjusting to it over `relaxation` ticks, and
+// are pushed apart from each other and from walls by forces of `repulsion` at touching distance,
+// falling off exponentially over `range`. With no destinations, half the crowd walks right and
+// half left. Destinations are visited in turn, starting from one picked by id.
+#[derive(Clone)]
+pub struct SocialForce {
+    pub desired_speed: f32,
+    pub relaxation: f32,
+    pub repulsion: f32,
+    pub range: f32,
+    pub radius: f32,
+    pub destinations: Vec<(f32, f32)>,
+}
+
+struct ActiveEmitter {
+    emitter: Emitter,
+    // Fractions of a boid owed from earlier ticks
+    pending: f32,
+    added: u32,
+}
+
 #[repr(C)]
 #[derive(Clone)]
 pub struct Boid {
     position: Position,
     velocity: Velocity,
+    id: u32,
+    // Ticks left to rest for when perched
+    rest: u32,
+    // Ticks since the boid was added, launched or respawned
+    age: u32,
 }
 
 impl Boid {
-    fn new() -> Self {
+    fn new(id: u32) -> Self {
         Boid {
             position: Position::new(0., 0.),
             velocity: Velocity::new(0., 0.),
+            id,
+            rest: 0,
+            age: 0,
         }
     }
+
+    // Stays the same while the boid moves around the grid
+    pub fn id(&self) -> u32 {
+        self.id
+    }
+
+    pub fn position(&self) -> Point2<f32> {
+        self.position.cast()
+    }
+
+    pub fn velocity(&self) -> Vector2<f32> {
+        self.velocity.cast()
+    }
+
+    // In the simulation's own precision, for working out the rules
+    pub(crate) fn exact_position(&self) -> Position {
+        self.position
+    }
+
+    pub(crate) fn exact_velocity(&self) -> Velocity {
+        self.velocity
+    }
+
+    pub fn age(&self) -> u32 {
+        self.age
+    }
 }
 
 pub struct FlockingSystem {
-    width: f32,
-    height: f32,
+    width: Real,
+    height: Real,
     dim_x: usize,
     dim_y: usize,
     boid_grid: Vec<Boid>,
     forces: Vec<Force>,
     params: FlockingConstants,
+    boundary: Boundary,
     mouse_position: Position,
-    mouse_multiplier: f32,
-    rng: ThreadRng,
+    mouse_multiplier: Real,
+    mouse_force: MouseForce,
+    // Strength of the swirl around a vortex, compared to the push, negative to swirl the other way
+    mouse_swirl: Real,
+    rng: Isaac64Rng,
+    tick: u64,
+    // Formation target of each boid, indexed by boid id
+    targets: Vec<Position>,
+    forming: bool,
+    obstacles: Option<ObstacleField>,
+    verlet: Option<VerletLists>,
+    neighbour_cap: usize,
+    timings: UpdateTimings,
+    emitters: Vec<ActiveEmitter>,
+    wells: Vec<Well>,
+    paths: Vec<FollowedPath>,
+    startle: Option<Startle>,
+    // Where each startle going on started and the tick it ends
+    startles: Vec<(Position, u64)>,
+    social_force: Option<SocialForce>,
+    // Index of the destination each person is walking to, by boid id
+    destinations: Vec<usize>,
+    // The emitter that launched each boid with a lifetime and the tick it expires, by boid id
+    expiry: Vec<Option<(usize, u64)>>,
+    // Age at which boids are retired and respawned somewhere random
+    max_age: Option<u32>,
+    extents: GridExtents,
 }
 
 impl FlockingSystem {
-    pub fn new(conf: FlockingConfig) -> Self {
+    pub fn new(mut conf: FlockingConfig) -> Self {
+        if !(conf.width >= MIN_WORLD_SIZE && conf.height >= MIN_WORLD_SIZE) {
+            println!(
+                "Warning: world size {}x{} is too small, using at least {}x{}",
+                conf.width, conf.height, MIN_WORLD_SIZE, MIN_WORLD_SIZE
+            );
+            conf.width = conf.width.max(MIN_WORLD_SIZE);
+            conf.height = conf.height.max(MIN_WORLD_SIZE);
+        }
         // TODO: conf.grid_size()
-        let (dim_x, dim_y) = grid_size(conf.width, conf.height, conf.boid_count);
+        let (dim_x, dim_y) = grid_size(real(conf.width), real(conf.height), conf.boid_count);
         let grid_capacity = dim_x * dim_y;
 
         // TODO: Use sentinal values so boid count can be exactly as requested
         // Could have a sentinal boid at position 0
         let boid_count = grid_capacity;
+        let seed = conf.seed;
+        let verlet = if conf.verlet_skin > 0. {
+            Some(VerletLists::new(real(conf.verlet_skin)))
+        } else {
+            None
+        };
 
-        FlockingSystem {
-            width: conf.width,
-            height: conf.height,
+        let mut system = FlockingSystem {
+            width: real(conf.width),
+            height: real(conf.height),
             dim_x,
             dim_y,
-            boid_grid: (0..boid_count).map(|_| Boid::new()).collect(),
+            boid_grid: (0..boid_count as u32).map(Boid::new).collect(),
             forces: vec![Force::new(0., 0.); boid_count],
+            boundary: conf.boundary,
             params: FlockingConstants::from_config(conf),
             mouse_position: Position::new(0., 0.),
             mouse_multiplier: 1.,
-            rng: rand::thread_rng(),
-        }
+            mouse_force: MouseForce::Push,
+            mouse_swirl: 1.,
+            rng: Isaac64Rng::from_seed(&[seed]),
+            tick: 0,
+            targets: vec![],
+            forming: false,
+            obstacles: None,
+            verlet,
+            neighbour_cap: NEIGHBOURHOOD_SIZE,
+            timings: UpdateTimings::default(),
+            emitters: vec![],
+            wells: vec![],
+            paths: vec![],
+            startle: None,
+            startles: vec![],
+            social_force: None,
+            destinations: vec![],
+            expiry: vec![],
+            max_age: None,
+            extents: GridExtents::default(),
+        };
+        system.index_grid();
+        system
     }
 
     pub fn randomise(&mut self) {
@@ -120,6 +590,7 @@ impl FlockingSystem {
         for boid in &mut self.boid_grid {
             boid.position = center
         }
+        self.index_grid();
         self.randomise_velocities();
     }
 
@@ -127,18 +598,75 @@ impl FlockingSystem {
         for boid in &mut self.boid_grid {
             boid.position = Position::new(0., 0.);
         }
+        self.index_grid();
         self.randomise_velocities();
     }
 
     // TODO: Supply a time delta to update so simulation can be frame independant
     pub fn update(&mut self) {
-        self.sort_boids();
-        self.calculate_forces();
-        self.update_boids();
+        if !self.emitters.is_empty() {
+            self.run_emitters();
+        }
+        self.advance_destinations();
+        if self.startle.is_some() {
+            self.run_startles();
+        }
+        let steps = self.params.substeps();
+        for step in 0..steps {
+            let start = Instant::now();
+            if self.verlet.is_some() {
+                self.refresh_verlet_lists();
+            } else {
+                self.sort_boids();
+            }
+            let sorted = Instant::now();
+            self.calculate_forces();
+            let calculated = Instant::now();
+            self.update_boids(1. / steps as Real, step + 1 == steps);
+            self.timings.neighbours += sorted - start;
+            self.timings.forces += calculated - sorted;
+            self.timings.integration += calculated.elapsed();
+        }
+        self.age_boids();
+        self.index_grid();
+        self.tick += 1;
+    }
+
+    pub fn take_timings(&mut self) -> UpdateTimings {
+        mem::take(&mut self.timings)
+    }
+
+    // Number of updates since the simulation was created
+    pub fn tick(&self) -> u64 {
+        self.tick
+    }
+
+    pub fn dimensions(&self) -> (f32, f32) {
+        (single(self.width), single(self.height))
+    }
+
+    // Hash of every boid's position and velocity down to the last bit, so two runs can be
+    // checked to be in exactly the same state
+    pub fn checksum(&self) -> u64 {
+        let mut hash = FNV_OFFSET;
+        for boid in &self.boid_grid {
+            let (p, v) = (boid.exact_position(), boid.exact_velocity());
+            for &value in &[p.x, p.y, v.x, v.y] {
+                for byte in bits(value).to_le_bytes() {
+                    hash = (hash ^ u64::from(byte)).wrapping_mul(FNV_PRIME);
+                }
+            }
+        }
+        hash
     }
 
     pub fn set_mouse(&mut self, x: f32, y: f32) {
-        self.mouse_position = Position::new(x, y);
+        self.mouse_position = Position::new(real(x), real(y));
+    }
+
+    pub fn set_mouse_force(&mut self, force: MouseForce, swirl: f32) {
+        self.mouse_force = force;
+        self.mouse_swirl = real(swirl);
     }
 
     pub fn enable_mouse_attraction(&mut self) {
@@ -149,10 +677,463 @@ impl FlockingSystem {
         self.mouse_multiplier = 1.;
     }
 
+    // How many grid cells of its neighbourhood a boid looks at, trading accuracy for speed
+    pub fn neighbour_cap(&self) -> usize {
+        self.neighbour_cap
+    }
+
+    pub fn set_neighbour_cap(&mut self, cap: usize) {
+        let cap = cap.clamp(1, NEIGHBOURHOOD_SIZE);
+        if cap != self.neighbour_cap {
+            // Neighbour lists were found with the old cap, so have to be found again
+            if let Some(ref mut lists) = self.verlet {
+                lists.built_at.clear();
+            }
+        }
+        self.neighbour_cap = cap;
+    }
+
+    pub fn parameter(&self, param: Parameter) -> f32 {
+        single(self.params.get(param))
+    }
+
+    pub fn set_parameter(&mut self, param: Parameter, value: f32) {
+        self.params.set(param, real(value));
+    }
+
+    // The flock always fills its grid, so at least `count` boids are added.
+    // Existing boids keep their slots and get re-sorted into the new grid over the next few ticks.
+    pub fn spawn(&mut self, count: u32, position: Option<(f32, f32)>) {
+        let sim_space_x = Range::new(0., self.width);
+        let sim_space_y = Range::new(0., self.height);
+        let vel_space = Range::new(0., self.params.max_speed);
+        let ang_space = Range::new(0., TWO_PI);
+        self.grow(count, |rng, id| {
+            let (x, y) = position
+                .map(|(x, y)| (real(x), real(y)))
+                .unwrap_or_else(|| (sim_space_x.ind_sample(rng), sim_space_y.ind_sample(rng)));
+            let a = ang_space.ind_sample(rng);
+            let m = vel_space.ind_sample(rng);
+            Boid {
+                position: Position::new(x, y),
+                velocity: velocity_from_polar(a, m),
+                id,
+                rest: 0,
+                age: 0,
+            }
+        });
+    }
+
+    // Adds boids made by `new_boid` from a random number generator and an id until there are at
+    // least `count` more and the grid is full
+    fn grow<F>(&mut self, count: u32, mut new_boid: F)
+    where
+        F: FnMut(&mut Isaac64Rng, u32) -> Boid,
+    {
+        let total = self.boid_grid.len() as u32 + count;
+        let (dim_x, dim_y) = grid_size(self.width, self.height, total);
+        let grid_capacity = dim_x * dim_y;
+        while self.boid_grid.len() < grid_capacity {
+            let id = self.boid_grid.len() as u32;
+            let boid = new_boid(&mut self.rng, id);
+            self.boid_grid.push(boid);
+        }
+        self.forces.resize(grid_capacity, Force::new(0., 0.));
+        self.dim_x = dim_x;
+        self.dim_y = dim_y;
+        self.index_grid();
+    }
+
+    pub fn set_social_force(&mut self, model: Option<SocialForce>) {
+        self.social_force = model;
+        self.destinations.clear();
+    }
+
+    // Sends people who have reached their destination on to the next
+    fn advance_destinations(&mut self) {
+        let count = match self.social_force {
+            Some(ref model) if !model.destinations.is_empty() => model.destinations.len(),
+            _ => return,
+        };
+        if self.destinations.len() < self.boid_grid.len() {
+            let from = self.destinations.len();
+            self.destinations
+                .extend((from..self.boid_grid.len()).map(|id| id % count));
+        }
+        for boid in &self.boid_grid {
+            let id = boid.id as usize;
+            if (self.destination(id) - boid.position).magnitude2() < DESTINATION_RADIUS.powi(2) {
+                self.destinations[id] = (self.destinations[id] + 1) % count;
+            }
+        }
+    }
+
+    fn destination(&self, id: usize) -> Position {
+        let model = self.social_force.as_ref().unwrap();
+        let (x, y) = model.destinations[self.destinations[id]];
+        Position::new(real(x), real(y))
+    }
+
+    pub fn add_emitter(&mut self, emitter: Emitter) {
+        self.emitters.push(ActiveEmitter {
+            emitter,
+            pending: 0.,
+            added: 0,
+        });
+    }
+
+    // The flock is given random ages up to the max, so boids don't all retire together
+    pub fn set_max_age(&mut self, max_age: Option<u32>) {
+        self.max_age = max_age;
+        if let Some(max) = max_age {
+            let ages = Range::new(0, max.max(1));
+            for boid in &mut self.boid_grid {
+                boid.age = ages.ind_sample(&mut self.rng);
+            }
+        }
+    }
+
+    fn age_boids(&mut self) {
+        let mut retired = vec![];
+        for (index, boid) in self.boid_grid.iter_mut().enumerate() {
+            boid.age = boid.age.saturating_add(1);
+            if self.max_age.is_some_and(|max| boid.age > max) {
+                retired.push(index);
+            }
+        }
+        for index in retired {
+            let x = Range::new(0., self.width).ind_sample(&mut self.rng);
+            let y = Range::new(0., self.height).ind_sample(&mut self.rng);
+            let a = Range::new(0., TWO_PI).ind_sample(&mut self.rng);
+            let m = Range::new(0., self.params.max_speed).ind_sample(&mut self.rng);
+            let boid = &mut self.boid_grid[index];
+            boid.position = Position::new(x, y);
+            boid.velocity = velocity_from_polar(a, m);
+            boid.rest = 0;
+            boid.age = 0;
+        }
+    }
+
+    pub fn add_well(&mut self, well: Well) {
+        self.wells.push(well);
+    }
+
+    pub fn set_startle(&mut self, startle: Option<Startle>) {
+        self.startle = startle;
+        self.startles.clear();
+    }
+
+    // Startles happen each tick with a chance of one over the interval
+    fn run_startles(&mut self) {
+        let tick = self.tick;
+        self.startles.retain(|&(_, ends)| ends > tick);
+        let startle = self.startle.as_ref().unwrap();
+        if Range::new(0., startle.interval.max(1.)).ind_sample(&mut self.rng) >= 1. {
+            return;
+        }
+        // An empty flock has no boid to startle, so it comes from a random point instead
+        let position = if startle.from_boid && !self.boid_grid.is_empty() {
+            let index = Range::new(0, self.boid_grid.len()).ind_sample(&mut self.rng);
+            self.boid_grid[index].position
+        } else {
+            let x = Range::new(0., self.width).ind_sample(&mut self.rng);
+            let y = Range::new(0., self.height).ind_sample(&mut self.rng);
+            Position::new(x, y)
+        };
+        self.startles
+            .push((position, tick + u64::from(startle.duration)));
+    }
+
+    pub fn add_path(&mut self, path: Path) {
+        if path.points.len() < 2 {
+            println!("Warning: ignoring a path with fewer than two points");
+            return;
+        }
+        let points: Vec<Position> = path
+            .points
+            .iter()
+            .map(|&(x, y)| Position::new(real(x), real(y)))
+            .collect();
+        let points = if path.smooth {
+            spline(&points, path.closed)
+        } else {
+            points
+        };
+        self.paths.push(FollowedPath {
+            points,
+            radius: real(path.radius),
+            weight: real(path.weight),
+            closed: path.closed,
+        });
+    }
+
+    // Relaunches expired boids, then tops each emitter up to its rate with new ones
+    fn run_emitters(&mut self) {
+        let mut relaunched = vec![0u32; self.emitters.len()];
+        if self.emitters.iter().any(|e| e.emitter.lifetime.is_some()) {
+            for boid in &mut self.boid_grid {
+                if let Some(&Some((index, expires))) = self.expiry.get(boid.id as usize) {
+                    if expires <= self.tick {
+                        let emitter = &self.emitters[index].emitter;
+                        *boid = launch(emitter, self.params.max_speed, &mut self.rng, boid.id);
+                        self.expiry[boid.id as usize] = expiry(emitter, index, self.tick);
+                        relaunched[index] += 1;
+                    }
+                }
+            }
+        }
+        for (index, relaunched) in relaunched.into_iter().enumerate() {
+            let active = &mut self.emitters[index];
+            active.pending += active.emitter.rate - relaunched as f32;
+            let mut count = active.pending.floor().max(0.) as u32;
+            if let Some(max) = active.emitter.max {
+                count = count.min(max.saturating_sub(active.added));
+            }
+            if count == 0 {
+                continue;
+            }
+            let emitter = active.emitter.clone();
+            let before = self.boid_grid.len();
+            let max_speed = self.params.max_speed;
+            self.grow(count, |rng, id| launch(&emitter, max_speed, rng, id));
+            // Filling the grid can add more boids than asked for, which later ticks make up for
+            let after = self.boid_grid.len();
+            let active = &mut self.emitters[index];
+            active.added += (after - before) as u32;
+            active.pending -= (after - before) as f32;
+            self.expiry.resize(after, None);
+            for id in before..after {
+                self.expiry[id] = expiry(&emitter, index, self.tick);
+            }
+        }
+    }
+
+    // Gives every boid a random target point in one of the square cells of size `cell_size`
+    // with the given top left corners. Boids are paired with targets in the same order along
+    // both axes, so each boid has a nearby target and the flock doesn't cross itself forming.
+    pub fn set_targets(&mut self, cells: &[Point2<f32>], cell_size: f32) {
+        let count = self.boid_grid.len();
+        let offset = Range::new(0., real(cell_size));
+        let mut targets: Vec<(Position, usize)> = (0..count)
+            .map(|i| {
+                let cell: Position = cells[i * cells.len() / count].cast();
+                let x = cell.x + offset.ind_sample(&mut self.rng);
+                let y = cell.y + offset.ind_sample(&mut self.rng);
+                (Position::new(x, y), i)
+            })
+            .collect();
+        let mut boids: Vec<(Position, usize)> = self
+            .boid_grid
+            .iter()
+            .map(|b| (b.position, b.id as usize))
+            .collect();
+        sort_into_strips(&mut targets);
+        sort_into_strips(&mut boids);
+
+        self.targets = vec![Position::new(0., 0.); count];
+        for (&(_, id), &(target, _)) in boids.iter().zip(targets.iter()) {
+            if let Some(slot) = self.targets.get_mut(id) {
+                *slot = target;
+            }
+        }
+    }
+
+    // Boids steer towards their targets while forming, and scatter when they stop
+    pub fn form(&mut self) {
+        self.forming = !self.targets.is_empty();
+    }
+
+    pub fn disperse(&mut self) {
+        if self.forming {
+            self.forming = false;
+            self.randomise_velocities();
+        }
+    }
+
+    pub fn is_forming(&self) -> bool {
+        self.forming
+    }
+
+    // Blocks off the cells of a `width` by `height` grid stretched over the world.
+    // Boids can't move into blocked cells and steer away from them as they get close.
+    pub fn set_obstacles(&mut self, width: usize, height: usize, blocked: Vec<bool>) {
+        let cell_size = (self.width / width as Real, self.height / height as Real);
+        self.obstacles = Some(ObstacleField::new(width, height, cell_size, blocked));
+    }
+
+    pub fn snapshot(&self) -> Snapshot {
+        Snapshot {
+            tick: self.tick,
+            dim_x: self.dim_x,
+            dim_y: self.dim_y,
+            boid_grid: self.boid_grid.clone(),
+            rng: self.rng,
+            forming: self.forming,
+            verlet: self.verlet.clone(),
+            emitted: self.emitters.iter().map(|e| (e.pending, e.added)).collect(),
+            startles: self.startles.clone(),
+            destinations: self.destinations.clone(),
+            expiry: self.expiry.clone(),
+        }
+    }
+
+    // Puts the flock back as it was when the snapshot was taken, tick count included
+    pub fn rewind(&mut self, snapshot: &Snapshot) {
+        self.tick = snapshot.tick;
+        self.dim_x = snapshot.dim_x;
+        self.dim_y = snapshot.dim_y;
+        self.boid_grid.clone_from(&snapshot.boid_grid);
+        self.forces.resize(self.boid_grid.len(), Force::new(0., 0.));
+        self.rng = snapshot.rng;
+        self.forming = snapshot.forming && !self.targets.is_empty();
+        self.verlet.clone_from(&snapshot.verlet);
+        for (active, &(pending, added)) in self.emitters.iter_mut().zip(&snapshot.emitted) {
+            active.pending = pending;
+            active.added = added;
+        }
+        self.startles.clone_from(&snapshot.startles);
+        self.destinations.clone_from(&snapshot.destinations);
+        self.expiry.clone_from(&snapshot.expiry);
+        self.index_grid();
+    }
+
+    // Replaces the flock, topping it up with random boids if the grid isn't full
+    pub fn restore(&mut self, tick: u64, boids: &[(Point2<f32>, Vector2<f32>)]) {
+        self.boid_grid = boids
+            .iter()
+            .enumerate()
+            .map(|(id, &(position, velocity))| Boid {
+                position: position.cast(),
+                velocity: velocity.cast(),
+                id: id as u32,
+                rest: 0,
+                age: 0,
+            })
+            .collect();
+        self.tick = tick;
+        self.expiry.clear();
+        self.spawn(0, None);
+    }
+
     pub fn boids(&self) -> &[Boid] {
         &self.boid_grid
     }
 
+    // The boid closest to `point`, if there are any boids
+    pub fn nearest_boid(&self, point: Point2<f32>) -> Option<&Boid> {
+        let point: Position = point.cast();
+        let by_gap = |extents: &[(Real, Real)], v: Real| {
+            let mut gaps: Vec<(Real, usize)> = extents
+                .iter()
+                .enumerate()
+                .map(|(i, &extent)| (gap(extent, v), i))
+                .collect();
+            gaps.sort_by(|a, b| a.0.total_cmp(&b.0));
+            gaps
+        };
+        let rows = by_gap(&self.extents.rows, point.y);
+        let columns = by_gap(&self.extents.columns, point.x);
+        // Cells are visited closest first, until none left could hold a closer boid
+        let mut nearest: Option<(Real, usize)> = None;
+        for &(dy, row) in &rows {
+            if nearest.is_some_and(|(best, _)| dy * dy >= best) {
+                break;
+            }
+            for &(dx, col) in &columns {
+                if nearest.is_some_and(|(best, _)| dx * dx + dy * dy >= best) {
+                    break;
+                }
+                let index = row * self.dim_x + col;
+                let dist_2 = (self.boid_grid[index].position - point).magnitude2();
+                if nearest.is_none_or(|(best, _)| dist_2 < best) {
+                    nearest = Some((dist_2, index));
+                }
+            }
+        }
+        nearest.map(|(_, index)| &self.boid_grid[index])
+    }
+
+    // Boids no further than `radius` from `point`
+    pub fn boids_within(&self, point: Point2<f32>, radius: f32) -> Vec<&Boid> {
+        let (point, radius): (Position, Real) = (point.cast(), real(radius));
+        let reach = Vector2::new(radius, radius);
+        self.boids_between(point + -reach, point + reach)
+            .filter(|boid| (boid.position - point).magnitude2() <= radius * radius)
+            .collect()
+    }
+
+    // Boids inside the rectangle with its top left corner at `x`, `y`
+    pub fn boids_in_rect(&self, (x, y, width, height): (f32, f32, f32, f32)) -> Vec<&Boid> {
+        let min = Position::new(real(x), real(y));
+        let max = Position::new(real(x + width), real(y + height));
+        self.boids_between(min, max).collect()
+    }
+
+    // Boids in the box from `min` to `max`, looking only in the grid rows and columns that reach it
+    fn boids_between(&self, min: Position, max: Position) -> impl Iterator<Item = &Boid> + '_ {
+        let overlapping = |extents: &[(Real, Real)], from: Real, to: Real| -> Vec<usize> {
+            let indices = extents.iter().enumerate();
+            indices
+                .filter(|&(_, &(low, high))| low <= to && high >= from)
+                .map(|(i, _)| i)
+                .collect()
+        };
+        let rows = overlapping(&self.extents.rows, min.y, max.y);
+        let columns = overlapping(&self.extents.columns, min.x, max.x);
+        rows.into_iter()
+            .flat_map(move |row| {
+                let columns = columns.clone();
+                columns
+                    .into_iter()
+                    .map(move |col| &self.boid_grid[row * self.dim_x + col])
+            })
+            .filter(move |boid| {
+                let p = boid.position;
+                p.x >= min.x && p.x <= max.x && p.y >= min.y && p.y <= max.y
+            })
+    }
+
+    fn index_grid(&mut self) {
+        self.extents = GridExtents::new(&self.boid_grid, self.dim_x, self.dim_y);
+    }
+
+    // The weighted force each rule currently exerts on the boid at `index` in `boids()`
+    pub fn steering(&self, index: usize) -> Steering {
+        let boid = &self.boid_grid[index];
+        let mut neighbours = Vec::with_capacity(10);
+        self.gather_neighbours(index, &mut neighbours);
+        let rules = self.steer_by_rules(boid, &neighbours);
+        Steering {
+            separation: rules.separation.cast(),
+            alignment: rules.alignment.cast(),
+            cohesion: rules.cohesion.cast(),
+        }
+    }
+
+    pub fn stats(&self) -> FlockStats {
+        let mut speed_acc = 0.;
+        let mut heading_acc = Vector2::new(0., 0.);
+        for boid in &self.boid_grid {
+            let speed = boid.velocity.magnitude();
+            speed_acc += speed;
+            if speed > 0. {
+                heading_acc += boid.velocity / speed;
+            }
+        }
+        let boid_count = self.boid_grid.len();
+        let (average_speed, polarization) = if boid_count > 0 {
+            let n = boid_count as Real;
+            (single(speed_acc / n), single(heading_acc.magnitude() / n))
+        } else {
+            (0., 0.)
+        };
+        FlockStats {
+            boid_count,
+            average_speed,
+            polarization,
+        }
+    }
+
     fn randomise_positions(&mut self) {
         let sim_space_x = Range::new(0., self.width);
         let sim_space_y = Range::new(0., self.height);
@@ -161,6 +1142,7 @@ impl FlockingSystem {
             let y = sim_space_y.ind_sample(&mut self.rng);
             boid.position = Point2::new(x, y);
         }
+        self.index_grid();
     }
 
     fn randomise_velocities(&mut self) {
@@ -237,46 +1219,240 @@ impl FlockingSystem {
     //TODO: As above
     #[inline(always)]
     fn update_boid_grid(&mut self, column: usize, row: usize, boid: &Boid) {
-        let b = &mut self.boid_grid.get_mut(column + (row * self.dim_x)).unwrap();
-        b.position = boid.position;
-        b.velocity = boid.velocity;
+        let b = self.boid_grid.get_mut(column + (row * self.dim_x)).unwrap();
+        *b = boid.clone();
     }
 
+    // Forces only depend on the last tick, so each row of the grid is worked out in parallel
     fn calculate_forces(&mut self) {
-        //TODO: ROLLY THING
-        let mut neighbours = Vec::with_capacity(10); // FIXME: remove hardcoded
-        for row in 0..self.dim_y {
-            for col in 0..self.dim_x {
-                let boid_index = col + (row * self.dim_x);
-                let boid = self.boid_grid.get(boid_index).unwrap();
-                let mut force = Vector2::new(0., 0.);
-                neighbours.clear();
-                self.find_neighbours(col, row, boid, &mut neighbours);
-                let boid = boid.clone();
-                force += self.react_to_neighbours(&boid, &neighbours);
-                force += self.react_to_mouse(&boid);
-                *self.forces.get_mut(boid_index).unwrap() = force;
+        let mut forces = mem::take(&mut self.forces);
+        forces.par_chunks_mut(self.dim_x).enumerate().for_each_init(
+            || Vec::with_capacity(NEIGHBOURHOOD_SIZE),
+            |neighbours, (row, row_forces)| {
+                for (col, force) in row_forces.iter_mut().enumerate() {
+                    let boid_index = col + (row * self.dim_x);
+                    let boid = &self.boid_grid[boid_index];
+                    neighbours.clear();
+                    self.gather_neighbours(boid_index, neighbours);
+                    *force = match self.social_force {
+                        Some(ref model) => self.walk(model, boid, neighbours),
+                        None => self.react_to_neighbours(boid, neighbours),
+                    };
+                    *force += self.react_to_mouse(boid);
+                    if !self.paths.is_empty() {
+                        *force += self.react_to_paths(boid);
+                    }
+                    if !self.startles.is_empty() {
+                        *force += self.react_to_startles(boid);
+                    }
+                    if !self.wells.is_empty() {
+                        *force += self.react_to_wells(boid);
+                    }
+                    if self.forming {
+                        *force += self.react_to_target(boid);
+                    }
+                    // Walls push people away as part of the social force model
+                    if let (Some(obstacles), None) = (&self.obstacles, &self.social_force) {
+                        *force += self.react_to_obstacles(obstacles, boid);
+                    }
+                }
+            },
+        );
+        self.forces = forces;
+    }
+
+    // Reynolds' arrive behaviour, slowing down as the boid nears its target
+    fn react_to_target(&self, boid: &Boid) -> Force {
+        let target = match self.targets.get(boid.id as usize) {
+            Some(&target) => target,
+            None => return Force::new(0., 0.),
+        };
+        let to_target = target - boid.position;
+        let distance = to_target.magnitude();
+        if distance > 0. {
+            let speed = self.params.max_speed * (distance / ARRIVE_RADIUS).min(1.);
+            let steer = limit(
+                normalize_to(to_target, speed) - boid.velocity,
+                self.params.max_force,
+            );
+            self.params.target_weight * steer
+        } else {
+            -boid.velocity
+        }
+    }
+
+    // Finds where the boid will be shortly and the nearest point to that on any path. Boids
+    // straying outside the path's radius steer back to a point a little further along it, and
+    // those inside just steer to head along it.
+    fn react_to_paths(&self, boid: &Boid) -> Force {
+        let predicted = boid.position + normalize_to(boid.velocity, PATH_LOOKAHEAD);
+        let mut nearest: Option<(Real, Position, Velocity, &FollowedPath)> = None;
+        for path in &self.paths {
+            let closing = if path.closed {
+                Some((path.points[path.points.len() - 1], path.points[0]))
+            } else {
+                None
+            };
+            let spans = path.points.windows(2).map(|w| (w[0], w[1])).chain(closing);
+            for (a, b) in spans {
+                let span = b - a;
+                let length_2 = span.magnitude2();
+                if length_2 == 0. {
+                    continue;
+                }
+                let t = ((predicted - a).dot(span) / length_2).clamp(0., 1.);
+                let point = a + span * t;
+                let dist_2 = (predicted - point).magnitude2();
+                if nearest.is_none_or(|(best, ..)| dist_2 < best) {
+                    nearest = Some((dist_2, point, span, path));
+                }
             }
         }
+        let (dist_2, point, span, path) = match nearest {
+            Some(nearest) => nearest,
+            None => return Force::new(0., 0.),
+        };
+        let desired = if dist_2 > path.radius.powi(2) {
+            let target = point + normalize_to(span, PATH_LOOKAHEAD);
+            normalize_to(target - boid.position, self.params.max_speed)
+        } else {
+            normalize_to(span, self.params.max_speed)
+        };
+        path.weight * limit(desired - boid.velocity, self.params.max_force)
     }
 
-    fn react_to_mouse(&mut self, boid: &Boid) -> Force {
+    fn react_to_obstacles(&self, obstacles: &ObstacleField, boid: &Boid) -> Force {
+        let (away, blocked) = obstacles.away(boid.position);
+        let distance = away.magnitude();
+        if distance == 0. || (!blocked && distance > AVOID_RADIUS) {
+            return Force::new(0., 0.);
+        }
+        // Escape at full strength if inside an obstacle, otherwise more strongly when closer
+        let closeness = if blocked {
+            1.
+        } else {
+            1. - distance / AVOID_RADIUS
+        };
+        let target_vel = normalize_to(away, self.params.max_speed);
+        let steer = limit(target_vel - boid.velocity, self.params.max_force);
+        self.params.obstacle_weight * closeness * steer
+    }
+
+    // Each well damps motion towards it in proportion to its share of the total pull
+    fn react_to_wells(&self, boid: &Boid) -> Force {
+        let mut pull = Force::new(0., 0.);
+        let mut damping = Force::new(0., 0.);
+        let mut total = 0.;
+        for well in &self.wells {
+            let centre = Position::new(real(well.position.0), real(well.position.1));
+            let to_well = centre - boid.position;
+            let dist_2 = (to_well.magnitude2() + real(well.softening).powi(2)).max(1.);
+            let strength = real(well.mass) * to_well.magnitude() / math::powf(dist_2, 1.5);
+            let towards = normalize_to(to_well, 1.);
+            pull += towards * strength;
+            damping -= towards * towards.dot(boid.velocity) * real(well.damping) * strength;
+            total += strength;
+        }
+        if total > 0. {
+            pull + damping / total
+        } else {
+            pull
+        }
+    }
+
+    fn react_to_startles(&self, boid: &Boid) -> Force {
+        let startle = self.startle.as_ref().unwrap();
+        let mut force = Force::new(0., 0.);
+        for &(position, _) in &self.startles {
+            let away = boid.position - position;
+            let dist_sq = away.magnitude2();
+            if dist_sq < real(startle.radius).powi(2) {
+                force += normalize_to(away, real(startle.strength) / dist_sq.max(1.));
+            }
+        }
+        force
+    }
+
+    fn react_to_mouse(&self, boid: &Boid) -> Force {
         let from_mouse = boid.position - self.mouse_position;
         let dist_sq = from_mouse.magnitude2();
         if dist_sq > 0. {
             let repulse = self.params.mouse_weight / dist_sq;
-            from_mouse.normalize_to(repulse) * self.mouse_multiplier
+            let push = normalize_to(from_mouse, repulse) * self.mouse_multiplier;
+            match self.mouse_force {
+                MouseForce::Push => push,
+                MouseForce::Vortex => {
+                    let around = Force::new(-from_mouse.y, from_mouse.x);
+                    push + normalize_to(around, repulse * self.mouse_swirl)
+                }
+            }
         } else {
             Force::new(0., 0.)
         }
     }
 
+    // Neighbour lists hold grid indices, so the grid is only re-sorted when they're rebuilt
+    fn refresh_verlet_lists(&mut self) {
+        let radius = self.params.neighbour_radius();
+        let mut lists = match self.verlet.take() {
+            Some(lists) => lists,
+            None => return,
+        };
+        if lists.is_stale(&self.boid_grid, self.width, self.height, radius) {
+            self.sort_boids();
+            let reach_2 = (radius + lists.skin).powi(2);
+            let count = self.boid_grid.len();
+            lists.counts.resize(count, 0);
+            lists.indices.resize(count * NEIGHBOURHOOD_SIZE, 0);
+            for index in 0..count {
+                let (col, row) = (index % self.dim_x, index / self.dim_x);
+                let boid = &self.boid_grid[index];
+                let start = index * NEIGHBOURHOOD_SIZE;
+                let mut found = 0;
+                for other in self.neighbour_indices(col, row, boid.velocity) {
+                    let offset = self.boid_grid[other].position - boid.position;
+                    if offset.magnitude2() < reach_2 {
+                        lists.indices[start + found] = other as u32;
+                        found += 1;
+                    }
+                }
+                lists.counts[index] = found as u8;
+            }
+            lists.radius = radius;
+            lists.built_at = self.boid_grid.iter().map(|b| b.position).collect();
+        }
+        self.verlet = Some(lists);
+    }
+
+    fn gather_neighbours(&self, index: usize, neighbourhood: &mut Vec<Boid>) {
+        match self.verlet {
+            Some(ref lists) => {
+                let found = lists.neighbours(index).iter();
+                neighbourhood.extend(found.map(|&i| self.boid_grid[i as usize].clone()));
+            }
+            None => {
+                let (col, row) = (index % self.dim_x, index / self.dim_x);
+                self.find_neighbours(col, row, &self.boid_grid[index], neighbourhood);
+            }
+        }
+    }
+
     fn find_neighbours(&self, col: usize, row: usize, boid: &Boid, neighbourhood: &mut Vec<Boid>) {
+        for index in self.neighbour_indices(col, row, boid.velocity) {
+            neighbourhood.push(self.boid_grid[index].clone());
+        }
+    }
+
+    // Grid indices of the flockmates a boid at `col`, `row` heading along `v` is facing
+    fn neighbour_indices(
+        &self,
+        col: usize,
+        row: usize,
+        v: Velocity,
+    ) -> impl Iterator<Item = usize> + '_ {
         //TODO: Could try other "kernals"
         //TODO Remove use of i32, use usize instead
 
-        let v = boid.velocity;
-
         // This is essentially a look up table to determine which flockmates the boid is facing
         #[rustfmt::skip]
             let neighbours = match (v.x > 0., v.y > 0., v.x.abs() > v.y.abs()) {
@@ -322,107 +1498,839 @@ impl FlockingSystem {
         };
 
         //TODO: Try and remove extra references and casting
-        for &(x, y) in neighbours.iter() {
+        let cap = self.neighbour_cap;
+        neighbours.iter().take(cap).filter_map(move |&(x, y)| {
             let nx = (col as i32 + x) as usize;
             let ny = (row as i32 + y) as usize;
             if nx > 0 && nx < self.dim_x && ny > 0 && ny < self.dim_y {
-                let neighbour = self.boid_grid.get(nx + (ny * self.dim_x)).unwrap();
-                neighbourhood.push(neighbour.clone());
+                Some(nx + (ny * self.dim_x))
+            } else {
+                None
             }
-        }
+        })
     }
 
-    fn react_to_neighbours(&self, boid: &Boid, neighbours: &[Boid]) -> Force {
-        let mut dodge = Vector2::new(0., 0.);
-        let mut ali_vel_acc = Vector2::new(0., 0.);
-        let mut ali_vel_count = 0;
-        let mut coh_pos_acc = Vector2::new(0., 0.);
-        let mut coh_pos_count = 0;
+    fn walk(&self, model: &SocialForce, boid: &Boid, neighbours: &[Boid]) -> Force {
+        let heading = if model.destinations.is_empty() {
+            let side = if boid.id & 1 == 0 { 1. } else { -1. };
+            Vector2::new(side, 0.)
+        } else {
+            let id = boid.id as usize;
+            match self.destinations.get(id) {
+                Some(_) => normalize_to(self.destination(id) - boid.position, 1.),
+                None => Vector2::new(0., 0.),
+            }
+        };
+        let (repulsion, radius, range) =
+            (real(model.repulsion), real(model.radius), real(model.range));
+        let mut force =
+            (heading * real(model.desired_speed) - boid.velocity) / real(model.relaxation).max(1.);
 
+        let facing = normalize_to(boid.velocity, 1.);
         for other in neighbours {
-            let from_neighbour = boid.position - other.position;
-            let dist_squared = from_neighbour.magnitude2();
-            if dist_squared > 0. {
-                if dist_squared < self.params.sep_radius_2 {
-                    let repulse = 1. / dist_squared.sqrt();
-                    dodge += from_neighbour.normalize_to(repulse);
-                }
-                if dist_squared < self.params.ali_radius_2 {
-                    ali_vel_acc += other.velocity;
-                    ali_vel_count += 1;
-                }
-                if dist_squared < self.params.coh_radius_2 {
-                    coh_pos_acc.x += other.position.x;
-                    coh_pos_acc.y += other.position.y;
-                    coh_pos_count += 1;
-                }
+            let offset = boid.position - other.position;
+            let distance = offset.magnitude();
+            if distance == 0. {
+                continue;
             }
+            let away = offset / distance;
+            // People pay more attention to those in front of them
+            let ahead = -away.dot(facing);
+            let weight = ANISOTROPY + (1. - ANISOTROPY) * (1. + ahead) / 2.;
+            let push = repulsion * math::exp((2. * radius - distance) / range);
+            force += away * push * weight;
         }
+
+        if let Some(ref obstacles) = self.obstacles {
+            let (away, blocked) = obstacles.away(boid.position);
+            let distance = if blocked { 0. } else { away.magnitude() };
+            let push = repulsion * math::exp((radius - distance) / range);
+            force += normalize_to(away, push);
+        }
+        force
+    }
+
+    fn react_to_neighbours(&self, boid: &Boid, neighbours: &[Boid]) -> Force {
+        let steering = self.steer_by_rules(boid, neighbours);
+        steering.separation + steering.alignment + steering.cohesion
+    }
+
+    fn steer_by_rules(&self, boid: &Boid, neighbours: &[Boid]) -> Rules {
+        let radii = Radii {
+            separation: self.params.sep_radius_2,
+            alignment: self.params.ali_radius_2,
+            cohesion: self.params.coh_radius_2,
+        };
+        let sums = lanes::sum_rules(boid.position, neighbours, &radii);
+        let dodge = sums.dodge;
         //TODO: Using MAX_SPEED to steer all the things might not be the most pleasing to look at?
-        let mut force = Vector2::new(0., 0.);
+        let mut steering = Rules {
+            separation: Vector2::new(0., 0.),
+            alignment: Vector2::new(0., 0.),
+            cohesion: Vector2::new(0., 0.),
+        };
         if dodge.magnitude2() > 0. {
-            let target_d_vel = dodge.normalize_to(self.params.max_speed);
+            let target_d_vel = normalize_to(dodge, self.params.max_speed);
             let d_steer = limit(target_d_vel - boid.velocity, self.params.max_force);
-            force += self.params.sep_weight * d_steer;
+            steering.separation = self.params.sep_weight * d_steer;
         }
-        if ali_vel_count > 0 {
-            let align = ali_vel_acc / ali_vel_count as f32;
-            let target_a_vel = align.normalize_to(self.params.max_speed);
+        if sums.velocity_count > 0. {
+            let align = sums.velocity / sums.velocity_count;
+            let target_a_vel = normalize_to(align, self.params.max_speed);
             let a_steer = limit(target_a_vel - boid.velocity, self.params.max_force);
-            force += self.params.ali_weight * a_steer;
+            steering.alignment = self.params.ali_weight * a_steer;
         }
-        if coh_pos_count > 0 {
-            let avg_pos = coh_pos_acc / coh_pos_count as f32;
+        if sums.position_count > 0. {
+            let avg_pos = sums.position / sums.position_count;
             let boid_pos = Vector2::new(boid.position.x, boid.position.y);
             let cohesion = avg_pos - boid_pos;
-            let target_c_vel = cohesion.normalize_to(self.params.max_speed);
+            let target_c_vel = normalize_to(cohesion, self.params.max_speed);
             let c_steer = limit(target_c_vel - boid.velocity, self.params.max_force);
-            force += self.params.coh_weight * c_steer;
+            steering.cohesion = self.params.coh_weight * c_steer;
         }
-        force
+        steering
     }
 
-    fn update_boids(&mut self) {
-        for (mut boid, force) in self.boid_grid.iter_mut().zip(self.forces.iter()) {
+    // Moves boids on by `step` of a tick, perched boids only rest a tick at the end of the last step
+    fn update_boids(&mut self, step: Real, end_of_tick: bool) {
+        let mut respawned = 0;
+        let max_turn = self.params.max_turn_rate.to_radians() / TICKS_PER_SECOND * step;
+        for (boid, force) in self.boid_grid.iter_mut().zip(self.forces.iter()) {
+            if boid.rest > 0 {
+                if !end_of_tick {
+                    continue;
+                }
+                boid.rest -= 1;
+                if boid.rest == 0 {
+                    // Take off upwards to rejoin the flock
+                    let a = Range::new(0.75 * PI, 1.25 * PI).ind_sample(&mut self.rng);
+                    let m = Range::new(0.5, 1.).ind_sample(&mut self.rng) * self.params.max_speed;
+                    boid.velocity = velocity_from_polar(a, m);
+                }
+                continue;
+            }
+
             // Update velocity
-            let vel = boid.velocity + force;
+            let vel = swim(
+                &self.params,
+                self.tick,
+                step,
+                boid,
+                boid.velocity + force * step,
+            );
+            let vel = if max_turn > 0. {
+                limit_turn(boid.velocity, vel, max_turn)
+            } else {
+                vel
+            };
             boid.velocity = limit(vel, self.params.max_speed);
 
-            // Update position
-            let mut new_pos = boid.position + boid.velocity;
-            if new_pos.x <= 0. {
-                new_pos.x += self.width;
+            // Never let bad maths poison the flock, boids that break are put back somewhere random
+            if !is_finite(boid.velocity) || !is_finite(boid.position.to_vec()) {
+                let x = Range::new(0., self.width).ind_sample(&mut self.rng);
+                let y = Range::new(0., self.height).ind_sample(&mut self.rng);
+                let a = Range::new(0., TWO_PI).ind_sample(&mut self.rng);
+                boid.position = Position::new(x, y);
+                boid.velocity = velocity_from_polar(a, self.params.max_speed / 2.);
+                respawned += 1;
+                continue;
             }
-            if new_pos.y <= 0. {
-                new_pos.y += self.height;
+
+            // Update position
+            let mut new_pos = boid.position + boid.velocity * step;
+            if let Some(ref obstacles) = self.obstacles {
+                // Bounce off obstacles, but let boids that started inside one out
+                if obstacles.is_blocked(new_pos) && !obstacles.is_blocked(boid.position) {
+                    if perch(&self.params, &mut self.rng, boid) {
+                        continue;
+                    }
+                    boid.velocity = -boid.velocity;
+                    new_pos = boid.position;
+                }
             }
-            if new_pos.x >= self.width {
-                new_pos.x -= self.width;
+            // The bottom edge is the ground
+            if new_pos.y >= self.height && perch(&self.params, &mut self.rng, boid) {
+                boid.position.y = self.height - 1.;
+                continue;
             }
-            if new_pos.y >= self.height {
-                new_pos.y -= self.height;
+            let outside = new_pos.x <= 0.
+                || new_pos.y <= 0.
+                || new_pos.x >= self.width
+                || new_pos.y >= self.height;
+            if outside {
+                let size = (self.width, self.height);
+                match self.boundary {
+                    Boundary::Wrap => wrap(size, &mut new_pos),
+                    Boundary::Reflect => reflect(size, &mut new_pos, &mut boid.velocity),
+                    Boundary::Slide => slide(size, &mut new_pos, &mut boid.velocity),
+                    Boundary::Respawn => {
+                        let speed = boid.velocity.magnitude();
+                        let (position, velocity) = enter(size, speed, &mut self.rng);
+                        new_pos = position;
+                        boid.velocity = velocity;
+                        boid.age = 0;
+                    }
+                }
             }
             boid.position = new_pos
         }
+        if respawned > 0 {
+            println!(
+                "Respawned {} boids with non-finite positions or velocities",
+                respawned
+            );
+        }
+    }
+}
+
+// How far the boids in each row of the grid spread in y, and in each column in x. The grid is
+// sorted by position, so these are narrow, and every boid is within the extents of both its row
+// and its column, which lets spatial queries skip the rows and columns that can't hold a match.
+#[derive(Default)]
+struct GridExtents {
+    rows: Vec<(Real, Real)>,
+    columns: Vec<(Real, Real)>,
+}
+
+impl GridExtents {
+    fn new(boids: &[Boid], dim_x: usize, dim_y: usize) -> Self {
+        let empty = (Real::INFINITY, Real::NEG_INFINITY);
+        let mut extents = GridExtents {
+            rows: vec![empty; dim_y],
+            columns: vec![empty; dim_x],
+        };
+        for (index, boid) in boids.iter().enumerate() {
+            let (row, col) = (index / dim_x, index % dim_x);
+            let (y, x) = (&mut extents.rows[row], &mut extents.columns[col]);
+            *y = (y.0.min(boid.position.y), y.1.max(boid.position.y));
+            *x = (x.0.min(boid.position.x), x.1.max(boid.position.x));
+        }
+        extents
+    }
+}
+
+// Distance from `v` to the nearest end of `extent`, or zero inside it
+fn gap((low, high): (Real, Real), v: Real) -> Real {
+    if v < low {
+        low - v
+    } else if v > high {
+        v - high
+    } else {
+        0.
+    }
+}
+
+// Verlet neighbour lists: the flockmates each boid finds in the neighbour grid that are within
+// the largest rule radius plus a skin. The lists are reused until some boid has moved half the
+// skin, so the grid only needs sorting and searching again every few ticks. Meanwhile they can
+// miss flockmates a fresh search of the grid would have brought in.
+#[derive(Clone)]
+struct VerletLists {
+    skin: Real,
+    radius: Real,
+    counts: Vec<u8>,
+    indices: Vec<u32>,
+    built_at: Vec<Position>,
+}
+
+impl VerletLists {
+    fn new(skin: Real) -> Self {
+        VerletLists {
+            skin,
+            radius: 0.,
+            counts: vec![],
+            indices: vec![],
+            built_at: vec![],
+        }
+    }
+
+    fn neighbours(&self, index: usize) -> &[u32] {
+        let start = index * NEIGHBOURHOOD_SIZE;
+        &self.indices[start..start + self.counts[index] as usize]
+    }
+
+    fn is_stale(&self, boids: &[Boid], width: Real, height: Real, radius: Real) -> bool {
+        if self.built_at.len() != boids.len() || radius > self.radius {
+            return true;
+        }
+        let limit_2 = (self.skin / 2.).powi(2);
+        boids.iter().zip(self.built_at.iter()).any(|(boid, &from)| {
+            // Wrapping around the world isn't a long way to move
+            let mut moved = boid.position - from;
+            if moved.x.abs() > width / 2. {
+                moved.x = width - moved.x.abs();
+            }
+            if moved.y.abs() > height / 2. {
+                moved.y = height - moved.y.abs();
+            }
+            moved.magnitude2() > limit_2
+        })
+    }
+}
+
+// Water slows boids down as they coast, so when they have a cruising speed they beat their tails
+// in short bursts to get back up to it, each at its own point in the cycle
+fn swim(
+    params: &FlockingConstants,
+    tick: u64,
+    step: Real,
+    boid: &Boid,
+    velocity: Velocity,
+) -> Velocity {
+    let velocity = velocity * math::powf(1. - params.drag, step);
+    if params.cruise_speed <= 0. {
+        return velocity;
+    }
+    let offset = u64::from(boid.id.wrapping_mul(BURST_SPREAD));
+    if (tick + offset) % BURST_PERIOD >= BURST_LENGTH {
+        return velocity;
+    }
+    let speed = velocity.magnitude();
+    if speed == 0. {
+        return velocity;
+    }
+    let thrust = (params.cruise_speed * BURST_OVERSHOOT - speed).max(0.) * BURST_THRUST * step;
+    velocity * (1. + thrust / speed)
+}
+
+fn launch(emitter: &Emitter, max_speed: Real, rng: &mut Isaac64Rng, id: u32) -> Boid {
+    let half_spread = real(emitter.spread).to_radians() / 2.;
+    let turn = if half_spread > 0. {
+        Range::new(-half_spread, half_spread).ind_sample(rng)
+    } else {
+        0.
+    };
+    // No angle is straight down, so straight up is half a turn
+    let a = PI + real(emitter.direction).to_radians() + turn;
+    let speed = emitter.speed.map_or(max_speed, real);
+    Boid {
+        position: Position::new(real(emitter.position.0), real(emitter.position.1)),
+        velocity: velocity_from_polar(a, speed),
+        id,
+        rest: 0,
+        age: 0,
+    }
+}
+
+fn expiry(emitter: &Emitter, index: usize, tick: u64) -> Option<(usize, u64)> {
+    emitter
+        .lifetime
+        .map(|lifetime| (index, tick + u64::from(lifetime)))
+}
+
+fn wrap((width, height): (Real, Real), position: &mut Position) {
+    if position.x <= 0. {
+        position.x += width;
+    }
+    if position.y <= 0. {
+        position.y += height;
+    }
+    if position.x >= width {
+        position.x -= width;
+    }
+    if position.y >= height {
+        position.y -= height;
+    }
+}
+
+// Mirrors the part of the step that went past an edge back inside, clamped in case the step was
+// longer than the world
+fn reflect((width, height): (Real, Real), position: &mut Position, velocity: &mut Velocity) {
+    if position.x < 0. || position.x > width {
+        position.x = if position.x < 0. {
+            -position.x
+        } else {
+            2. * width - position.x
+        };
+        velocity.x = -velocity.x;
+    }
+    if position.y < 0. || position.y > height {
+        position.y = if position.y < 0. {
+            -position.y
+        } else {
+            2. * height - position.y
+        };
+        velocity.y = -velocity.y;
+    }
+    position.x = position.x.max(0.).min(width);
+    position.y = position.y.max(0.).min(height);
+}
+
+// Keeps boids on the edge with only the part of their velocity along it
+fn slide((width, height): (Real, Real), position: &mut Position, velocity: &mut Velocity) {
+    if position.x < 0. || position.x > width {
+        position.x = position.x.max(0.).min(width);
+        velocity.x = 0.;
+    }
+    if position.y < 0. || position.y > height {
+        position.y = position.y.max(0.).min(height);
+        velocity.y = 0.;
+    }
+}
+
+// A random point on a random edge, with a velocity heading into the world
+fn enter((width, height): (Real, Real), speed: Real, rng: &mut Isaac64Rng) -> (Position, Velocity) {
+    let a = Range::new(0., TWO_PI).ind_sample(rng);
+    let mut velocity = velocity_from_polar(a, speed);
+    let along = Range::new(0., 1.).ind_sample(rng);
+    let position = match Range::new(0, 4).ind_sample(rng) {
+        0 => {
+            velocity.x = velocity.x.abs();
+            Position::new(0., along * height)
+        }
+        1 => {
+            velocity.x = -velocity.x.abs();
+            Position::new(width, along * height)
+        }
+        2 => {
+            velocity.y = velocity.y.abs();
+            Position::new(along * width, 0.)
+        }
+        _ => {
+            velocity.y = -velocity.y.abs();
+            Position::new(along * width, height)
+        }
+    };
+    (position, velocity)
+}
+
+// Boids reaching a surface land on it every so often, staying where they are to rest for a
+// random time around `perch_time` ticks
+fn perch(params: &FlockingConstants, rng: &mut Isaac64Rng, boid: &mut Boid) -> bool {
+    if params.perch_chance <= 0. || Range::new(0., 1.).ind_sample(rng) >= params.perch_chance {
+        return false;
     }
+    let time = Range::new(0.5, 1.5).ind_sample(rng) * params.perch_time;
+    boid.rest = (time as u32).max(1);
+    boid.velocity = Velocity::new(0., 0.);
+    true
+}
+
+// Obstacle cells, along with the nearest blocked cell to each free cell and the nearest free
+// cell to each blocked one, found with two pass chamfer sweeps
+struct ObstacleField {
+    width: usize,
+    height: usize,
+    cell_size: (Real, Real),
+    blocked: Vec<bool>,
+    nearest: Vec<Option<(usize, usize)>>,
 }
 
-fn grid_size(width: f32, height: f32, desired_count: u32) -> (usize, usize) {
+impl ObstacleField {
+    fn new(width: usize, height: usize, cell_size: (Real, Real), blocked: Vec<bool>) -> Self {
+        let mut field = ObstacleField {
+            width,
+            height,
+            cell_size,
+            blocked,
+            nearest: vec![None; width * height],
+        };
+        field.find_nearest();
+        field
+    }
+
+    fn cell(&self, position: Position) -> (usize, usize) {
+        let x = (position.x / self.cell_size.0).max(0.) as usize;
+        let y = (position.y / self.cell_size.1).max(0.) as usize;
+        (x.min(self.width - 1), y.min(self.height - 1))
+    }
+
+    fn is_blocked(&self, position: Position) -> bool {
+        let (x, y) = self.cell(position);
+        self.blocked[x + y * self.width]
+    }
+
+    // Vector pointing away from the nearest obstacle (or out of the one the position is inside),
+    // with the distance to it, and whether the position is blocked
+    fn away(&self, position: Position) -> (Vector2<Real>, bool) {
+        let (x, y) = self.cell(position);
+        let blocked = self.blocked[x + y * self.width];
+        let (nx, ny) = match self.nearest[x + y * self.width] {
+            Some(nearest) => nearest,
+            None => return (Vector2::new(0., 0.), blocked),
+        };
+        let centre = Position::new(
+            (nx as Real + 0.5) * self.cell_size.0,
+            (ny as Real + 0.5) * self.cell_size.1,
+        );
+        if blocked {
+            (centre - position, true)
+        } else {
+            (position - centre, false)
+        }
+    }
+
+    fn distance2(&self, (x, y): (usize, usize), (nx, ny): (usize, usize)) -> Real {
+        let dx = (x as Real - nx as Real) * self.cell_size.0;
+        let dy = (y as Real - ny as Real) * self.cell_size.1;
+        dx * dx + dy * dy
+    }
+
+    fn find_nearest(&mut self) {
+        let to_blocked = self.sweep(|blocked| blocked);
+        let to_free = self.sweep(|blocked| !blocked);
+        self.nearest = self
+            .blocked
+            .iter()
+            .enumerate()
+            .map(|(i, &blocked)| if blocked { to_free[i] } else { to_blocked[i] })
+            .collect();
+    }
+
+    // Nearest cell matching `is_source` for every cell
+    fn sweep<F: Fn(bool) -> bool>(&self, is_source: F) -> Vec<Option<(usize, usize)>> {
+        let (w, h) = (self.width as i64, self.height as i64);
+        let mut nearest: Vec<Option<(usize, usize)>> = self
+            .blocked
+            .iter()
+            .enumerate()
+            .map(|(i, &b)| {
+                if is_source(b) {
+                    Some((i % self.width, i / self.width))
+                } else {
+                    None
+                }
+            })
+            .collect();
+        let forward = [(-1, -1), (0, -1), (1, -1), (-1, 0)];
+        let backward = [(1, 0), (-1, 1), (0, 1), (1, 1)];
+        for &(offsets, backwards) in &[(&forward, false), (&backward, true)] {
+            for step in 0..w * h {
+                let i = if backwards { w * h - 1 - step } else { step };
+                let cell = ((i % w) as usize, (i / w) as usize);
+                for &(dx, dy) in offsets.iter() {
+                    let (ox, oy) = (i % w + dx, i / w + dy);
+                    if ox < 0 || oy < 0 || ox >= w || oy >= h {
+                        continue;
+                    }
+                    let candidate = match nearest[(ox + oy * w) as usize] {
+                        Some(candidate) => candidate,
+                        None => continue,
+                    };
+                    let closer = match nearest[i as usize] {
+                        Some(current) => {
+                            self.distance2(cell, candidate) < self.distance2(cell, current)
+                        }
+                        None => true,
+                    };
+                    if closer {
+                        nearest[i as usize] = Some(candidate);
+                    }
+                }
+            }
+        }
+        nearest
+    }
+}
+
+// Sorts points into vertical strips ordered left to right, each ordered top to bottom
+fn sort_into_strips(points: &mut [(Position, usize)]) {
+    let strip = ((points.len() as Real).sqrt().ceil() as usize).max(1);
+    points.sort_by(|a, b| a.0.x.partial_cmp(&b.0.x).unwrap_or(Ordering::Equal));
+    for chunk in points.chunks_mut(strip) {
+        chunk.sort_by(|a, b| a.0.y.partial_cmp(&b.0.y).unwrap_or(Ordering::Equal));
+    }
+}
+
+fn grid_size(width: Real, height: Real, desired_count: u32) -> (usize, usize) {
     let aspect_ratio = width / height;
-    let dim_y_unrounded = (desired_count as f32 / aspect_ratio).sqrt();
+    let dim_y_unrounded = (desired_count as Real / aspect_ratio).sqrt();
     let dim_y = dim_y_unrounded.ceil();
     let dim_x = (dim_y_unrounded * aspect_ratio).ceil();
-    (dim_x as usize, dim_y as usize)
+    // An empty flock still needs a row width so the grid can be split into rows
+    (dim_x.max(1.) as usize, dim_y as usize)
+}
+
+fn velocity_from_polar(a: Real, m: Real) -> Velocity {
+    rotate(Vector2::new(0., m), a)
+}
+
+// Scales a vector to a length, giving zero rather than NaN for vectors of no length and infinities
+fn normalize_to(v: Vector2<Real>, length: Real) -> Vector2<Real> {
+    let magnitude = v.magnitude();
+    if magnitude > 0. && magnitude.is_finite() {
+        v * (length / magnitude)
+    } else {
+        Vector2::new(0., 0.)
+    }
+}
+
+fn is_finite(v: Vector2<Real>) -> bool {
+    v.x.is_finite() && v.y.is_finite()
+}
+
+// Samples a Catmull-Rom spline through the points, which ends at the last point unless it's closed
+fn spline(points: &[Position], closed: bool) -> Vec<Position> {
+    let n = points.len();
+    let at = |i: isize| {
+        if closed {
+            points[i.rem_euclid(n as isize) as usize]
+        } else {
+            points[i.max(0).min(n as isize - 1) as usize]
+        }
+    };
+    let spans = if closed { n } else { n - 1 };
+    let mut sampled = Vec::with_capacity(spans * SPLINE_SAMPLES + 1);
+    for i in 0..spans as isize {
+        let (p0, p1, p2, p3) = (at(i - 1), at(i), at(i + 1), at(i + 2));
+        for s in 0..SPLINE_SAMPLES {
+            let t = s as Real / SPLINE_SAMPLES as Real;
+            let (t2, t3) = (t * t, t * t * t);
+            let v = (p1.to_vec() * 2.
+                + (p2 - p0) * t
+                + (p0.to_vec() * 2. - p1.to_vec() * 5. + p2.to_vec() * 4. - p3.to_vec()) * t2
+                + (p1.to_vec() * 3. - p0.to_vec() - p2.to_vec() * 3. + p3.to_vec()) * t3)
+                * 0.5;
+            sampled.push(Position::from_vec(v));
+        }
+    }
+    if !closed {
+        sampled.push(points[n - 1]);
+    }
+    sampled
 }
 
-fn velocity_from_polar(a: f32, m: f32) -> Velocity {
-    Basis2::from_angle(Rad(a)).rotate_vector(Vector2::new(0., m))
+// Converts to and from the single precision the rest of the program works in
+fn real(x: f32) -> Real {
+    x as Real
 }
 
-fn limit(force: Force, max: f32) -> Force {
+#[allow(clippy::unnecessary_cast)]
+fn single(x: Real) -> f32 {
+    x as f32
+}
+
+#[allow(clippy::useless_conversion)]
+fn bits(x: Real) -> u64 {
+    u64::from(x.to_bits())
+}
+
+// Keeps the new velocity's speed but turns it no more than `max_angle` from the old one
+fn limit_turn(from: Velocity, to: Velocity, max_angle: Real) -> Velocity {
+    let angle = math::atan2(from.x * to.y - from.y * to.x, from.dot(to));
+    if angle.abs() <= max_angle {
+        to
+    } else {
+        rotate(
+            normalize_to(from, to.magnitude()),
+            max_angle.copysign(angle),
+        )
+    }
+}
+
+// Turns a vector anticlockwise by an angle in radians
+fn rotate(v: Vector2<Real>, angle: Real) -> Vector2<Real> {
+    let (sin, cos) = math::sin_cos(angle);
+    Vector2::new(cos * v.x - sin * v.y, sin * v.x + cos * v.y)
+}
+
+fn limit(force: Force, max: Real) -> Force {
     if force.magnitude2() > max * max {
-        force.normalize_to(max)
+        normalize_to(force, max)
     } else {
         force
     }
 }
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    pub(crate) fn config(boid_count: u32) -> FlockingConfig {
+        FlockingConfig {
+            boid_count,
+            seed: 1,
+            width: 200.,
+            height: 100.,
+            max_speed: 2.5,
+            max_force: 0.4,
+            mouse_weight: 600.,
+            sep_weight: 1.5,
+            ali_weight: 1.,
+            coh_weight: 1.,
+            sep_radius: 6.,
+            ali_radius: 11.5,
+            coh_radius: 11.5,
+            target_weight: 3.,
+            obstacle_weight: 2.,
+            drag: 0.,
+            cruise_speed: 0.,
+            perch_chance: 0.,
+            perch_time: 120.,
+            max_turn_rate: 0.,
+            verlet_skin: 0.,
+            boundary: Boundary::Wrap,
+        }
+    }
+
+    #[test]
+    fn no_max_speed_does_not_panic() {
+        let mut simulation = FlockingSystem::new(FlockingConfig {
+            max_speed: 0.,
+            ..config(0)
+        });
+        simulation.randomise();
+        simulation.update();
+        simulation.set_parameter(Parameter::MaxSpeed, 0.);
+        simulation.spawn(10, None);
+        simulation.update();
+        assert!(simulation.boids().len() >= 10);
+    }
+
+    #[test]
+    fn startles_in_an_empty_flock() {
+        let mut simulation = FlockingSystem::new(config(0));
+        simulation.set_startle(Some(Startle {
+            interval: 1.,
+            strength: 300.,
+            radius: 40.,
+            duration: 10,
+            from_boid: true,
+        }));
+        for _ in 0..10 {
+            simulation.update();
+        }
+    }
+
+    // Inside the world, on its corners and well outside it
+    fn query_points() -> Vec<Point2<f32>> {
+        let mut points = vec![
+            Point2::new(0., 0.),
+            Point2::new(200., 100.),
+            Point2::new(-50., -30.),
+            Point2::new(500., 40.),
+            Point2::new(100., 1e6),
+        ];
+        points.extend(
+            (0..50).map(|i| Point2::new((i * 37 % 200) as f32 + 0.5, (i * 13 % 100) as f32)),
+        );
+        points
+    }
+
+    fn flock(boid_count: u32) -> FlockingSystem {
+        let mut simulation = FlockingSystem::new(config(boid_count));
+        simulation.randomise();
+        for _ in 0..20 {
+            simulation.update();
+        }
+        simulation
+    }
+
+    fn ids<'a>(boids: impl IntoIterator<Item = &'a Boid>) -> Vec<u32> {
+        let mut ids: Vec<u32> = boids.into_iter().map(Boid::id).collect();
+        ids.sort_unstable();
+        ids
+    }
+
+    fn distance_2(boid: &Boid, point: Point2<f32>) -> Real {
+        (boid.position - point.cast()).magnitude2()
+    }
+
+    #[test]
+    fn nearest_boid_matches_brute_force() {
+        let simulation = flock(500);
+        for point in query_points() {
+            let nearest = simulation.nearest_boid(point).unwrap();
+            let closest = simulation
+                .boids()
+                .iter()
+                .map(|boid| distance_2(boid, point))
+                .fold(Real::INFINITY, Real::min);
+            assert_eq!(distance_2(nearest, point), closest, "{:?}", point);
+        }
+    }
+
+    #[test]
+    fn boids_within_matches_brute_force() {
+        let simulation = flock(500);
+        for point in query_points() {
+            for &radius in &[0., 5., 25., 1000.] {
+                let expected = simulation
+                    .boids()
+                    .iter()
+                    .filter(|boid| distance_2(boid, point) <= real(radius) * real(radius));
+                assert_eq!(
+                    ids(simulation.boids_within(point, radius)),
+                    ids(expected),
+                    "{:?} {}",
+                    point,
+                    radius
+                );
+            }
+        }
+        // A zero radius still finds boids sitting right on the point
+        for boid in &simulation.boids()[..10] {
+            let point = Point2::new(single(boid.position.x), single(boid.position.y));
+            let expected = simulation
+                .boids()
+                .iter()
+                .filter(|b| distance_2(b, point) == 0.);
+            let found = ids(simulation.boids_within(point, 0.));
+            #[cfg(not(feature = "f64"))]
+            assert!(found.contains(&boid.id()));
+            assert_eq!(found, ids(expected));
+        }
+    }
+
+    #[test]
+    fn boids_in_rect_matches_brute_force() {
+        let simulation = flock(500);
+        for point in query_points() {
+            for &(width, height) in &[(0., 0.), (10., 40.), (60., 5.), (1e4, 1e4)] {
+                let rect = (point.x, point.y, width, height);
+                let (min, max) = (
+                    point.cast::<Real>(),
+                    (point + Vector2::new(width, height)).cast::<Real>(),
+                );
+                let expected = simulation.boids().iter().filter(|boid| {
+                    let p = boid.position;
+                    p.x >= min.x && p.x <= max.x && p.y >= min.y && p.y <= max.y
+                });
+                assert_eq!(
+                    ids(simulation.boids_in_rect(rect)),
+                    ids(expected),
+                    "{:?}",
+                    rect
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn queries_on_an_empty_flock() {
+        let simulation = flock(0);
+        for point in query_points() {
+            assert!(simulation.nearest_boid(point).is_none());
+            assert!(simulation.boids_within(point, 50.).is_empty());
+            assert!(simulation
+                .boids_in_rect((point.x, point.y, 50., 50.))
+                .is_empty());
+        }
+    }
+
+    #[test]
+    fn neighbour_cap_applies_to_verlet_lists() {
+        let mut simulation = FlockingSystem::new(FlockingConfig {
+            verlet_skin: 50.,
+            ..config(500)
+        });
+        simulation.randomise();
+        simulation.update();
+        let most = |s: &FlockingSystem| s.verlet.as_ref().unwrap().counts.iter().cloned().max();
+        assert!(most(&simulation) > Some(3));
+        simulation.set_neighbour_cap(3);
+        simulation.update();
+        assert!(most(&simulation) <= Some(3));
+    }
+
+    #[test]
+    fn non_finite_boids_are_respawned() {
+        let mut simulation = FlockingSystem::new(config(50));
+        simulation.randomise();
+        simulation.set_parameter(Parameter::SepWeight, f32::NAN);
+        simulation.update();
+        for boid in simulation.boids() {
+            assert!(is_finite(boid.position.to_vec()) && is_finite(boid.velocity));
+        }
+    }
+}
@@ -0,0 +1,118 @@
+// Teaching mode, introducing the flocking rules one at a time so a class can watch flocking
+// emerge. Space steps through the stages, and each enabled rule's steering force is drawn for a
+// sample of boids.
+
+use crate::overlay::{Colour, Overlay};
+use crate::system::{FlockingSystem, Parameter};
+
+// Boids whose steering forces are drawn, spread evenly through the flock
+const DRAWN_BOIDS: usize = 300;
+// World units drawn per unit of force
+const FORCE_SCALE: f32 = 40.;
+
+const SEPARATION_COLOUR: Colour = [1.0, 0.35, 0.35];
+const ALIGNMENT_COLOUR: Colour = [0.35, 1.0, 0.35];
+const COHESION_COLOUR: Colour = [0.4, 0.6, 1.0];
+const CAPTION_COLOUR: Colour = [0.95, 0.95, 0.95];
+
+struct Stage {
+    caption: &'static str,
+    separation: bool,
+    alignment: bool,
+    cohesion: bool,
+}
+
+const STAGES: [Stage; 4] = [
+    Stage {
+        caption: "1/4 No rules: every boid flies in a straight line",
+        separation: false,
+        alignment: false,
+        cohesion: false,
+    },
+    Stage {
+        caption: "2/4 Separation (red): steer away from neighbours that are too close",
+        separation: true,
+        alignment: false,
+        cohesion: false,
+    },
+    Stage {
+        caption: "3/4 + Alignment (green): steer towards the average heading of neighbours",
+        separation: true,
+        alignment: true,
+        cohesion: false,
+    },
+    Stage {
+        caption: "4/4 + Cohesion (blue): steer towards the average position of neighbours",
+        separation: true,
+        alignment: true,
+        cohesion: true,
+    },
+];
+
+pub struct Teaching {
+    stage: usize,
+    // Configured weights of separation, alignment and cohesion, restored as rules are enabled
+    weights: [f32; 3],
+}
+
+impl Teaching {
+    pub fn new(simulation: &mut FlockingSystem) -> Teaching {
+        let teaching = Teaching {
+            stage: 0,
+            weights: [
+                simulation.parameter(Parameter::SepWeight),
+                simulation.parameter(Parameter::AliWeight),
+                simulation.parameter(Parameter::CohWeight),
+            ],
+        };
+        teaching.apply(simulation);
+        teaching
+    }
+
+    pub fn next_stage(&mut self, simulation: &mut FlockingSystem) {
+        self.stage = (self.stage + 1) % STAGES.len();
+        self.apply(simulation);
+    }
+
+    fn apply(&self, simulation: &mut FlockingSystem) {
+        let stage = &STAGES[self.stage];
+        println!("{}", stage.caption);
+        let rules = [
+            (Parameter::SepWeight, stage.separation),
+            (Parameter::AliWeight, stage.alignment),
+            (Parameter::CohWeight, stage.cohesion),
+        ];
+        for (&(parameter, enabled), &weight) in rules.iter().zip(self.weights.iter()) {
+            simulation.set_parameter(parameter, if enabled { weight } else { 0. });
+        }
+    }
+
+    pub fn draw(&self, simulation: &FlockingSystem, overlay: &mut Overlay) {
+        let stage = &STAGES[self.stage];
+        let boids = simulation.boids();
+        let stride = (boids.len() / DRAWN_BOIDS).max(1);
+        for index in (0..boids.len()).step_by(stride) {
+            let position = boids[index].position();
+            let steering = simulation.steering(index);
+            let forces = [
+                (stage.separation, steering.separation, SEPARATION_COLOUR),
+                (stage.alignment, steering.alignment, ALIGNMENT_COLOUR),
+                (stage.cohesion, steering.cohesion, COHESION_COLOUR),
+            ];
+            for &(enabled, force, colour) in &forces {
+                if enabled {
+                    overlay.line(position, force * FORCE_SCALE, colour);
+                }
+            }
+        }
+
+        let margin = overlay.line_height();
+        overlay.text(margin, margin, stage.caption, CAPTION_COLOUR);
+        overlay.text(
+            margin,
+            margin + overlay.line_height(),
+            "Space: next step",
+            CAPTION_COLOUR,
+        );
+    }
+}
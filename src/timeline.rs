@@ -0,0 +1,139 @@
+// Keyframed parameter animation.
+// Each parameter gets its own track of keyframes. Between two keyframes the parameter moves from
+// the first value to the second using the second keyframe's easing, and it is left alone before
+// the first keyframe and after the last, so it can still be changed by hand.
+
+use crate::boids::Keyframe;
+use crate::system::{FlockingSystem, Parameter};
+
+#[derive(Clone, Copy, Debug, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Easing {
+    Step,
+    Linear,
+    Smooth,
+}
+
+impl Easing {
+    pub fn apply(self, t: f32) -> f32 {
+        match self {
+            Easing::Step => 0.,
+            Easing::Linear => t,
+            Easing::Smooth => t * t * (3. - 2. * t),
+        }
+    }
+}
+
+struct Track {
+    parameter: Parameter,
+    keyframes: Vec<Keyframe>,
+}
+
+impl Track {
+    fn value_at(&self, tick: u64) -> Option<f32> {
+        let next = self.keyframes.iter().position(|k| k.tick >= tick)?;
+        let to = &self.keyframes[next];
+        if to.tick == tick {
+            return Some(to.value);
+        }
+        let from = &self.keyframes[next.checked_sub(1)?];
+        let t = (tick - from.tick) as f32 / (to.tick - from.tick) as f32;
+        Some(from.value + (to.value - from.value) * to.easing.apply(t))
+    }
+}
+
+pub struct Timeline {
+    tracks: Vec<Track>,
+}
+
+impl Timeline {
+    pub fn new(keyframes: &[Keyframe]) -> Timeline {
+        let mut tracks: Vec<Track> = vec![];
+        for keyframe in keyframes {
+            match tracks
+                .iter_mut()
+                .find(|t| t.parameter == keyframe.parameter)
+            {
+                Some(track) => track.keyframes.push(keyframe.clone()),
+                None => tracks.push(Track {
+                    parameter: keyframe.parameter,
+                    keyframes: vec![keyframe.clone()],
+                }),
+            }
+        }
+        for track in &mut tracks {
+            track.keyframes.sort_by_key(|k| k.tick);
+        }
+        Timeline { tracks }
+    }
+
+    pub fn apply(&self, simulation: &mut FlockingSystem) {
+        let tick = simulation.tick();
+        for track in &self.tracks {
+            if let Some(value) = track.value_at(tick) {
+                simulation.set_parameter(track.parameter, value);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Sep weight goes from 1 at tick 100 to 3 at tick 200, then 2 at tick 300
+    fn track(easing: Easing) -> Track {
+        let keyframe = |tick, value| Keyframe {
+            tick,
+            parameter: Parameter::SepWeight,
+            value,
+            easing,
+        };
+        Track {
+            parameter: Parameter::SepWeight,
+            keyframes: vec![keyframe(100, 1.), keyframe(200, 3.), keyframe(300, 2.)],
+        }
+    }
+
+    #[test]
+    fn before_the_first_keyframe() {
+        assert_eq!(track(Easing::Linear).value_at(0), None);
+        assert_eq!(track(Easing::Linear).value_at(99), None);
+    }
+
+    #[test]
+    fn on_keyframes() {
+        for &easing in &[Easing::Step, Easing::Linear, Easing::Smooth] {
+            let track = track(easing);
+            assert_eq!(track.value_at(100), Some(1.));
+            assert_eq!(track.value_at(200), Some(3.));
+            assert_eq!(track.value_at(300), Some(2.));
+        }
+    }
+
+    #[test]
+    fn between_keyframes() {
+        let step = track(Easing::Step);
+        assert_eq!(step.value_at(101), Some(1.));
+        assert_eq!(step.value_at(199), Some(1.));
+        assert_eq!(step.value_at(250), Some(3.));
+
+        let linear = track(Easing::Linear);
+        assert_eq!(linear.value_at(125), Some(1.5));
+        assert_eq!(linear.value_at(150), Some(2.));
+        assert_eq!(linear.value_at(250), Some(2.5));
+
+        let smooth = track(Easing::Smooth);
+        assert_eq!(smooth.value_at(150), Some(2.));
+        // Eases in, so lags behind linear early on and leads it late
+        assert!(smooth.value_at(125).unwrap() < 1.5);
+        assert!(smooth.value_at(175).unwrap() > 2.5);
+        assert_eq!(smooth.value_at(125), Some(1. + 2. * 0.15625));
+    }
+
+    #[test]
+    fn after_the_last_keyframe() {
+        assert_eq!(track(Easing::Linear).value_at(301), None);
+        assert_eq!(track(Easing::Linear).value_at(u64::MAX), None);
+    }
+}
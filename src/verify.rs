@@ -0,0 +1,101 @@
+// Headless verification of input recordings.
+// Re-simulates a recording from its seed and input, comparing the flock's checksum after every
+// tick with the one recorded, and reports the first tick they differ on. The config has to match
+// the one the recording was made with, as only the seed, world size and input are recorded.
+
+use crate::boids::{self, SimulationConfig, SimulatorError};
+use crate::demo::Demo;
+use crate::event::BoidControlEvent;
+use crate::formation;
+use crate::history::History;
+use crate::mask::Mask;
+use crate::replay::{self, Input, Player};
+use crate::system::FlockingSystem;
+use crate::teaching::Teaching;
+use crate::timeline::Timeline;
+
+pub fn run(path: &str, config: &SimulationConfig) -> Result<(), SimulatorError> {
+    let mut player = Player::open(path)?;
+    let checksums = player.checksums().to_vec();
+    if checksums.is_empty() {
+        return Err(replay::invalid("recording has no checksums to verify against").into());
+    }
+    let flock_conf = boids::build_flocking_config(config, player.world(), player.seed());
+    let mut simulation = FlockingSystem::new(flock_conf);
+    boids::init_flock(&mut simulation, config)?;
+    if let Some(ref shape) = config.formation {
+        formation::load(shape, &mut simulation)?;
+    }
+    let mut teaching = if config.teaching {
+        Some(Teaching::new(&mut simulation))
+    } else {
+        None
+    };
+    if let Some(ref path) = config.obstacles {
+        let mask = Mask::load(path)?;
+        simulation.set_obstacles(mask.width, mask.height, mask.filled);
+    }
+    let timeline = Timeline::new(&config.timeline);
+    let mut history = boids::rewind_seconds(config, Some(&player)).map(History::new);
+    let mut demo = config
+        .demo
+        .map(|(interval, transition)| Demo::new(interval, transition));
+
+    println!("Verifying {} ticks of {}", checksums.len(), path);
+    let mut expected = checksums.iter().peekable();
+    let mut verified = 0;
+    // Pausing only holds back ticks, which the checksums already account for
+    let mut paused = false;
+    loop {
+        // Input is applied after the tick it was recorded on, as it was when recording
+        player.replay(simulation.tick(), |input| match input {
+            Input::Command(command) => boids::apply_command(&mut simulation, &mut paused, command),
+            Input::Event(BoidControlEvent::Pause) => {
+                if let Some(ref mut t) = teaching {
+                    t.next_stage(&mut simulation);
+                }
+            }
+            Input::Event(BoidControlEvent::StepBack) => {
+                if let Some(ref mut h) = history {
+                    h.back(&mut simulation);
+                }
+            }
+            Input::Event(BoidControlEvent::StepForward) => {
+                if let Some(ref mut h) = history {
+                    h.forward(&mut simulation);
+                }
+            }
+            Input::Event(event) => boids::handle_event(&mut simulation, event),
+        });
+        if expected.peek().is_none() {
+            break;
+        }
+        timeline.apply(&mut simulation);
+        if let Some(ref mut d) = demo {
+            d.poll(&mut simulation);
+        }
+        simulation.update();
+        if let Some(ref mut h) = history {
+            h.poll(&simulation);
+        }
+        let tick = simulation.tick();
+        // Ticks before the simulation's first, e.g. when starting from a saved state, are skipped
+        while expected.next_if(|&&(t, _)| t < tick).is_some() {}
+        if let Some(&(_, checksum)) = expected.next_if(|&&(t, _)| t == tick) {
+            let actual = simulation.checksum();
+            if actual != checksum {
+                println!(
+                    "Checksum {:016x} differs from the recorded {:016x}",
+                    actual, checksum
+                );
+                return Err(SimulatorError::Diverged(tick));
+            }
+            verified += 1;
+        }
+    }
+    println!(
+        "Replay matches the recording, {} checksums verified",
+        verified
+    );
+    Ok(())
+}
@@ -0,0 +1,60 @@
+// Video export by piping raw frames into an ffmpeg child process.
+// Every rendered frame becomes exactly one video frame at a fixed frame rate, so captures play
+// back smoothly even if the simulation couldn't render in real time while recording.
+
+use std::{
+    io::{self, prelude::*},
+    process::{Child, ChildStdin, Command, Stdio},
+};
+
+use crate::glx;
+
+pub struct VideoRecorder {
+    ffmpeg: Child,
+    stdin: ChildStdin,
+    width: u32,
+    height: u32,
+}
+
+impl VideoRecorder {
+    pub fn start(path: &str, width: u32, height: u32, fps: u32) -> io::Result<VideoRecorder> {
+        let mut ffmpeg = Command::new("ffmpeg")
+            .args(["-loglevel", "error", "-y"])
+            .args(["-f", "rawvideo", "-pix_fmt", "rgba"])
+            .args(["-s", &format!("{}x{}", width, height)])
+            .args(["-framerate", &fps.to_string()])
+            .args(["-i", "-"])
+            // GL rows start at the bottom, and most encoders need even dimensions
+            .args(["-vf", "vflip,pad=ceil(iw/2)*2:ceil(ih/2)*2"])
+            .args(["-pix_fmt", "yuv420p", path])
+            .stdin(Stdio::piped())
+            .spawn()
+            .map_err(|err| io::Error::new(err.kind(), format!("could not run ffmpeg: {}", err)))?;
+        let stdin = ffmpeg.stdin.take().expect("ffmpeg stdin is piped");
+        Ok(VideoRecorder {
+            ffmpeg,
+            stdin,
+            width,
+            height,
+        })
+    }
+
+    // Call after rendering and before swapping buffers
+    pub fn capture(&mut self) -> io::Result<()> {
+        let pixels = glx::read_pixels(self.width, self.height);
+        self.stdin.write_all(&pixels)
+    }
+
+    pub fn finish(self) -> io::Result<()> {
+        let VideoRecorder {
+            mut ffmpeg, stdin, ..
+        } = self;
+        drop(stdin);
+        let status = ffmpeg.wait()?;
+        if status.success() {
+            Ok(())
+        } else {
+            Err(io::Error::other(format!("ffmpeg exited with {}", status)))
+        }
+    }
+}